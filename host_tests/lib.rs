@@ -0,0 +1,50 @@
+// The kernel modules that are pure arithmetic, built for the host. Module
+// paths match the kernel crate so `crate::addr` etc. resolve the same way.
+// Their `#[test_case]`s run through `test_runner` below, property tests
+// live in tests/.
+
+#![cfg_attr(not(test), no_std)]
+// only part of the kernel is here, some items are used by the rest of it
+#![allow(dead_code)]
+// the kernel documents unsafe fns with `// unsafe:` comments, which clippy
+// doesn't read as a `# Safety` section
+#![allow(clippy::missing_safety_doc)]
+#![feature(step_trait)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::test_runner)]
+
+#[path = "../src/addr.rs"]
+pub mod addr;
+
+#[path = "../src/structures"]
+pub mod structures {
+    pub mod page;
+    pub mod page_table;
+    pub mod phys_frame;
+}
+
+
+#[cfg(test)]
+pub trait Testable {
+    fn run(&self);
+}
+
+#[cfg(test)]
+impl<T> Testable for T
+where
+    T: Fn()
+{
+    fn run(&self) {
+        print!("{}...\t", core::any::type_name::<T>());
+        self();
+        println!("[ok]");
+    }
+}
+
+#[cfg(test)]
+pub fn test_runner(tests: &[&dyn Testable]) {
+    println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+}
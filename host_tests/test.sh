@@ -0,0 +1,8 @@
+#!/bin/sh
+# Runs the host tests. Cargo picks up .cargo/config.toml from the current
+# directory upwards, and the kernel's one (custom target, build-std) can't be
+# overridden from a nested config, so run cargo from outside the tree.
+set -e
+manifest="$(cd "$(dirname "$0")" && pwd)/Cargo.toml"
+cd /
+exec cargo +nightly test --manifest-path "$manifest" "$@"
@@ -0,0 +1,108 @@
+// Property tests for the address and paging types.
+
+use proptest::prelude::*;
+
+use rustyos_host_tests::{
+    addr::{align_down, align_up, PhyAddr, VirtualAddr},
+    structures::{
+        page::{Page, Size2Mib, Size4Kib},
+        page_table::{PageTableEntry, PageTableFlags},
+        phys_frame::PhysFrame,
+    },
+};
+
+fn canonical() -> impl Strategy<Value = u64> {
+    any::<u64>().prop_map(|addr| VirtualAddr::new_truncate(addr).as_u64())
+}
+
+fn alignment() -> impl Strategy<Value = u64> {
+    (0u32..32).prop_map(|shift| 1u64 << shift)
+}
+
+proptest! {
+    #[test]
+    fn new_truncate_sign_extends_bit_47(addr in any::<u64>()) {
+        let truncated = VirtualAddr::new_truncate(addr).as_u64();
+        prop_assert_eq!(truncated & 0xffff_ffff_ffff, addr & 0xffff_ffff_ffff);
+        let upper = if addr & (1 << 47) != 0 { 0xffff } else { 0 };
+        prop_assert_eq!(truncated >> 48, upper);
+        prop_assert!(VirtualAddr::try_new(truncated).is_ok());
+        prop_assert_eq!(VirtualAddr::try_new(addr).is_ok(), truncated == addr);
+    }
+
+    #[test]
+    fn alignment_rounds_to_the_nearest_multiple(addr in 0u64..u64::MAX / 2, align in alignment()) {
+        let down = align_down(addr, align);
+        let up = align_up(addr, align);
+        prop_assert_eq!(down % align, 0);
+        prop_assert_eq!(up % align, 0);
+        prop_assert!(down <= addr && addr - down < align);
+        prop_assert!(up >= addr && up - addr < align);
+        prop_assert_eq!(down == addr, up == addr);
+    }
+
+    #[test]
+    fn indices_rebuild_the_address(addr in canonical()) {
+        let virt = VirtualAddr::new(addr);
+        let rebuilt = (u64::from(virt.p4_index()) << 39)
+            | (u64::from(virt.p3_index()) << 30)
+            | (u64::from(virt.p2_index()) << 21)
+            | (u64::from(virt.p1_index()) << 12)
+            | u64::from(virt.page_offset());
+        prop_assert_eq!(VirtualAddr::new_truncate(rebuilt), virt);
+    }
+
+    #[test]
+    fn page_contains_its_addresses(addr in canonical()) {
+        let virt = VirtualAddr::new(addr);
+        let page = Page::<Size4Kib>::containing_address(virt);
+        prop_assert!(page.start_address() <= virt);
+        prop_assert!(virt - page.start_address() < 4096);
+        prop_assert_eq!(page.p1_index(), virt.p1_index());
+
+        let huge = Page::<Size2Mib>::containing_address(virt);
+        prop_assert!(huge.start_address() <= page.start_address());
+        prop_assert_eq!(huge.p2_index(), virt.p2_index());
+    }
+
+    #[test]
+    fn range_length_matches_iteration(start in 0u64..0x7fff_ffff, len in 0u64..64) {
+        let first = Page::<Size4Kib>::containing_address(VirtualAddr::new(start << 12));
+        let range = Page::range(first, first + len);
+        prop_assert_eq!(range.len(), len);
+        prop_assert_eq!(range.size(), len * 4096);
+        prop_assert_eq!(range.count() as u64, len);
+
+        let inclusive = Page::range_inclusive(first, first + len);
+        prop_assert_eq!(inclusive.len(), len + 1);
+        prop_assert_eq!(inclusive.count() as u64, len + 1);
+    }
+
+    #[test]
+    fn range_steps_over_the_hole(before in 1u64..16, after in 0u64..16) {
+        let first = Page::<Size4Kib>::containing_address(VirtualAddr::new(0x0000_8000_0000_0000 - before * 4096));
+        let last = Page::<Size4Kib>::containing_address(VirtualAddr::new(0xffff_8000_0000_0000 + after * 4096));
+        let range = Page::range_inclusive(first, last);
+        prop_assert_eq!(range.len(), before + after + 1);
+        prop_assert_eq!(range.count() as u64, before + after + 1);
+    }
+
+    #[test]
+    fn phys_addr_keeps_52_bits(addr in any::<u64>()) {
+        let truncated = PhyAddr::new_truncate(addr);
+        prop_assert_eq!(truncated.as_u64(), addr & ((1 << 52) - 1));
+        prop_assert_eq!(PhyAddr::try_new(addr).is_ok(), addr < 1 << 52);
+    }
+
+    #[test]
+    fn entry_keeps_frame_and_flags(frame in 0u64..1 << 40, bits in any::<u64>()) {
+        let frame = PhysFrame::<Size4Kib>::frame_containing_addr(PhyAddr::new(frame << 12));
+        let flags = PageTableFlags::from_bits_truncate(bits) | PageTableFlags::PRESENT;
+        let flags = flags - PageTableFlags::HUGE_PAGE;
+
+        let mut entry = PageTableEntry::new();
+        entry.set_frame(frame, flags);
+        prop_assert_eq!(entry.frame(), Ok(frame));
+        prop_assert_eq!(entry.flags(), flags);
+    }
+}
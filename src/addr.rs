@@ -1,13 +1,20 @@
-use core::{fmt::{self, Debug}, iter::Enumerate, ops::{Add, AddAssign, Sub, SubAssign}};
+use core::{fmt::{self, Debug}, iter::Step, ops::{Add, AddAssign, Sub, SubAssign}};
 
+#[cfg(feature = "memory_encryption")]
 use crate::meme_encrypt::ENC_BIT_MASK;
 
 const ADDRESS_SPACE_SIZE: u64 = 0x1_0000_0000_0000;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+// Highest address of the lower half / lowest of the upper half, everything
+// in between isn't canonical.
+const LOWER_HALF_END: u64 = 0x0000_7fff_ffff_ffff;
+const UPPER_HALF_START: u64 = 0xffff_8000_0000_0000;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct VirtualAddr(u64);
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct PhyAddr(u64);
 
@@ -27,16 +34,18 @@ impl Debug for VirtAddrNotValid {
 
 impl VirtualAddr {
 
+    // Panics if bits 48..64 aren't a sign extension of bit 47.
+    #[inline]
     pub const fn new(addr: u64) -> VirtualAddr {
-        match Self::try_from(addr) {
-            Ok( virtaddr ) => v,
+        match Self::try_new(addr) {
+            Ok( virtaddr ) => virtaddr,
             Err( _ ) => panic!("virtual addrees must be sign extneded in bits 48 to 64")
         }
     }
 
 
     #[inline]
-    pub fn try_new( addr : u64) -> Result<VirtualAddr, VirtAddrNotValid> {
+    pub const fn try_new( addr : u64) -> Result<VirtualAddr, VirtAddrNotValid> {
         let v = Self::new_truncate( addr );
         if v.0 == addr {
             Ok(v)
@@ -45,18 +54,20 @@ impl VirtualAddr {
         }
     }
 
+    // Overwrites bits 48..64 with the sign extension of bit 47.
     #[inline]
-    pub fn new_truncate(addr: u64) -> VirtualAddr {
-        VirtualAddr(((addr << 16) as i64 >> 10) as u64 )
+    pub const fn new_truncate(addr: u64) -> VirtualAddr {
+        VirtualAddr(((addr << 16) as i64 >> 16) as u64 )
     }
 
+    // no checks at all, the address has to be canonical.
     #[inline]
     pub const unsafe fn new_safe(addr : u64) -> VirtualAddr {
         VirtualAddr(addr)
     }
 
     #[inline]
-    pub fn zero() -> VirtualAddr {
+    pub const fn zero() -> VirtualAddr {
         VirtualAddr(0)
     }
 
@@ -68,7 +79,7 @@ impl VirtualAddr {
 
     #[cfg(target_pointer_width =  "64")]
     #[inline]
-    pub fn from_ptr<T: ?Sized>( prt: *const T ) -> Self {
+    pub fn from_ptr<T: ?Sized>( ptr: *const T ) -> Self {
         Self::new(ptr as *const () as u64)
     }
 
@@ -77,10 +88,10 @@ impl VirtualAddr {
         self.as_u64() as *const T
     }
 
-    // #[inline]
-    // pub const fn as_mut_ptr<T>(self) -> bool {
-    //     self.as_ptr::<T>() as *mut T
-    // }
+    #[inline]
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.as_ptr::<T>() as *mut T
+    }
 
     #[inline]
     pub const fn is_null(&self) -> bool {
@@ -88,19 +99,20 @@ impl VirtualAddr {
     }
 
     #[inline]
-    pub fn align_up(&self, align: U) -> Self
+    pub fn align_up<U>(self, align: U) -> Self
     where
-        u: Into<u64>
+        U: Into<u64>
     {
         Self::new_truncate(align_up(self.0, align.into()))
     }
 
 
-    pub fn align_down<T>( self, align: U ) -> Self
+    #[inline]
+    pub fn align_down<U>( self, align: U ) -> Self
     where
         U: Into<u64>
     {
-        self.align_down(align.into())
+        self.align_down_as_u64(align.into())
     }
 
     #[inline]
@@ -116,15 +128,221 @@ impl VirtualAddr {
         self.is_aligned_u64(align.into())
     }
 
-    pub(crate) fn is_aligned_u64(self, align: u64) -> bool {
+    pub(crate) const fn is_aligned_u64(self, align: u64) -> bool {
         self.align_down_as_u64(align).as_u64() == self.as_u64()
     }
 
-    // pub const fn page_offset(self) -> Page
+    // offset inside the 4KiB page.
+    #[inline]
+    pub const fn page_offset(self) -> PageOffset {
+        PageOffset::new_truncate(self.0 as u16)
+    }
+
+    // Index into the page table of the given level (1..=4).
+    #[inline]
+    pub const fn page_table_index(self, level: u8) -> PageTableIndex {
+        assert!(level >= 1 && level <= 4, "page table levels go from 1 to 4");
+        PageTableIndex::new_truncate((self.0 >> (12 + 9 * (level as u64 - 1))) as u16)
+    }
+
+    #[inline]
+    pub const fn p1_index(self) -> PageTableIndex {
+        self.page_table_index(1)
+    }
+
+    #[inline]
+    pub const fn p2_index(self) -> PageTableIndex {
+        self.page_table_index(2)
+    }
+
+    #[inline]
+    pub const fn p3_index(self) -> PageTableIndex {
+        self.page_table_index(3)
+    }
+
+    #[inline]
+    pub const fn p4_index(self) -> PageTableIndex {
+        self.page_table_index(4)
+    }
 
+    // None if the result isn't a valid address. Jumping over the
+    // non-canonical hole doesn't count as valid.
+    #[inline]
+    pub const fn checked_add(self, rhs: u64) -> Option<Self> {
+        match self.0.checked_add(rhs) {
+            Some(addr) => match Self::try_new(addr) {
+                Ok(addr) => Some(addr),
+                Err(_) => None,
+            },
+            None => None,
+        }
+    }
+
+    #[inline]
+    pub const fn checked_sub(self, rhs: u64) -> Option<Self> {
+        match self.0.checked_sub(rhs) {
+            Some(addr) => match Self::try_new(addr) {
+                Ok(addr) => Some(addr),
+                Err(_) => None,
+            },
+            None => None,
+        }
+    }
+
+    // Stops at the end of the half the address is in.
+    #[inline]
+    pub const fn saturating_add(self, rhs: u64) -> Self {
+        match self.checked_add(rhs) {
+            Some(addr) => addr,
+            None if self.0 <= LOWER_HALF_END => VirtualAddr(LOWER_HALF_END),
+            None => VirtualAddr(u64::MAX),
+        }
+    }
+
+    // Stops at the start of the half the address is in.
+    #[inline]
+    pub const fn saturating_sub(self, rhs: u64) -> Self {
+        match self.checked_sub(rhs) {
+            Some(addr) => addr,
+            None if self.0 <= LOWER_HALF_END => VirtualAddr(0),
+            None => VirtualAddr(UPPER_HALF_START),
+        }
+    }
+
+    // Like `checked_add`, but skips the non-canonical hole: one step past
+    // the end of the lower half is the start of the upper half.
+    pub const fn forward_checked(self, count: u64) -> Option<Self> {
+        let mut addr = match self.0.checked_add(count) {
+            Some(addr) => addr,
+            None => return None,
+        };
+        if self.0 <= LOWER_HALF_END && addr > LOWER_HALF_END {
+            addr = match addr.checked_add(UPPER_HALF_START - LOWER_HALF_END - 1) {
+                Some(addr) => addr,
+                None => return None,
+            };
+        }
+        match Self::try_new(addr) {
+            Ok(addr) => Some(addr),
+            Err(_) => None,
+        }
+    }
+
+    pub const fn backward_checked(self, count: u64) -> Option<Self> {
+        let mut addr = match self.0.checked_sub(count) {
+            Some(addr) => addr,
+            None => return None,
+        };
+        if self.0 >= UPPER_HALF_START && addr < UPPER_HALF_START {
+            addr = match addr.checked_sub(UPPER_HALF_START - LOWER_HALF_END - 1) {
+                Some(addr) => addr,
+                None => return None,
+            };
+        }
+        match Self::try_new(addr) {
+            Ok(addr) => Some(addr),
+            Err(_) => None,
+        }
+    }
+
+    // Number of steps from `start` to `end`, without the hole. None if
+    // `end` is below `start`.
+    pub const fn steps_between(start: Self, end: Self) -> Option<u64> {
+        if start.0 > end.0 {
+            return None;
+        }
+        let mut steps = end.0 - start.0;
+        if start.0 <= LOWER_HALF_END && end.0 >= UPPER_HALF_START {
+            steps -= UPPER_HALF_START - LOWER_HALF_END - 1;
+        }
+        Some(steps)
+    }
 
 }
 
+impl Debug for VirtualAddr {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("VirtAddr")
+            .field(&format_args!("{:#x}", self.0))
+            .finish()
+    }
+}
+
+impl fmt::LowerHex for VirtualAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::UpperHex for VirtualAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.0, f)
+    }
+}
+
+// Add, panics if the result isn't canonical
+impl Add<u64> for VirtualAddr {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: u64) -> Self::Output {
+        VirtualAddr::new(self.0.checked_add(rhs).expect("virtual address overflow"))
+    }
+}
+
+impl AddAssign<u64> for VirtualAddr {
+    #[inline]
+    fn add_assign(&mut self, rhs: u64) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<u64> for VirtualAddr {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: u64) -> Self::Output {
+        VirtualAddr::new(self.0.checked_sub(rhs).expect("virtual address underflow"))
+    }
+}
+
+impl SubAssign<u64> for VirtualAddr {
+    #[inline]
+    fn sub_assign(&mut self, rhs: u64) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<VirtualAddr> for VirtualAddr {
+    type Output = u64;
+
+    #[inline]
+    fn sub(self, rhs: VirtualAddr) -> Self::Output {
+        self.as_u64().checked_sub(rhs.as_u64()).unwrap()
+    }
+}
+
+impl Step for VirtualAddr {
+    #[inline]
+    fn steps_between(start: &Self, end: &Self) -> (usize, Option<usize>) {
+        match VirtualAddr::steps_between(*start, *end) {
+            Some(steps) => (steps as usize, Some(steps as usize)),
+            None => (0, None),
+        }
+    }
+
+    #[inline]
+    fn forward_checked(start: Self, count: usize) -> Option<Self> {
+        start.forward_checked(count as u64)
+    }
+
+    #[inline]
+    fn backward_checked(start: Self, count: usize) -> Option<Self> {
+        start.backward_checked(count as u64)
+    }
+}
+
+
 pub struct PhyAddrNotValid(pub u64);
 
 impl Debug for PhyAddrNotValid {
@@ -156,31 +374,30 @@ impl PhyAddr {
     }
 
     // throws a bits 52..64 + Encrytion Bit away.
-    #[cfg(not(feature = "memory_encryption"))]
+    #[cfg(feature = "memory_encryption")]
     #[inline]
-    pub const fn new_truncate(addr: u64) -> PhyAddr {
-        PhyAddr( addr % (1 << 52) & !ENC_BIT_MASK.load(core::sync::atomic::Ordering::Release))
+    pub fn new_truncate(addr: u64) -> PhyAddr {
+        PhyAddr( addr % (1 << 52) & !ENC_BIT_MASK.load(core::sync::atomic::Ordering::Relaxed))
     }
 
     // not a checked bits, need to check 52..64 bits.
     #[inline]
-    pub unsafe fn new_unsafe(addr: u64) -> PhyAddr {
+    pub const unsafe fn new_unsafe(addr: u64) -> PhyAddr {
         PhyAddr(addr)
     }
 
-    #[cfg(not(feature = "memory_encryption"))]
     #[inline]
-    pub const fn try_new(addr: u64) -> Result<Self, ()> {
-        let pa = Self::new_truncate(addr);
+    pub const fn try_new(addr: u64) -> Result<Self, PhyAddrNotValid> {
+        let pa = PhyAddr( addr % (1 << 52));
         if pa.0 == addr {
             Ok(pa)
         } else {
-            Err(())
+            Err(PhyAddrNotValid(addr))
         }
     }
 
     #[inline]
-    pub const fn zero(&self) -> PhyAddr {
+    pub const fn zero() -> PhyAddr {
         PhyAddr(0)
     }
 
@@ -191,7 +408,7 @@ impl PhyAddr {
 
     #[inline]
     pub const fn is_null(&self) -> bool {
-        return self.0 == 0;
+        self.0 == 0
     }
 
     #[inline]
@@ -211,6 +428,14 @@ impl PhyAddr {
         PhyAddr::new(align_down(self.0, align.into()))
     }
 
+    #[inline]
+    pub fn align_down<U>(&self, align: U) -> Self
+    where
+    U: Into<u64>,
+    {
+        self.align_down_u64(align)
+    }
+
     #[inline]
     pub fn is_aligned<U>(self, align: U) -> bool
     where
@@ -224,6 +449,39 @@ impl PhyAddr {
         self.align_down_u64(align).as_u64() == self.as_u64()
     }
 
+    #[inline]
+    pub const fn checked_add(self, rhs: u64) -> Option<Self> {
+        match self.0.checked_add(rhs) {
+            Some(addr) => match Self::try_new(addr) {
+                Ok(addr) => Some(addr),
+                Err(_) => None,
+            },
+            None => None,
+        }
+    }
+
+    #[inline]
+    pub const fn checked_sub(self, rhs: u64) -> Option<Self> {
+        match self.0.checked_sub(rhs) {
+            Some(addr) => Some(PhyAddr(addr)),
+            None => None,
+        }
+    }
+
+    // stops at the highest physical address (2^52 - 1).
+    #[inline]
+    pub const fn saturating_add(self, rhs: u64) -> Self {
+        match self.checked_add(rhs) {
+            Some(addr) => addr,
+            None => PhyAddr((1 << 52) - 1),
+        }
+    }
+
+    #[inline]
+    pub const fn saturating_sub(self, rhs: u64) -> Self {
+        PhyAddr(self.0.saturating_sub(rhs))
+    }
+
 }
 
 impl Debug for PhyAddr {
@@ -261,23 +519,21 @@ impl fmt::Octal for PhyAddr {
     }
 }
 
-// Add
+// Add, panics if the result is past the physical address space
 impl Add<u64> for PhyAddr {
     type Output = Self;
 
-    fn add(self, rhs: u64) -> Option<Self::Output> {
-        if let Some( addr ) = self.0.checked_add(rhs) {
-            return Some(PhyAddr::new(addr));
-        } else {
-            return None;
-        }
+    #[inline]
+    fn add(self, rhs: u64) -> Self::Output {
+        self.checked_add(rhs).expect("physical address overflow")
     }
 }
 
 // AddAssign
 impl AddAssign<u64> for PhyAddr {
+    #[inline]
     fn add_assign(&mut self, rhs: u64) {
-        *self = *self.0 + rhs
+        *self = *self + rhs;
     }
 }
 
@@ -286,19 +542,17 @@ impl AddAssign<u64> for PhyAddr {
 impl Sub<u64> for PhyAddr {
     type Output = Self;
 
-    fn sub(self, rhs: u64) -> Option<Self::Output> {
-        if let Some( addr ) = self.0.checked_sub(rhs) {
-            return Some(PhyAddr::new(addr));
-        } else {
-            return None;
-        }
+    #[inline]
+    fn sub(self, rhs: u64) -> Self::Output {
+        self.checked_sub(rhs).expect("physical address underflow")
     }
 }
 
 // SubAssign
 impl SubAssign<u64> for PhyAddr {
+    #[inline]
     fn sub_assign(&mut self, rhs: u64) {
-        *self = *self.0 - rhs;
+        *self = *self - rhs;
     }
 }
 
@@ -306,9 +560,118 @@ impl SubAssign<u64> for PhyAddr {
 impl Sub<PhyAddr> for PhyAddr {
     type Output = u64;
 
-    fn sub(self, rhs: u64) -> Self::Output {
-        type Output = u64;
-        return self.as_u64().checked_sub(rhs.as_u64()).unwrap();
+    #[inline]
+    fn sub(self, rhs: PhyAddr) -> Self::Output {
+        self.as_u64().checked_sub(rhs.as_u64()).unwrap()
+    }
+}
+
+impl Step for PhyAddr {
+    #[inline]
+    fn steps_between(start: &Self, end: &Self) -> (usize, Option<usize>) {
+        match end.0.checked_sub(start.0) {
+            Some(steps) => (steps as usize, Some(steps as usize)),
+            None => (0, None),
+        }
+    }
+
+    #[inline]
+    fn forward_checked(start: Self, count: usize) -> Option<Self> {
+        start.checked_add(count as u64)
+    }
+
+    #[inline]
+    fn backward_checked(start: Self, count: usize) -> Option<Self> {
+        start.checked_sub(count as u64)
+    }
+}
+
+
+// 9 bit index into one page table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageTableIndex(u16);
+
+impl PageTableIndex {
+
+    // Panics if the index is 512 or bigger.
+    #[inline]
+    pub const fn new(index: u16) -> Self {
+        assert!(index < 512, "page table index out of range");
+        PageTableIndex(index)
+    }
+
+    // keeps the low 9 bits.
+    #[inline]
+    pub const fn new_truncate(index: u16) -> Self {
+        PageTableIndex(index % 512)
+    }
+
+    #[inline]
+    pub const fn as_usize(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl From<PageTableIndex> for u16 {
+    #[inline]
+    fn from(index: PageTableIndex) -> Self {
+        index.0
+    }
+}
+
+impl From<PageTableIndex> for u64 {
+    #[inline]
+    fn from(index: PageTableIndex) -> Self {
+        index.0 as u64
+    }
+}
+
+impl From<PageTableIndex> for usize {
+    #[inline]
+    fn from(index: PageTableIndex) -> Self {
+        index.0 as usize
+    }
+}
+
+
+// 12 bit offset into a 4KiB page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PageOffset(u16);
+
+impl PageOffset {
+
+    // Panics if the offset is 4096 or bigger.
+    #[inline]
+    pub const fn new(offset: u16) -> Self {
+        assert!(offset < 4096, "page offset out of range");
+        PageOffset(offset)
+    }
+
+    // keeps the low 12 bits.
+    #[inline]
+    pub const fn new_truncate(offset: u16) -> Self {
+        PageOffset(offset % 4096)
+    }
+}
+
+impl From<PageOffset> for u16 {
+    #[inline]
+    fn from(offset: PageOffset) -> Self {
+        offset.0
+    }
+}
+
+impl From<PageOffset> for u64 {
+    #[inline]
+    fn from(offset: PageOffset) -> Self {
+        offset.0 as u64
+    }
+}
+
+impl From<PageOffset> for usize {
+    #[inline]
+    fn from(offset: PageOffset) -> Self {
+        offset.0 as usize
     }
 }
 
@@ -316,15 +679,85 @@ impl Sub<PhyAddr> for PhyAddr {
 // Downwards address alignment.
 // aligned = address & ~(alignment - 1)
 pub const fn align_down( addr: u64, align: u64 ) -> u64 {
-    return addr & !(align - 1);
+    assert!(align.is_power_of_two(), "`align` must be a power of two");
+    addr & !(align - 1)
 }
 
 
 // upward address alignment
 // aligned = (address + alignment - 1) & ~(alignment - 1)
 pub const fn align_up( addr: u64, align: u64 ) -> u64 {
-    (addr + align - 1) & !(align - 1)
+    assert!(align.is_power_of_two(), "`align` must be a power of two");
+    let mask = align - 1;
+    if addr & mask == 0 {
+        addr
+    } else {
+        (addr | mask).checked_add(1).expect("attempt to add with overflow")
+    }
 }
 
 
-// tests
\ No newline at end of file
+// tests
+
+#[test_case]
+fn test_new_truncate_sign_extends() {
+    assert_eq!(VirtualAddr::new_truncate(0).as_u64(), 0);
+    assert_eq!(VirtualAddr::new_truncate(1 << 47).as_u64(), 0xffff_8000_0000_0000);
+    assert_eq!(VirtualAddr::new_truncate(0x1234_5678_9abc).as_u64(), 0x1234_5678_9abc);
+    assert_eq!(VirtualAddr::new_truncate(0x1_0000_0000_0000).as_u64(), 0);
+    assert!(VirtualAddr::try_new(0x0000_8000_0000_0000).is_err());
+    assert!(VirtualAddr::try_new(0xffff_8000_0000_0000).is_ok());
+}
+
+// the octal digits are grouped by table index on purpose
+#[allow(clippy::unusual_byte_groupings)]
+#[test_case]
+fn test_page_indices() {
+    // 0o177777_777_776_775_774_1234: p4 777, p3 776, p2 775, p1 774
+    let addr = VirtualAddr::new(0o177777_777_776_775_774_1234);
+    assert_eq!(addr.p4_index(), PageTableIndex::new(0o777));
+    assert_eq!(addr.p3_index(), PageTableIndex::new(0o776));
+    assert_eq!(addr.p2_index(), PageTableIndex::new(0o775));
+    assert_eq!(addr.p1_index(), PageTableIndex::new(0o774));
+    assert_eq!(addr.page_offset(), PageOffset::new(0o1234));
+}
+
+#[test_case]
+fn test_alignment() {
+    assert_eq!(align_up(0, 4096), 0);
+    assert_eq!(align_up(1, 4096), 4096);
+    assert_eq!(align_up(4096, 4096), 4096);
+    assert_eq!(align_down(4097, 4096), 4096);
+
+    let addr = VirtualAddr::new(0x1234);
+    assert_eq!(addr.align_down(0x1000u64).as_u64(), 0x1000);
+    assert_eq!(addr.align_up(0x1000u64).as_u64(), 0x2000);
+    assert!(!addr.is_aligned(0x1000u64));
+    assert!(PhyAddr::new(0x2000).is_aligned(0x1000u64));
+}
+
+#[test_case]
+fn test_checked_and_saturating_arithmetic() {
+    let end_of_lower = VirtualAddr::new(LOWER_HALF_END);
+    assert_eq!(end_of_lower.checked_add(1), None);
+    assert_eq!(end_of_lower.saturating_add(10), end_of_lower);
+    assert_eq!(VirtualAddr::zero().checked_sub(1), None);
+    assert_eq!(VirtualAddr::new(UPPER_HALF_START).saturating_sub(1).as_u64(), UPPER_HALF_START);
+    assert_eq!(VirtualAddr::new(0x2000) - VirtualAddr::new(0x1000), 0x1000);
+
+    assert_eq!(PhyAddr::new((1 << 52) - 1).checked_add(1), None);
+    assert_eq!(PhyAddr::zero().saturating_sub(1), PhyAddr::zero());
+    assert_eq!(PhyAddr::new(0x1000) + 0x10, PhyAddr::new(0x1010));
+    assert_eq!(PhyAddr::new(0x2000) - PhyAddr::new(0x1000), 0x1000);
+}
+
+#[test_case]
+fn test_steps_skip_the_hole() {
+    let end_of_lower = VirtualAddr::new(LOWER_HALF_END);
+    let start_of_upper = VirtualAddr::new(UPPER_HALF_START);
+    assert_eq!(end_of_lower.forward_checked(1), Some(start_of_upper));
+    assert_eq!(start_of_upper.backward_checked(1), Some(end_of_lower));
+    assert_eq!(VirtualAddr::steps_between(end_of_lower, start_of_upper), Some(1));
+    assert_eq!(VirtualAddr::steps_between(start_of_upper, end_of_lower), None);
+    assert_eq!(VirtualAddr::new(u64::MAX).forward_checked(1), None);
+}
\ No newline at end of file
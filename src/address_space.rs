@@ -0,0 +1,400 @@
+// Address spaces.
+// Every address space has its own P4 table. The user half, P4 entries
+// USER_P4_START..USER_P4_END, belongs to the address space alone. Every
+// other P4 entry is the kernel's and points at the same P3 table as in the
+// boot page table, so whatever the kernel maps below those shows up in
+// every address space. New kernel P4 entries (a VMA or `cow::fork_range`
+// in a fresh 512GiB slot) only go into the boot P4. An address space made
+// before that gets the entry the first time it faults on it, see
+// `sync_kernel_entry`. That doesn't work for the stacks, a missing stack
+// is a double fault, so `init` gives the stack region and the heap their
+// P3 tables up front. Doing it for all 416 kernel P4 entries would cost
+// 1.6MiB.
+//
+// The bootloader puts the kernel in the lower half, and so do the heap and
+// the stacks, so the kernel half isn't literally the upper half. Only the
+// direct map is up there. The user half is a window nothing else uses.
+//
+// With PCID every address space gets its own TLB tag and CR3 is written
+// with the no-flush bit, so switching keeps the TLB entries of all of
+// them. Entries of a PCID go stale when the tables behind it change while
+// it isn't active, or when it's handed to a new address space. `STALE`
+// remembers which cpus still have to flush it, they do when they activate
+// it next. PCID 0 is shared by the kernel and whatever didn't get one of
+// its own, it's flushed on every switch.
+
+use core::{arch::asm, ptr, sync::atomic::{AtomicBool, AtomicU64, Ordering}};
+
+use spin::Mutex;
+use x86_64::registers::control::{Cr3, Cr4};
+
+use crate::{addr::{PhyAddr, VirtualAddr}, allocator, cow::{self, CowError}, cpuid, memory, stack, tlb::{self, FlushRequest}, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::OffsetPageTable, page::Page, page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags}, phys_frame::PhysFrame}};
+
+
+// P4 entries 32..128, 48TiB
+pub const USER_P4_START: usize = 32;
+pub const USER_P4_END: usize = 128;
+pub const USER_SPACE_START: u64 = (USER_P4_START as u64) << 39;
+pub const USER_SPACE_END: u64 = (USER_P4_END as u64) << 39;
+
+const CR4_PCIDE: u64 = 1 << 17;
+const CR3_NO_FLUSH: u64 = 1 << 63;
+const PCID_COUNT: usize = 4096;
+
+// boot page table, the kernel's own address space
+static KERNEL_P4: AtomicU64 = AtomicU64::new(0);
+static PCID_ENABLED: AtomicBool = AtomicBool::new(false);
+// bit set = PCID in use, 0 is the kernel's
+static PCIDS: Mutex<[u64; PCID_COUNT / 64]> = Mutex::new([0; PCID_COUNT / 64]);
+// per PCID, bit set = that cpu (by local apic id) may still have entries
+// of older tables and has to flush before using it
+static STALE: [AtomicU64; PCID_COUNT] = [const { AtomicU64::new(0) }; PCID_COUNT];
+
+// parts of the kernel half that must not wait for `sync_kernel_entry`
+const KERNEL_RANGES: [(u64, u64); 2] = [
+    (allocator::HEAP_START, allocator::HEAP_START + allocator::HEAP_SIZE),
+    (stack::STACK_REGION_START, stack::STACK_REGION_END),
+];
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpaceError {
+    FrameAllocationFailed,
+    Cow(CowError),
+}
+
+impl From<CowError> for AddressSpaceError {
+    fn from(err: CowError) -> Self {
+        AddressSpaceError::Cow(err)
+    }
+}
+
+
+// Remembers the boot page table as the kernel's, gives the P4 entries of
+// `KERNEL_RANGES` a P3 table and turns on PCID if the cpu has it. Called
+// by `memory::init`, after the direct map is built.
+//
+// unsafe: the boot page table must be active, and this must only run once.
+pub unsafe fn init() {
+    KERNEL_P4.store(memory::active_level_4_table_addr().as_u64(), Ordering::Relaxed);
+
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let p4 = page_table.as_mut().expect("memory::init wasn't called").level_4_table_mut();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+
+    for index in USER_P4_START..USER_P4_END {
+        assert!(p4[index].is_unused(), "the bootloader mapped something into the user half");
+    }
+    for index in KERNEL_RANGES.iter().flat_map(|&(start, end)| p4_index(start)..=p4_index(end - 1)) {
+        assert!(!is_user_index(index), "kernel range in the user half");
+        if p4[index].is_unused() {
+            let frame = frame_allocator.allocate_frame().expect("out of memory for kernel page tables");
+            unsafe { zero_frame(frame) };
+            p4[index].set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+    }
+
+    if cpuid::has_pcid() {
+        // CR3 holds PCID 0 right now, which is what PCIDE wants
+        unsafe { Cr4::write_raw(Cr4::read_raw() | CR4_PCIDE) };
+        PCIDS.lock()[0] |= 1;
+        PCID_ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+#[inline]
+fn p4_index(addr: u64) -> usize {
+    (addr >> 39) as usize & 511
+}
+
+#[inline]
+fn is_user_index(index: usize) -> bool {
+    (USER_P4_START..USER_P4_END).contains(&index)
+}
+
+#[inline]
+pub fn is_user_addr(addr: VirtualAddr) -> bool {
+    (USER_SPACE_START..USER_SPACE_END).contains(&addr.as_u64())
+}
+
+#[inline]
+pub fn pcid_enabled() -> bool {
+    PCID_ENABLED.load(Ordering::Relaxed)
+}
+
+// Goes back to the boot page table.
+//
+// unsafe: see `AddressSpace::activate`.
+pub unsafe fn activate_kernel() {
+    unsafe { write_cr3(PhyAddr::new(KERNEL_P4.load(Ordering::Relaxed)), 0) };
+}
+
+// Called by the page fault handler before anything else. If the fault is
+// on a kernel P4 entry the active address space doesn't have yet, copies
+// it from the boot P4 and returns true, the access can run again.
+pub fn sync_kernel_entry(addr: VirtualAddr) -> bool {
+    let index = p4_index(addr.as_u64());
+    let kernel_p4 = KERNEL_P4.load(Ordering::Relaxed);
+    let active = memory::active_level_4_table_addr();
+    if kernel_p4 == 0 || is_user_index(index) || active.as_u64() == kernel_p4 {
+        return false;
+    }
+
+    let kernel = unsafe { &*table(PhyAddr::new(kernel_p4)) };
+    let p4 = unsafe { &mut *table(active) };
+    if !p4[index].is_unused() || kernel[index].is_unused() {
+        return false;
+    }
+    // not present entries aren't cached, nothing to flush
+    p4[index] = kernel[index];
+    true
+}
+
+// Mapper for whatever page table is active right now.
+//
+// unsafe: there must be no other mutable reference to the active tables,
+// and the address space must stay active while the mapper is used.
+pub unsafe fn active_mapper() -> OffsetPageTable<'static> {
+    let phys_offset = memory::physical_memory_offset().expect("physical memory not mapped");
+    unsafe { OffsetPageTable::new(memory::active_level_4_table(), phys_offset) }
+}
+
+// Makes every cpu flush the PCID of the active address space before it
+// uses it again. For changes through `active_mapper`, the `MapperFlush`
+// only reaches this cpu.
+pub fn mark_active_stale() {
+    if pcid_enabled() {
+        mark_stale(Cr3::read_raw().1);
+    }
+}
+
+
+pub struct AddressSpace {
+    p4: PhysFrame,
+    // 0 without PCID
+    pcid: u16,
+}
+
+impl AddressSpace {
+
+    // An empty user half with the kernel half shared.
+    pub fn new() -> Result<Self, AddressSpaceError> {
+        let p4 = {
+            let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+            frame_allocator.allocate_frame().ok_or(AddressSpaceError::FrameAllocationFailed)?
+        };
+
+        let kernel = unsafe { &*table(PhyAddr::new(KERNEL_P4.load(Ordering::Relaxed))) };
+        let p4_table = unsafe { &mut *table(p4.start_addr_of_physframe()) };
+        for index in 0..512 {
+            // with a recursive page table the recursive entry comes along
+            // too, it keeps pointing at the kernel P4. That's fine, the
+            // recursive mapper only ever works on the kernel tables.
+            p4_table[index] = if is_user_index(index) { PageTableEntry::new() } else { kernel[index] };
+        }
+
+        Ok(AddressSpace { p4, pcid: allocate_pcid() })
+    }
+
+    #[inline]
+    pub fn p4_frame(&self) -> PhysFrame {
+        self.p4
+    }
+
+    #[inline]
+    pub fn pcid(&self) -> u16 {
+        self.pcid
+    }
+
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        memory::active_level_4_table_addr() == self.p4.start_addr_of_physframe()
+    }
+
+    // Switches to this address space. Our TLB entries from the last time
+    // it was active here stay, unless they're stale.
+    //
+    // unsafe: the code and stack we're running on have to be in the
+    // kernel half (they always are), and nothing may keep using pointers
+    // into the user half of the old address space.
+    pub unsafe fn activate(&self) {
+        unsafe { write_cr3(self.p4.start_addr_of_physframe(), self.pcid) };
+    }
+
+    // Mapper for this address space, whether it's active or not. Changes to
+    // an inactive one don't need TLB flushes, the PCID is marked stale
+    // everywhere so the next activation flushes it. Changes to an active
+    // one still need `invalidate` when other cpus are using it.
+    pub fn mapper(&mut self) -> OffsetPageTable<'_> {
+        mark_stale(self.pcid);
+        let phys_offset = memory::physical_memory_offset().expect("physical memory not mapped");
+        unsafe { OffsetPageTable::new(&mut *table(self.p4.start_addr_of_physframe()), phys_offset) }
+    }
+
+    // A copy of this address space. The user pages are shared copy-on-write,
+    // see `cow`.
+    pub fn fork(&mut self) -> Result<AddressSpace, AddressSpaceError> {
+        let mut child = AddressSpace::new()?;
+
+        {
+            let mut child_mapper = child.mapper();
+            let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+
+            let mut result = Ok(());
+            unsafe {
+                walk_user_half(self.p4, &mut |visit| {
+                    let Visit::Page(page, entry) = visit else { return };
+                    if result.is_err() {
+                        return;
+                    }
+                    let frame = entry.frame().expect("walk only visits 4KiB pages");
+                    let mut flags = entry.flags();
+                    if flags.contains(PageTableFlags::WRITABLE) {
+                        flags = (flags - PageTableFlags::WRITABLE) | cow::COW;
+                        entry.set_flags(flags);
+                    }
+                    result = cow::map_shared(&mut child_mapper, page, frame, flags, frame_allocator);
+                });
+            }
+            result?;
+        }
+
+        // our own pages just lost WRITABLE
+        self.invalidate(FlushRequest::All);
+        Ok(child)
+    }
+
+    // Flushes `request` on the cpus that have this address space active
+    // and marks its PCID stale on all the others.
+    pub fn invalidate(&self, request: FlushRequest) {
+        mark_stale(self.pcid);
+        tlb::shootdown(self.p4.start_addr_of_physframe(), request);
+    }
+}
+
+impl Drop for AddressSpace {
+    // Frees every user page (shared ones lose a reference) and the page
+    // tables of the user half. The kernel half stays, it's shared.
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropping the active address space");
+
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+        unsafe {
+            walk_user_half(self.p4, &mut |visit| match visit {
+                Visit::Page(_, entry) => {
+                    frame_allocator.deallocate_frame(entry.frame().expect("walk only visits 4KiB pages"));
+                    entry.set_unused();
+                }
+                Visit::Table(frame) => frame_allocator.deallocate_frame(frame),
+            });
+            frame_allocator.deallocate_frame(self.p4);
+        }
+
+        if self.pcid != 0 {
+            PCIDS.lock()[self.pcid as usize / 64] &= !(1 << (self.pcid % 64));
+        }
+    }
+}
+
+
+fn allocate_pcid() -> u16 {
+    if !pcid_enabled() {
+        return 0;
+    }
+    let mut pcids = PCIDS.lock();
+    match pcids.iter().position(|&word| word != u64::MAX) {
+        Some(word) => {
+            let bit = pcids[word].trailing_ones() as usize;
+            pcids[word] |= 1 << bit;
+            let pcid = (word * 64 + bit) as u16;
+            // the TLBs may still have entries of the last one that had it
+            mark_stale(pcid);
+            pcid
+        }
+        // all taken, share the kernel's. Still correct, switching to it
+        // always flushes.
+        None => 0,
+    }
+}
+
+fn mark_stale(pcid: u16) {
+    if pcid != 0 {
+        STALE[pcid as usize].store(u64::MAX, Ordering::SeqCst);
+    }
+}
+
+unsafe fn write_cr3(p4: PhyAddr, pcid: u16) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // set_active comes first: whoever marks the PCID stale after we
+        // took our bit sees us active and sends a shootdown, which only
+        // gets handled once CR3 is written
+        tlb::set_active(p4);
+        let mut value = p4.as_u64() | pcid as u64;
+        if pcid != 0 {
+            let cpu = 1 << tlb::current_cpu();
+            if STALE[pcid as usize].fetch_and(!cpu, Ordering::SeqCst) & cpu == 0 {
+                value |= CR3_NO_FLUSH;
+            }
+        }
+        unsafe { asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags)) };
+    });
+}
+
+unsafe fn table(addr: PhyAddr) -> *mut PageTable {
+    memory::phys_to_virt(addr).expect("physical memory not mapped").as_mut_ptr::<PageTable>()
+}
+
+unsafe fn zero_frame(frame: PhysFrame) {
+    unsafe { ptr::write_bytes(table(frame.start_addr_of_physframe()), 0, 1) };
+}
+
+
+enum Visit<'a> {
+    // a mapped 4KiB page
+    Page(Page, &'a mut PageTableEntry),
+    // a page table below the P4, after all its entries were visited
+    Table(PhysFrame),
+}
+
+// Walks the user half of the tables under `p4`. The user half only has
+// 4KiB pages, huge ones aren't supported there.
+//
+// unsafe: `p4` must be a valid level 4 table and nobody else may be
+// changing its user half.
+unsafe fn walk_user_half(p4: PhysFrame, visit: &mut dyn FnMut(Visit)) {
+    let p4 = unsafe { &mut *table(p4.start_addr_of_physframe()) };
+    for i4 in USER_P4_START..USER_P4_END {
+        let Some(p3_frame) = next_table(&p4[i4]) else { continue };
+        let p3 = unsafe { &mut *table(p3_frame.start_addr_of_physframe()) };
+        for i3 in 0..512 {
+            let Some(p2_frame) = next_table(&p3[i3]) else { continue };
+            let p2 = unsafe { &mut *table(p2_frame.start_addr_of_physframe()) };
+            for i2 in 0..512 {
+                let Some(p1_frame) = next_table(&p2[i2]) else { continue };
+                let p1 = unsafe { &mut *table(p1_frame.start_addr_of_physframe()) };
+                for i1 in 0..512 {
+                    if !p1[i1].flags().contains(PageTableFlags::PRESENT) {
+                        continue;
+                    }
+                    let addr = ((i4 as u64) << 39) | ((i3 as u64) << 30) | ((i2 as u64) << 21) | ((i1 as u64) << 12);
+                    visit(Visit::Page(Page::containing_address(VirtualAddr::new(addr)), &mut p1[i1]));
+                }
+                visit(Visit::Table(p1_frame));
+            }
+            visit(Visit::Table(p2_frame));
+        }
+        visit(Visit::Table(p3_frame));
+    }
+}
+
+fn next_table(entry: &PageTableEntry) -> Option<PhysFrame> {
+    match entry.frame() {
+        Ok(frame) => Some(frame),
+        Err(FrameError::FrameNotPresent) => None,
+        Err(FrameError::HugeFrame) => panic!("huge page in the user half"),
+    }
+}
@@ -0,0 +1,104 @@
+// Bump allocator.
+// Hands out memory by moving `next` up and never reuses single
+// allocations; the whole heap is reset once every allocation is freed.
+// As fast as it gets, but one long lived allocation keeps everything
+// after it from being reused.
+
+use core::{alloc::{GlobalAlloc, Layout}, ptr};
+
+use super::{align_up, HeapStats, Locked};
+
+
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+    peak: usize,
+}
+
+impl BumpAllocator {
+
+    pub const fn new() -> Self {
+        BumpAllocator { heap_start: 0, heap_end: 0, next: 0, allocations: 0, peak: 0 }
+    }
+
+    // unsafe: the range must be valid, unused memory, and init must only
+    // be called once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    // null if the rest of the heap is too small.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let alloc_start = align_up(self.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) if end <= self.heap_end => end,
+            _ => return ptr::null_mut(),
+        };
+
+        self.next = alloc_end;
+        self.allocations += 1;
+        self.peak = self.peak.max(self.next - self.heap_start);
+        alloc_start as *mut u8
+    }
+
+    // unsafe: `ptr` must come from `allocate`.
+    pub unsafe fn deallocate(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
+
+    // everything below `next` counts as used, freed or not.
+    pub fn stats(&self) -> HeapStats {
+        let free = self.heap_end - self.next;
+        HeapStats {
+            size: self.heap_end - self.heap_start,
+            used: self.next - self.heap_start,
+            peak: self.peak,
+            free,
+            largest_free: free,
+        }
+    }
+}
+
+
+unsafe impl GlobalAlloc for Locked<BumpAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().deallocate(ptr, layout) };
+    }
+}
+
+
+#[test_case]
+fn test_bump_resets_when_empty() {
+    static mut ARENA: [u64; 64] = [0; 64];
+
+    let allocator = Locked::new(BumpAllocator::new());
+    let start = &raw mut ARENA as usize;
+    unsafe { allocator.lock().init(start, 512) };
+
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    unsafe {
+        let a = allocator.alloc(layout);
+        let b = allocator.alloc(layout);
+        assert_eq!(a as usize, start);
+        assert_eq!(b as usize, start + 104);
+
+        // freeing one doesn't give anything back
+        allocator.dealloc(a, layout);
+        assert_eq!(allocator.lock().stats().used, 204);
+
+        allocator.dealloc(b, layout);
+        assert_eq!(allocator.lock().stats().used, 0);
+        assert_eq!(allocator.alloc(layout) as usize, start);
+    }
+}
@@ -0,0 +1,166 @@
+// Fixed size block allocator.
+// Small allocations are rounded up to one of a few block sizes, each with
+// its own free list, so alloc and free are just a list push / pop. Blocks
+// are carved from the linked list allocator on demand and never given
+// back to it; anything bigger than the largest block goes to it directly.
+
+use core::{alloc::{GlobalAlloc, Layout}, mem};
+
+use super::{linked_list::LinkedListAllocator, HeapStats, Locked};
+
+
+// Block sizes double as alignment, so they have to be powers of two.
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback: LinkedListAllocator,
+
+    // what the callers asked for, rounded to block sizes
+    used: usize,
+    peak: usize,
+}
+
+impl FixedSizeBlockAllocator {
+
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback: LinkedListAllocator::new(),
+            used: 0,
+            peak: 0,
+        }
+    }
+
+    // unsafe: the range must be valid, unused memory, and init must only
+    // be called once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.fallback.init(heap_start, heap_size) };
+    }
+
+    // null if the heap is exhausted.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = match list_index(&layout) {
+            Some(index) => match self.list_heads[index].take() {
+                Some(node) => {
+                    self.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                // list is empty, get a new block
+                None => {
+                    let block_size = BLOCK_SIZES[index];
+                    let block_layout = Layout::from_size_align(block_size, block_size).unwrap();
+                    self.fallback.allocate(block_layout)
+                }
+            },
+            None => self.fallback.allocate(layout),
+        };
+
+        if !ptr.is_null() {
+            self.used += allocation_size(&layout);
+            self.peak = self.peak.max(self.used);
+        }
+        ptr
+    }
+
+    // unsafe: `ptr` must come from `allocate` with the same layout.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match list_index(&layout) {
+            Some(index) => {
+                // blocks are at least as big and aligned as a node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+
+                let node = ListNode { next: self.list_heads[index].take() };
+                let node_ptr = ptr as *mut ListNode;
+                unsafe {
+                    node_ptr.write(node);
+                    self.list_heads[index] = Some(&mut *node_ptr);
+                }
+            }
+            None => unsafe { self.fallback.deallocate(ptr, layout) },
+        }
+        self.used -= allocation_size(&layout);
+    }
+
+    // Blocks sitting in the free lists count as free, but only the
+    // fallback's regions can serve allocations of any size.
+    pub fn stats(&self) -> HeapStats {
+        let fallback = self.fallback.stats();
+        let mut free = fallback.free;
+        for (index, head) in self.list_heads.iter().enumerate() {
+            let mut current = head.as_deref();
+            while let Some(node) = current {
+                free += BLOCK_SIZES[index];
+                current = node.next.as_deref();
+            }
+        }
+
+        HeapStats { size: fallback.size, used: self.used, peak: self.peak, free, largest_free: fallback.largest_free }
+    }
+}
+
+// index of the smallest block that fits the layout.
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&size| size >= required_block_size)
+}
+
+// bytes an allocation really takes up.
+fn allocation_size(layout: &Layout) -> usize {
+    match list_index(layout) {
+        Some(index) => BLOCK_SIZES[index],
+        None => layout.size(),
+    }
+}
+
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().deallocate(ptr, layout) };
+    }
+}
+
+
+#[test_case]
+fn test_blocks_are_reused() {
+    // only ever touched through the allocator
+    #[allow(dead_code)]
+    #[repr(align(4096))]
+    struct Arena([u8; 8192]);
+    static mut ARENA: Arena = Arena([0; 8192]);
+
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    let start = &raw mut ARENA as usize;
+    unsafe { allocator.lock().init(start, 8192) };
+
+    let small = Layout::from_size_align(24, 8).unwrap();
+    unsafe {
+        let a = allocator.alloc(small);
+        let b = allocator.alloc(small);
+        assert_eq!(allocator.lock().stats().used, 64);
+
+        allocator.dealloc(a, small);
+        // same block size, straight from the free list
+        assert_eq!(allocator.alloc(Layout::from_size_align(32, 8).unwrap()), a);
+
+        // too big for a block
+        let big = Layout::from_size_align(4096, 8).unwrap();
+        let c = allocator.alloc(big);
+        assert!(!c.is_null());
+        assert_eq!(allocator.lock().stats().used, 64 + 4096);
+
+        allocator.dealloc(c, big);
+        allocator.dealloc(b, small);
+    }
+}
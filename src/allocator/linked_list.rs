@@ -0,0 +1,230 @@
+// Free list allocator.
+// Free regions are kept in a list sorted by address, with the list node
+// stored at the start of each region. Allocation is first fit; freed
+// regions are merged with their neighbours so the heap doesn't crumble
+// into pieces too small to use.
+
+use core::{alloc::{GlobalAlloc, Layout}, mem, ptr};
+
+use super::{align_up, HeapStats, Locked};
+
+
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut ListNode>,
+}
+
+impl ListNode {
+    const fn new(size: usize) -> Self {
+        ListNode { size, next: None }
+    }
+
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+
+pub struct LinkedListAllocator {
+    // dummy node, `head.next` is the first free region
+    head: ListNode,
+
+    size: usize,
+    used: usize,
+    peak: usize,
+}
+
+impl LinkedListAllocator {
+
+    pub const fn new() -> Self {
+        LinkedListAllocator { head: ListNode::new(0), size: 0, used: 0, peak: 0 }
+    }
+
+    // unsafe: the range must be valid, unused memory, and init must only
+    // be called once.
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.size = heap_size;
+        unsafe { self.add_free_region(heap_start, heap_size) };
+    }
+
+    // null if no free region is big enough.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        let Some((region, alloc_start)) = self.find_region(size, align) else {
+            return ptr::null_mut();
+        };
+
+        let (region_start, region_end) = (region.start_addr(), region.end_addr());
+        let alloc_end = alloc_start + size;
+        unsafe {
+            if alloc_start > region_start {
+                self.add_free_region(region_start, alloc_start - region_start);
+            }
+            if region_end > alloc_end {
+                self.add_free_region(alloc_end, region_end - alloc_end);
+            }
+        }
+
+        self.used += size;
+        self.peak = self.peak.max(self.used);
+        alloc_start as *mut u8
+    }
+
+    // unsafe: `ptr` must come from `allocate` with the same layout.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        unsafe { self.add_free_region(ptr as usize, size) };
+        self.used -= size;
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let (mut free, mut largest_free) = (0, 0);
+        let mut current = &self.head.next;
+        while let Some(region) = current {
+            free += region.size;
+            largest_free = largest_free.max(region.size);
+            current = &region.next;
+        }
+        HeapStats { size: self.size, used: self.used, peak: self.peak, free, largest_free }
+    }
+
+    // Puts a region back into the list at its place by address and merges
+    // it with the regions right before and after it.
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        let head: *mut ListNode = &mut self.head;
+        let mut prev = head;
+        unsafe {
+            while let Some(next) = (*prev).next.as_mut() {
+                if next.start_addr() > addr {
+                    break;
+                }
+                prev = &mut **next;
+            }
+
+            let node_ptr = addr as *mut ListNode;
+            node_ptr.write(ListNode { size, next: (*prev).next.take() });
+            let node = &mut *node_ptr;
+
+            if let Some(next) = node.next.take() {
+                if node.end_addr() == next.start_addr() {
+                    node.size += next.size;
+                    node.next = next.next.take();
+                } else {
+                    node.next = Some(next);
+                }
+            }
+
+            if prev != head && (*prev).end_addr() == addr {
+                (*prev).size += node.size;
+                (*prev).next = node.next.take();
+            } else {
+                (*prev).next = Some(node);
+            }
+        }
+    }
+
+    // Takes the first region that fits out of the list, together with the
+    // start address of the allocation inside it.
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+
+        while let Some(ref mut region) = current.next {
+            if let Some(alloc_start) = Self::alloc_from_region(region, size, align) {
+                let next = region.next.take();
+                let found = current.next.take().map(|region| (region, alloc_start));
+                current.next = next;
+                return found;
+            } else {
+                current = current.next.as_mut().unwrap();
+            }
+        }
+        None
+    }
+
+    // Where an allocation would start in `region`. The space left in front
+    // of and behind it must be either empty or big enough for a list node.
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Option<usize> {
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start != region.start_addr() && alloc_start - region.start_addr() < mem::size_of::<ListNode>() {
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
+
+        let alloc_end = alloc_start.checked_add(size)?;
+        if alloc_end > region.end_addr() {
+            return None;
+        }
+
+        let excess = region.end_addr() - alloc_end;
+        if excess > 0 && excess < mem::size_of::<ListNode>() {
+            return None;
+        }
+        Some(alloc_start)
+    }
+
+    // Every allocation must be able to hold a list node once it is freed.
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+}
+
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().deallocate(ptr, layout) };
+    }
+}
+
+
+#[test_case]
+fn test_free_regions_coalesce() {
+    // only ever touched through the allocator
+    #[allow(dead_code)]
+    #[repr(align(16))]
+    struct Arena([u8; 1024]);
+    static mut ARENA: Arena = Arena([0; 1024]);
+
+    let allocator = Locked::new(LinkedListAllocator::new());
+    let start = &raw mut ARENA as usize;
+    unsafe { allocator.lock().init(start, 1024) };
+
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    unsafe {
+        let a = allocator.alloc(layout);
+        let b = allocator.alloc(layout);
+        let c = allocator.alloc(layout);
+        assert_eq!(a as usize, start);
+        assert_eq!(b as usize, start + 256);
+
+        // freeing b and then a gives one 512 byte region again
+        allocator.dealloc(b, layout);
+        allocator.dealloc(a, layout);
+        let big = allocator.alloc(Layout::from_size_align(512, 8).unwrap());
+        assert_eq!(big as usize, start);
+
+        allocator.dealloc(big, Layout::from_size_align(512, 8).unwrap());
+        allocator.dealloc(c, layout);
+        let all = allocator.alloc(Layout::from_size_align(1024, 8).unwrap());
+        assert_eq!(all as usize, start);
+
+        let stats = allocator.lock().stats();
+        assert_eq!((stats.used, stats.free), (1024, 0));
+        assert_eq!(stats.peak, 1024);
+    }
+}
@@ -0,0 +1,129 @@
+// Kernel heap.
+// A fixed virtual range is mapped to frames from the frame allocator at
+// boot and handed to the global allocator, so `alloc` (Box, Vec, String...)
+// can be used from there on.
+//
+// Which allocator manages the heap is picked with a cargo feature:
+// `heap_bump`, `heap_linked_list` or (default) the fixed size block one.
+
+use core::{alloc::Layout, fmt};
+
+use spin::{Mutex, MutexGuard};
+
+use crate::{addr::VirtualAddr, memory, structures::{frame_alloc::FrameAllocator, mapper::{MapToError, Mapper}, page::{Page, Size4Kib}, page_table::PageTableFlags}};
+
+pub mod bump;
+pub mod fixed_size_block;
+pub mod linked_list;
+
+#[cfg(all(feature = "heap_bump", feature = "heap_linked_list"))]
+compile_error!("features `heap_bump` and `heap_linked_list` can't be enabled together");
+
+#[cfg(feature = "heap_bump")]
+pub type HeapAllocator = bump::BumpAllocator;
+
+#[cfg(feature = "heap_linked_list")]
+pub type HeapAllocator = linked_list::LinkedListAllocator;
+
+#[cfg(not(any(feature = "heap_bump", feature = "heap_linked_list")))]
+pub type HeapAllocator = fixed_size_block::FixedSizeBlockAllocator;
+
+
+pub const HEAP_START: u64 = 0x_4444_4444_0000;
+pub const HEAP_SIZE: u64 = 1024 * 1024;
+
+#[global_allocator]
+static ALLOCATOR: Locked<HeapAllocator> = Locked::new(HeapAllocator::new());
+
+
+// Maps the heap range and hands it to the allocator. Needs `memory::init`
+// to have run.
+pub fn init_heap() -> Result<(), MapToError<Size4Kib>> {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().expect("memory::init wasn't called");
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+
+    let heap_start_page = Page::<Size4Kib>::containing_address(VirtualAddr::new(HEAP_START));
+    let heap_end_page = Page::containing_address(VirtualAddr::new(HEAP_START + HEAP_SIZE - 1));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    for page in Page::range_inclusive(heap_start_page, heap_end_page) {
+        let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    unsafe { ALLOCATOR.lock().init(HEAP_START as usize, HEAP_SIZE as usize) };
+    Ok(())
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+pub fn print_stats() {
+    crate::serial_println!("heap ({}): {}", core::any::type_name::<HeapAllocator>(), stats());
+}
+
+
+// Heap usage in bytes. What counts as used depends on the allocator, the
+// bump allocator for example can't tell freed memory below `next` apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub peak: usize,
+    pub free: usize,
+    pub largest_free: usize,
+}
+
+impl HeapStats {
+    // Share of the free memory outside the largest free block, in percent.
+    // 0 means all free memory can be handed out in one piece.
+    pub fn fragmentation(&self) -> usize {
+        if self.free == 0 {
+            0
+        } else {
+            100 - self.largest_free * 100 / self.free
+        }
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} / {} bytes used, peak {}, {} free, largest free block {} ({}% fragmented)",
+            self.used, self.size, self.peak, self.free, self.largest_free, self.fragmentation()
+        )
+    }
+}
+
+
+// spin::Mutex is from another crate, so `GlobalAlloc` can't be implemented
+// on it directly.
+pub struct Locked<A> {
+    inner: Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked { inner: Mutex::new(inner) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+
+// `align` has to be a power of two.
+#[inline]
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!("allocation error: {:?}", layout)
+}
@@ -0,0 +1,146 @@
+// I/O APIC driver.
+// The registers are accessed indirectly: write the register index into
+// IOREGSEL, then read or write its value through IOWIN.
+
+use core::ptr;
+
+use bitflags::bitflags;
+
+use crate::addr::VirtualAddr;
+
+const IOREGSEL: u64 = 0x00;
+const IOWIN: u64 = 0x10;
+
+// registers
+const IOAPIC_ID: u32 = 0x00;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RedirectionFlags: u32 {
+        // destination is a logical apic set instead of an apic id
+        const LOGICAL_DESTINATION = 1 << 11;
+
+        // read only, interrupt is waiting to be delivered
+        const DELIVERY_PENDING = 1 << 12;
+
+        // line is active low instead of active high
+        const ACTIVE_LOW = 1 << 13;
+
+        // level triggered instead of edge triggered
+        const LEVEL_TRIGGERED = 1 << 15;
+
+        const MASKED = 1 << 16;
+    }
+}
+
+// one entry of the redirection table, decides where an input line goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    pub flags: RedirectionFlags,
+    pub destination: u8,
+}
+
+impl RedirectionEntry {
+
+    fn from_raw(low: u32, high: u32) -> Self {
+        RedirectionEntry {
+            vector: low as u8,
+            flags: RedirectionFlags::from_bits_truncate(low),
+            destination: (high >> 24) as u8,
+        }
+    }
+
+    // (low, high) register values, delivery mode is always fixed (0b000)
+    fn to_raw(self) -> (u32, u32) {
+        (u32::from(self.vector) | self.flags.bits(), u32::from(self.destination) << 24)
+    }
+}
+
+
+#[derive(Debug)]
+pub struct IoApic {
+    base: VirtualAddr,
+}
+
+impl IoApic {
+
+    // unsafe: `base` must be the virtual address of the I/O apic registers.
+    pub unsafe fn new(base: VirtualAddr) -> IoApic {
+        IoApic { base }
+    }
+
+    unsafe fn read(&self, register: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base.as_u64() + IOREGSEL) as *mut u32, register);
+            ptr::read_volatile((self.base.as_u64() + IOWIN) as *const u32)
+        }
+    }
+
+    unsafe fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base.as_u64() + IOREGSEL) as *mut u32, register);
+            ptr::write_volatile((self.base.as_u64() + IOWIN) as *mut u32, value);
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        unsafe { ((self.read(IOAPIC_ID) >> 24) & 0xf) as u8 }
+    }
+
+    pub fn version(&self) -> u8 {
+        unsafe { self.read(IOAPIC_VERSION) as u8 }
+    }
+
+    // number of input lines this I/O apic handles
+    pub fn redirection_entries(&self) -> u8 {
+        unsafe { ((self.read(IOAPIC_VERSION) >> 16) as u8) + 1 }
+    }
+
+    pub fn redirection(&self, gsi: u8) -> RedirectionEntry {
+        assert!(gsi < self.redirection_entries(), "I/O APIC has no input {}", gsi);
+        let register = IOAPIC_REDIRECTION_TABLE + 2 * u32::from(gsi);
+        unsafe { RedirectionEntry::from_raw(self.read(register), self.read(register + 1)) }
+    }
+
+    pub fn set_redirection(&mut self, gsi: u8, entry: RedirectionEntry) {
+        assert!(gsi < self.redirection_entries(), "I/O APIC has no input {}", gsi);
+        let register = IOAPIC_REDIRECTION_TABLE + 2 * u32::from(gsi);
+        let (low, high) = entry.to_raw();
+        unsafe {
+            // mask while the entry is half written
+            self.write(register, RedirectionFlags::MASKED.bits());
+            self.write(register + 1, high);
+            self.write(register, low);
+        }
+    }
+
+    pub fn mask(&mut self, gsi: u8) {
+        let mut entry = self.redirection(gsi);
+        entry.flags.insert(RedirectionFlags::MASKED);
+        self.set_redirection(gsi, entry);
+    }
+
+    pub fn unmask(&mut self, gsi: u8) {
+        let mut entry = self.redirection(gsi);
+        entry.flags.remove(RedirectionFlags::MASKED);
+        self.set_redirection(gsi, entry);
+    }
+
+    // Routes a legacy ISA IRQ to `vector` on the local apic `apic_id`.
+    // ISA lines are edge triggered / active high and map 1:1 onto the
+    // I/O apic inputs, except the PIT which the firmware wires to input 2.
+    // (Without parsing the MADT interrupt source overrides this is what
+    // qemu and most PCs do.)
+    pub fn route_isa_irq(&mut self, irq: u8, vector: u8, apic_id: u8) {
+        let gsi = if irq == 0 { 2 } else { irq };
+        self.set_redirection(gsi, RedirectionEntry {
+            vector,
+            flags: RedirectionFlags::empty(),
+            destination: apic_id,
+        });
+    }
+}
@@ -0,0 +1,217 @@
+// Local APIC driver.
+// Supports both register interfaces: xAPIC where the registers are memory
+// mapped at the apic base, and x2APIC where every register is an MSR at
+// 0x800 + (offset >> 4).
+
+use core::ptr;
+
+use crate::{addr::VirtualAddr, apic::{mmio_base, ApicError}, model_specific::{ApicBase, ApicBaseFlags, Msr}};
+
+// register offsets (xAPIC layout)
+const ID: u32 = 0x020;
+const VERSION: u32 = 0x030;
+const TASK_PRIORITY: u32 = 0x080;
+const END_OF_INTERRUPT: u32 = 0x0B0;
+const SPURIOUS_VECTOR: u32 = 0x0F0;
+const ERROR_STATUS: u32 = 0x280;
+const INTERRUPT_COMMAND_LOW: u32 = 0x300;
+const INTERRUPT_COMMAND_HIGH: u32 = 0x310;
+const LVT_TIMER: u32 = 0x320;
+const LVT_LINT0: u32 = 0x350;
+const LVT_LINT1: u32 = 0x360;
+const LVT_ERROR: u32 = 0x370;
+const TIMER_INITIAL_COUNT: u32 = 0x380;
+const TIMER_CURRENT_COUNT: u32 = 0x390;
+const TIMER_DIVIDE: u32 = 0x3E0;
+
+// spurious vector register: apic software enable
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+
+// local vector table entry: interrupt masked
+const LVT_MASKED: u32 = 1 << 16;
+
+// interrupt command register: level assert, and the xAPIC's still sending bit
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+
+// x2APIC registers live at this MSR base
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicMode {
+    XApic { base: VirtualAddr },
+    X2Apic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    // counts down once and fires a single interrupt
+    OneShot = 0b00,
+
+    // reloads the initial count and fires again every time it hits zero
+    Periodic = 0b01,
+}
+
+// the timer counts at bus clock / divide
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TimerDivide {
+    By1 = 0b1011,
+    By2 = 0b0000,
+    By4 = 0b0001,
+    By8 = 0b0010,
+    By16 = 0b0011,
+    By32 = 0b1000,
+    By64 = 0b1001,
+    By128 = 0b1010,
+}
+
+
+#[derive(Debug)]
+pub struct LocalApic {
+    mode: ApicMode,
+}
+
+impl LocalApic {
+
+    // Enables the local apic of the current cpu, in x2APIC mode if asked for.
+    //
+    // unsafe: must be called once per cpu, and the IDT needs handlers for
+    // the vectors that get programmed afterwards.
+    pub unsafe fn enable(x2apic: bool) -> Result<LocalApic, ApicError> {
+        let (base, mut flags) = ApicBase::read();
+
+        flags.insert(ApicBaseFlags::LAPIC_ENABLE);
+        let mode = if x2apic {
+            flags.insert(ApicBaseFlags::X2APIC_ENABLE);
+            ApicMode::X2Apic
+        } else {
+            ApicMode::XApic { base: mmio_base(base)? }
+        };
+
+        unsafe { ApicBase::write(base, flags); }
+
+        let mut local_apic = LocalApic { mode };
+        unsafe {
+            // accept every priority, nothing set up on the local interrupt pins
+            local_apic.write(TASK_PRIORITY, 0);
+            local_apic.write(LVT_LINT0, LVT_MASKED);
+            local_apic.write(LVT_LINT1, LVT_MASKED);
+            local_apic.write(LVT_TIMER, LVT_MASKED);
+        }
+        Ok(local_apic)
+    }
+
+    #[inline]
+    pub fn mode(&self) -> ApicMode {
+        self.mode
+    }
+
+    unsafe fn read(&self, offset: u32) -> u32 {
+        match self.mode {
+            ApicMode::XApic { base } => unsafe {
+                ptr::read_volatile((base.as_u64() + u64::from(offset)) as *const u32)
+            },
+            ApicMode::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (offset >> 4)).read() as u32
+            },
+        }
+    }
+
+    unsafe fn write(&mut self, offset: u32, value: u32) {
+        match self.mode {
+            ApicMode::XApic { base } => unsafe {
+                ptr::write_volatile((base.as_u64() + u64::from(offset)) as *mut u32, value)
+            },
+            ApicMode::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (offset >> 4)).write(u64::from(value))
+            },
+        }
+    }
+
+    // id of this local apic, what the I/O apic uses as destination
+    pub fn id(&self) -> u8 {
+        let id = unsafe { self.read(ID) };
+        match self.mode {
+            // xAPIC keeps the id in the top byte, x2APIC uses the full register
+            ApicMode::XApic { .. } => (id >> 24) as u8,
+            ApicMode::X2Apic => id as u8,
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        unsafe { self.read(VERSION) as u8 }
+    }
+
+    // error status register, has to be written before it can be read
+    pub fn error_status(&mut self) -> u32 {
+        unsafe {
+            self.write(ERROR_STATUS, 0);
+            self.read(ERROR_STATUS)
+        }
+    }
+
+    // Sets the vector delivered for spurious interrupts and software
+    // enables the apic. Spurious interrupts must not be acknowledged.
+    //
+    // unsafe: the IDT needs a handler for `vector`.
+    pub unsafe fn set_spurious_vector(&mut self, vector: u8) {
+        unsafe { self.write(SPURIOUS_VECTOR, SOFTWARE_ENABLE | u32::from(vector)); }
+    }
+
+    // unsafe: the IDT needs a handler for `vector`.
+    pub unsafe fn set_error_vector(&mut self, vector: u8) {
+        unsafe { self.write(LVT_ERROR, u32::from(vector)); }
+    }
+
+    // Starts the timer, it counts down from `initial_count` at bus clock / `divide`.
+    //
+    // unsafe: the IDT needs a handler for `vector`.
+    pub unsafe fn set_timer(&mut self, vector: u8, mode: TimerMode, divide: TimerDivide, initial_count: u32) {
+        unsafe {
+            self.write(TIMER_DIVIDE, divide as u32);
+            self.write(LVT_TIMER, ((mode as u32) << 17) | u32::from(vector));
+            // writing the initial count starts the timer
+            self.write(TIMER_INITIAL_COUNT, initial_count);
+        }
+    }
+
+    pub fn stop_timer(&mut self) {
+        unsafe {
+            self.write(LVT_TIMER, LVT_MASKED);
+            self.write(TIMER_INITIAL_COUNT, 0);
+        }
+    }
+
+    pub fn timer_current_count(&self) -> u32 {
+        unsafe { self.read(TIMER_CURRENT_COUNT) }
+    }
+
+    // Sends interrupt `vector` to the local apic `destination` (fixed
+    // delivery, physical destination mode).
+    //
+    // unsafe: the destination's IDT needs a handler for `vector`.
+    pub unsafe fn send_ipi(&mut self, destination: u32, vector: u8) {
+        let command = ICR_ASSERT | u32::from(vector);
+        match self.mode {
+            ApicMode::XApic { .. } => unsafe {
+                // writing the low half sends it, the destination goes first
+                self.write(INTERRUPT_COMMAND_HIGH, destination << 24);
+                self.write(INTERRUPT_COMMAND_LOW, command);
+                while self.read(INTERRUPT_COMMAND_LOW) & ICR_DELIVERY_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            },
+            // a single 64 bit MSR in x2APIC mode
+            ApicMode::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (INTERRUPT_COMMAND_LOW >> 4)).write((u64::from(destination) << 32) | u64::from(command))
+            },
+        }
+    }
+
+    // unsafe: only at the end of a handler for an interrupt delivered by this apic.
+    pub unsafe fn end_of_interrupt(&mut self) {
+        unsafe { self.write(END_OF_INTERRUPT, 0); }
+    }
+}
@@ -0,0 +1,90 @@
+// Local APIC and I/O APIC interrupt controllers.
+//
+// The local apic sits in every cpu, receives the interrupts and has its own
+// timer. The I/O apic receives the ISA / PCI interrupt lines and forwards
+// them as messages to a local apic, replacing the 8259 PICs.
+
+pub mod io;
+pub mod local;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::{addr::{PhyAddr, VirtualAddr}, cpuid, interrupts::{self, InterruptIndex}, memory};
+use self::{io::IoApic, local::LocalApic};
+
+// The address where firmware places the I/O apic on pretty much every PC
+// (and on qemu). The real address comes from the ACPI MADT.
+pub const IO_APIC_DEFAULT_BASE: u64 = 0xFEC0_0000;
+
+// Interrupt handlers lock the local apic for the EOI and the error status,
+// so outside of them it has to be locked with interrupts disabled.
+pub static LOCAL_APIC: Mutex<Option<LocalApic>> = Mutex::new(None);
+pub static IO_APIC: Mutex<Option<IoApic>> = Mutex::new(None);
+
+static APIC_ENABLED: AtomicBool = AtomicBool::new(false);
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    // cpu has no local apic
+    NotSupported,
+
+    // MMIO registers can't be reached before physical memory is mapped
+    PhysicalMemoryNotMapped,
+}
+
+
+// true once interrupts are delivered through the apics instead of the PICs
+#[inline]
+pub fn is_enabled() -> bool {
+    APIC_ENABLED.load(Ordering::SeqCst)
+}
+
+// MMIO registers are reached through the physical memory mapping.
+fn mmio_base(addr: PhyAddr) -> Result<VirtualAddr, ApicError> {
+    memory::phys_to_virt(addr).ok_or(ApicError::PhysicalMemoryNotMapped)
+}
+
+
+// Switches the interrupt delivery from the PICs to the apics.
+// Masks the PICs, enables the local apic of this cpu (x2APIC when the cpu
+// supports it) and routes the ISA timer and keyboard through the I/O apic.
+pub fn init() -> Result<(), ApicError> {
+    if !cpuid::has_apic() {
+        return Err(ApicError::NotSupported);
+    }
+
+    let mut local_apic = unsafe { LocalApic::enable(cpuid::has_x2apic()) }?;
+    let mut io_apic = unsafe { IoApic::new(mmio_base(PhyAddr::new(IO_APIC_DEFAULT_BASE))?) };
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        interrupts::PICS.lock().disable();
+
+        unsafe {
+            local_apic.set_spurious_vector(InterruptIndex::ApicSpurious.as_u8());
+            local_apic.set_error_vector(InterruptIndex::ApicError.as_u8());
+        }
+
+        let apic_id = local_apic.id();
+        io_apic.route_isa_irq(InterruptIndex::Timer.irq(), InterruptIndex::Timer.as_u8(), apic_id);
+        io_apic.route_isa_irq(InterruptIndex::Keyboard.irq(), InterruptIndex::Keyboard.as_u8(), apic_id);
+
+        *LOCAL_APIC.lock() = Some(local_apic);
+        *IO_APIC.lock() = Some(io_apic);
+        APIC_ENABLED.store(true, Ordering::SeqCst);
+    });
+
+    Ok(())
+}
+
+// Signals end of interrupt to the local apic of this cpu.
+//
+// unsafe: must only be called at the end of a handler for an interrupt
+// that was delivered through the local apic.
+pub unsafe fn end_of_interrupt() {
+    if let Some(local_apic) = LOCAL_APIC.lock().as_mut() {
+        unsafe { local_apic.end_of_interrupt(); }
+    }
+}
@@ -0,0 +1,155 @@
+// Copy-on-write sharing.
+// Forking a page maps its frame a second time and takes away WRITABLE on
+// both sides, with the software bit `COW` set instead. The frame gets an
+// extra reference in the frame allocator. The first write to either side
+// faults: if the frame is still shared it's copied into a fresh frame,
+// if the other side is already gone the page just gets WRITABLE back.
+
+use core::ptr;
+
+use crate::{addr::VirtualAddr, address_space, idt::PageFaultErrorCode, memory::{self, ActivePageTable}, pmm::BitmapFrameAllocator, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::{MapToError, MappedFrame, Mapper, Translate, TranslateResult}, page::{Page, PageRange, PageSize, Size4Kib}, page_table::PageTableFlags, phys_frame::PhysFrame}};
+
+
+// marks a page that was writable before it got shared
+pub const COW: PageTableFlags = PageTableFlags::BIT9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CowError {
+    // source and destination don't have the same number of pages
+    LengthMismatch,
+    // the source is mapped with a huge page
+    HugePage,
+    Map(MapToError<Size4Kib>),
+}
+
+impl From<MapToError<Size4Kib>> for CowError {
+    fn from(err: MapToError<Size4Kib>) -> Self {
+        CowError::Map(err)
+    }
+}
+
+
+// Write protects `page` for sharing and returns its frame and the flags
+// both sides should use. None if the page isn't mapped.
+pub fn protect<M>(mapper: &mut M, page: Page) -> Result<Option<(PhysFrame, PageTableFlags)>, CowError>
+where
+    M: Mapper<Size4Kib> + Translate,
+{
+    let (frame, flags) = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { frame: MappedFrame::Size4Kib(frame), flags, .. } => (frame, flags),
+        TranslateResult::Mapped { .. } => return Err(CowError::HugePage),
+        _ => return Ok(None),
+    };
+
+    // read only pages can be shared as they are
+    if !flags.contains(PageTableFlags::WRITABLE) {
+        return Ok(Some((frame, flags)));
+    }
+
+    let shared = (flags - PageTableFlags::WRITABLE) | COW;
+    unsafe { mapper.update_flags(page, shared).expect("translated page isn't mapped").flush() };
+    Ok(Some((frame, shared)))
+}
+
+// Maps `frame`, returned by `protect`, at `page` and takes a reference
+// to it.
+//
+// unsafe: `frame` and `flags` must come from `protect`.
+pub unsafe fn map_shared<M>(
+    mapper: &mut M,
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), CowError>
+where
+    M: Mapper<Size4Kib>,
+{
+    // the tables above have to allow writing, or getting WRITABLE back
+    // on a write fault wouldn't help
+    let parent_table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | (flags & PageTableFlags::USERACCESSIBLE);
+    unsafe { mapper.map_to_with_table_flags(page, frame, flags, parent_table_flags, frame_allocator)?.flush() };
+    frame_allocator.add_ref(frame);
+    Ok(())
+}
+
+// Shares every mapped page of `src` with `dst`, in the active page table.
+// Pages of `src` that aren't mapped are left alone.
+pub fn fork_range(src: PageRange, dst: PageRange) -> Result<(), CowError> {
+    if src.len() != dst.len() {
+        return Err(CowError::LengthMismatch);
+    }
+
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper: &mut ActivePageTable = page_table.as_mut().expect("memory::init wasn't called");
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+
+    for (src_page, dst_page) in src.zip(dst) {
+        if let Some((frame, flags)) = protect(mapper, src_page)? {
+            unsafe { map_shared(mapper, dst_page, frame, flags, frame_allocator)? };
+        }
+    }
+    Ok(())
+}
+
+
+// Called by the page fault handler. Returns true if `addr` was a write to
+// a copy-on-write page and the page is writable now.
+pub fn handle_page_fault(addr: VirtualAddr, error_code: PageFaultErrorCode) -> bool {
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE) {
+        return false;
+    }
+
+    let page = Page::containing_address(addr);
+    memory::with_fault_locks(|mapper, frame_allocator| {
+        // the user half belongs to whatever address space is active, the
+        // kernel page table only has the kernel's
+        if address_space::is_user_addr(addr) {
+            let mut mapper = unsafe { address_space::active_mapper() };
+            let resolved = unsafe { resolve(&mut mapper, frame_allocator, page) };
+            if resolved {
+                // other cpus may still have the shared frame under this PCID
+                address_space::mark_active_stale();
+            }
+            return resolved;
+        }
+        unsafe { resolve(mapper, frame_allocator, page) }
+    }).unwrap_or(false)
+}
+
+// Gives `page` its own writable frame if it's a copy-on-write page.
+//
+// unsafe: `mapper` must be the active page table.
+pub unsafe fn resolve<M>(mapper: &mut M, frame_allocator: &mut BitmapFrameAllocator, page: Page) -> bool
+where
+    M: Mapper<Size4Kib> + Translate,
+{
+    let (frame, flags) = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { frame: MappedFrame::Size4Kib(frame), flags, .. } if flags.contains(COW) => (frame, flags),
+        _ => return false,
+    };
+    let writable = (flags - COW) | PageTableFlags::WRITABLE;
+
+    // last one left, no need to copy
+    if frame_allocator.ref_count(frame) == 1 {
+        unsafe { mapper.update_flags(page, writable).expect("translated page isn't mapped").flush() };
+        return true;
+    }
+
+    let Some(copy) = frame_allocator.allocate_frame() else { return false };
+    let (Some(from), Some(to)) = (memory::phys_to_virt(frame.start_addr_of_physframe()), memory::phys_to_virt(copy.start_addr_of_physframe())) else {
+        unsafe { frame_allocator.deallocate_frame(copy) };
+        return false;
+    };
+    unsafe { ptr::copy_nonoverlapping(from.as_ptr::<u8>(), to.as_mut_ptr::<u8>(), Size4Kib::SIZE as usize) };
+
+    let (_, flush) = mapper.unmap(page).expect("translated page isn't mapped");
+    flush.ignore();
+    // the page tables are all there, so no new ones get allocated
+    unsafe { mapper.map_to(page, copy, writable, frame_allocator).expect("remapping a page failed").flush() };
+
+    // drop our reference to the shared frame
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    true
+}
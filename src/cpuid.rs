@@ -0,0 +1,63 @@
+// CPUID feature detection.
+// Small wrappers around the `cpuid` instruction for the features the kernel
+// needs to decide between code paths.
+
+use core::arch::x86_64::{CpuidResult, __cpuid_count};
+
+
+#[inline]
+pub fn cpuid(leaf: u32, sub_leaf: u32) -> CpuidResult {
+    __cpuid_count(leaf, sub_leaf)
+}
+
+// highest supported standard leaf
+#[inline]
+pub fn max_leaf() -> u32 {
+    cpuid(0, 0).eax
+}
+
+// highest supported extended leaf (0x8000_0000 and up)
+#[inline]
+pub fn max_extended_leaf() -> u32 {
+    cpuid(0x8000_0000, 0).eax
+}
+
+// local apic present, CPUID.01H:EDX[9]
+pub fn has_apic() -> bool {
+    cpuid(1, 0).edx & (1 << 9) != 0
+}
+
+// x2APIC mode supported, CPUID.01H:ECX[21]
+pub fn has_x2apic() -> bool {
+    cpuid(1, 0).ecx & (1 << 21) != 0
+}
+
+// time stamp counter present, CPUID.01H:EDX[4]
+pub fn has_tsc() -> bool {
+    cpuid(1, 0).edx & (1 << 4) != 0
+}
+
+// TSC runs at a constant rate in every power state, CPUID.80000007H:EDX[8]
+pub fn has_invariant_tsc() -> bool {
+    max_extended_leaf() >= 0x8000_0007 && cpuid(0x8000_0007, 0).edx & (1 << 8) != 0
+}
+
+// process context identifiers, CPUID.01H:ECX[17]
+pub fn has_pcid() -> bool {
+    cpuid(1, 0).ecx & (1 << 17) != 0
+}
+
+// INVPCID instruction, CPUID.(EAX=07H,ECX=0):EBX[10]
+pub fn has_invpcid() -> bool {
+    max_leaf() >= 7 && cpuid(7, 0).ebx & (1 << 10) != 0
+}
+
+// initial local apic id of the cpu running this, CPUID.01H:EBX[31:24]
+pub fn apic_id() -> u8 {
+    (cpuid(1, 0).ebx >> 24) as u8
+}
+
+// 1GiB pages, CPUID.80000001H:EDX[26]
+pub fn has_1gib_pages() -> bool {
+    max_extended_leaf() >= 0x8000_0001 && cpuid(0x8000_0001, 0).edx & (1 << 26) != 0
+}
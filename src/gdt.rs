@@ -2,30 +2,35 @@ use lazy_static::lazy_static;
 use x86_64::instructions::tables::load_tss;
 use x86_64::registers::segmentation::{Segment, CS};
 use x86_64::{structures::tss::TaskStateSegment, VirtAddr};
-use x86_64::structures::gdt::{self, Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+
+use crate::stack::{self, KernelStack, StackError};
 
 pub const DOUBLE_FAULT_1ST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
 
-lazy_static!{
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_1ST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK:[ u8; STACK_SIZE ] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE.try_into().unwrap();
-            stack_end
-        };
-        tss
-    };
-}
+// every IST entry we use, with its stack size in pages
+const IST_STACKS: [(u16, u64); 3] = [
+    (DOUBLE_FAULT_1ST_INDEX, 5),
+    (NMI_IST_INDEX, 2),
+    (MACHINE_CHECK_IST_INDEX, 2),
+];
+
+// Until memory is set up the IST entries point into this one. It has no
+// guard page, `init_stacks` replaces it once stacks can be mapped.
+const BOOT_STACK_SIZE: usize = 4096 * 5;
+static mut BOOT_STACK: [u8; BOOT_STACK_SIZE] = [0; BOOT_STACK_SIZE];
+
+// The cpu reads the IST from memory on every interrupt, so the entries can
+// still change after the TSS is loaded.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable , Selectors) = {
          let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.append(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.append(Descriptor::tss_segment(&TSS));
+        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*(&raw const TSS) }));
         (
             gdt,
             Selectors {
@@ -41,9 +46,30 @@ struct Selectors {
 }
 
 pub fn init() {
+    let boot_stack_end = VirtAddr::from_ptr(&raw const BOOT_STACK) + BOOT_STACK_SIZE as u64;
+    for (index, _) in IST_STACKS {
+        unsafe { (*(&raw mut TSS)).interrupt_stack_table[index as usize] = boot_stack_end };
+    }
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
         load_tss(GDT.1.tss_selector);
     }
+}
+
+// Gives every IST entry its own stack with a guard page. Needs the page
+// table and frame allocator, so it runs at the end of `memory::init`.
+pub fn init_stacks() -> Result<(), StackError> {
+    for (index, pages) in IST_STACKS {
+        let stack = stack::allocate(pages)?;
+        let top = VirtAddr::new(stack.top().as_u64());
+        x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+            (*(&raw mut TSS)).interrupt_stack_table[index as usize] = top;
+        });
+
+        // IST stacks live as long as the kernel
+        core::mem::forget::<KernelStack>(stack);
+    }
+    Ok(())
 }
\ No newline at end of file
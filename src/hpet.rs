@@ -0,0 +1,72 @@
+// High Precision Event Timer.
+// Only the free running main counter is used, as a reference clock.
+// There's no ACPI parsing yet, so the HPET is probed at the address the
+// chipsets (and qemu) put it at.
+
+use core::ptr;
+
+use crate::{addr::{PhyAddr, VirtualAddr}, memory};
+
+pub const HPET_DEFAULT_BASE: u64 = 0xFED0_0000;
+
+// registers
+const CAPABILITIES: u64 = 0x000;
+const CONFIGURATION: u64 = 0x010;
+const MAIN_COUNTER: u64 = 0x0F0;
+
+const ENABLE: u64 = 1 << 0;
+
+// the spec caps the counter period at 100ns
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+
+#[derive(Debug)]
+pub struct Hpet {
+    base: VirtualAddr,
+
+    // length of one counter tick in femtoseconds
+    period_fs: u64,
+}
+
+impl Hpet {
+
+    // Looks for a HPET at the default address and starts its main counter.
+    // None if physical memory isn't mapped or nothing sane is there.
+    pub fn probe() -> Option<Hpet> {
+        let base = memory::phys_to_virt(PhyAddr::new(HPET_DEFAULT_BASE))?;
+        let capabilities = unsafe { ptr::read_volatile((base.as_u64() + CAPABILITIES) as *const u64) };
+
+        let revision = capabilities as u8;
+        let period_fs = capabilities >> 32;
+        if revision == 0 || period_fs == 0 || period_fs > MAX_PERIOD_FS {
+            return None;
+        }
+
+        let mut hpet = Hpet { base, period_fs };
+        hpet.enable();
+        Some(hpet)
+    }
+
+    fn enable(&mut self) {
+        let configuration = (self.base.as_u64() + CONFIGURATION) as *mut u64;
+        unsafe {
+            ptr::write_volatile(configuration, ptr::read_volatile(configuration) | ENABLE);
+        }
+    }
+
+    #[inline]
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    // counter ticks per second
+    #[inline]
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_fs
+    }
+
+    #[inline]
+    pub fn counter(&self) -> u64 {
+        unsafe { ptr::read_volatile((self.base.as_u64() + MAIN_COUNTER) as *const u64) }
+    }
+}
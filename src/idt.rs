@@ -1,11 +1,13 @@
 // Interrupt Descriptor Table
 
-use core::{fmt::{self, write}, marker::PhantomData, ops::{Bound, Deref, IndexMut, Range, RangeBounds, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive}};
+use core::{arch::asm, fmt, marker::PhantomData, ops::{Bound, Deref, Index, IndexMut, Range, RangeBounds, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive}};
 
+use bit_field::BitField;
 use bitflags::bitflags;
 use volatile::Volatile;
+use x86_64::{registers::rflags::RFlags, PrivilegeLevel, VirtAddr};
 
-use crate::{segmentation::{SegmentSelector, CS}, DescriptorTablePointer};
+use crate::{segmentation::{Segment, SegmentSelector, CS}, DescriptorTablePointer};
 
 #[repr(C)]
 pub struct InterruptDescriptorTable {
@@ -84,13 +86,14 @@ impl InterruptDescriptorTable {
             unsafe { self.load_unsafe(); }
         }
 
-        pub fn load_unsafe(&self) {
+        // unsafe: the table has to stay alive, and unchanged, for as long as
+        // it's loaded.
+        pub unsafe fn load_unsafe(&self) {
             unsafe {
                 lidt(&self.pointer());
             }
         }
 
-        #[cfg(all( feature = "instructions", target_arch="x86_64" ))]
         pub fn pointer(&self) -> DescriptorTablePointer {
             use core::mem::size_of;
             DescriptorTablePointer { 
@@ -110,8 +113,8 @@ impl InterruptDescriptorTable {
             };
 
             let upper_idx = match bounds.end_bound() {
-                core::ops::Bound::Included(end) => usize::from(*end),
-                core::ops::Bound::Excluded(end) => usize::from(*end) + 1,
+                core::ops::Bound::Included(end) => usize::from(*end) + 1,
+                core::ops::Bound::Excluded(end) => usize::from(*end),
                 core::ops::Bound::Unbounded => 256
             };
 
@@ -127,7 +130,7 @@ impl InterruptDescriptorTable {
         #[inline]
         pub fn slice(&self, bounds: impl RangeBounds<u8>) -> &[Entry<HandlerFunc>] {
             let ( lower_idx, upper_idx ) = self.condition_slice_bounds(bounds);
-            &mut self.interrupts[ (lower_idx - 32)..(upper_idx - 32) ] 
+            &self.interrupts[ (lower_idx - 32)..(upper_idx - 32) ] 
         }
 
 
@@ -138,10 +141,15 @@ impl InterruptDescriptorTable {
         }
 }
 
-impl default for InterruptDescriptorTable {
+// unsafe: `idt` has to point to a valid IDT that stays alive while it's loaded.
+unsafe fn lidt(idt: &DescriptorTablePointer) {
+    unsafe { asm!("lidt [{}]", in(reg) idt, options(readonly, nostack, preserves_flags)) };
+}
+
+impl Default for InterruptDescriptorTable {
     #[inline]
     fn default() -> Self {
-        Self::new();
+        Self::new()
     }
 }
 
@@ -165,7 +173,7 @@ impl Index<u8> for InterruptDescriptorTable {
             20 =>   &self.virtualization,
             28 =>   &self.hv_injection_exception,
             i @ 32..=255 =>         &self.interrupts[usize::from(i) - 32],
-            i @ 15 | i @ 31 | i @ 22..27 =>     panic!("entry {} is reserved", i),
+            i @ 15 | i @ 31 | i @ 22..=27 =>     panic!("entry {} is reserved", i),
             i @ 8 | i @ 10..=14 | i @ 17 | i @ 21 | i @ 29 | i @ 30 => {
                 panic!("entry {} is an exception with wrror code", i)
             }
@@ -192,7 +200,7 @@ impl IndexMut<u8> for InterruptDescriptorTable {
             20 =>   &mut self.virtualization,
             28 =>   &mut self.hv_injection_exception,
             i @ 32..=255 =>         &mut self.interrupts[usize::from(i) - 32],
-            i @ 15 | i @ 31 | i @ 22..27 =>     panic!("entry {} is reserved", i),
+            i @ 15 | i @ 31 | i @ 22..=27 =>     panic!("entry {} is reserved", i),
             i @ 8 | i @ 10..=14 | i @ 17 | i @ 21 | i @ 29 | i @ 30 => {
                 panic!("entry {} is an exception with wrror code", i)
             }
@@ -211,13 +219,13 @@ macro_rules! impl_idx_for_idt {
             fn index(&self, index: $ty) -> &Self::Output {
                 self.slice(index)
             }
+        }
+
+        impl IndexMut<$ty> for InterruptDescriptorTable {
 
-            impl IndexMut<$ty> for InterruptDescriptorTable {
-                
-                #[inline]
-                fn index_mut(&mut self, index: $ty) -> &mut Self::Output {
-                    self.slice_mut(index)
-                }
+            #[inline]
+            fn index_mut(&mut self, index: $ty) -> &mut Self::Output {
+                self.slice_mut(index)
             }
         }
     };
@@ -243,9 +251,9 @@ impl_idx_for_idt!(RangeFull);
 #[repr(C)]
 pub struct Entry<F> {
     pointer_low: u16,
-    pointer_high: u16,
     options: EntryOptions,
     pointer_middle: u16,
+    pointer_high: u32,
     reserved: u32,
     phantom: PhantomData<F>
 }
@@ -265,6 +273,7 @@ impl<T> PartialEq for Entry<T> {
     fn eq(&self, other: &Self) -> bool {
         self.pointer_low == other.pointer_low
         && self.options == other.options
+        && self.pointer_middle == other.pointer_middle
         && self.pointer_high == other.pointer_high
         && self.reserved == other.reserved
     }
@@ -273,7 +282,7 @@ impl<T> PartialEq for Entry<T> {
 // HandlerFunc
 
 #[cfg(all(
-    any(target_arch="x86", target_arch="x86_64", feature = "abi_x86_interrupt"),
+    any(target_arch="x86", target_arch="x86_64"),
     feature = "abi_x86_interrupt"
 ))]
 pub type HandlerFunc = extern "x86-interrupt" fn(InterruptStackFrame);
@@ -307,10 +316,10 @@ pub struct HandlerFuncWithErrCode(());
     any(target_arch="x86", target_arch="x86_64"),
     feature = "abi_x86_interrupt"
 ))]
-pub type PageFaultHandlerFunc = extern "abi_x86_interrupt" fn(InterruptStackFrame, error_code: PageFaultErrorCode);
+pub type PageFaultHandlerFunc = extern "x86-interrupt" fn(InterruptStackFrame, error_code: PageFaultErrorCode);
 
 #[cfg(not(all(
-    any(target_abi="x86", target_abi="x86_64"),
+    any(target_arch="x86", target_arch="x86_64"),
     feature = "abi_x86_interrupt"
 )))]
 #[derive(Debug, Clone, Copy)]
@@ -337,7 +346,7 @@ pub struct DivergingHandlerFunc(());
     any(target_arch = "x86", target_arch = "x86_64"),
     feature = "abi_x86_interrupt"
 ))]
-pub type DivergingHandlerFuncWithErrCode = extern "x86_64" fn(InterruptStackFrame, error_code: u64) -> !;
+pub type DivergingHandlerFuncWithErrCode = extern "x86-interrupt" fn(InterruptStackFrame, error_code: u64) -> !;
 
 #[cfg(not(all(
     any(target_arch = "x86", target_arch = "x86_64"),
@@ -350,7 +359,7 @@ pub struct DivergingHandlerFuncWithErrCode(());
 // Entry point
 pub type GeneralHandlerFunc = fn(InterruptStackFrame, index: u8, error_code: Option<u64>);
 
-impl<T> GeneralHandlerFunc<T> {
+impl<F> Entry<F> {
     pub const fn missing() -> Self {
         Entry {
             pointer_low: 0,
@@ -369,9 +378,10 @@ impl<T> GeneralHandlerFunc<T> {
     // 4. Pivilege level Ring0 
     // 4. No IST is configured (existing stack will be used).. 
 
-    #[cfg(all(feature = "instructions", target_arch = "x86_64"))]
+    // unsafe: `addr` has to be the address of a handler with the right
+    // signature for the entry.
     #[inline]
-    pub unsafe fn set_handler_func(&mut self, addr: VirtAddr) -> &mut EntryOptions {
+    pub unsafe fn set_handler_addr(&mut self, addr: VirtAddr) -> &mut EntryOptions {
         let addr = addr.as_u64();
 
         self.pointer_low = addr as u16;
@@ -381,7 +391,7 @@ impl<T> GeneralHandlerFunc<T> {
         self.options = EntryOptions::minimal();
 
         unsafe {
-            self.options.set_code_selector(CS::get_reg())
+            self.options.set_code_selector(CS::get_reg());
         }
         self.options.set_present(true);
         &mut self.options
@@ -389,7 +399,7 @@ impl<T> GeneralHandlerFunc<T> {
 
     #[inline]
     pub fn handler_addr(&self) -> VirtAddr {
-        let addr = self.pointer_low as u64 | ((self.pointer_high as u64) >> 32 ) | ((self.pointer_middle as u64) >> 16 );
+        let addr = self.pointer_low as u64 | ((self.pointer_high as u64) << 32 ) | ((self.pointer_middle as u64) << 16 );
         VirtAddr::new_truncate(addr)
     }
 
@@ -414,15 +424,15 @@ impl <F: HandlerFuncType> Entry<F> {
 macro_rules! impl_handler_func_type {
     ($f: ty) => {
         #[cfg(all(
-            any(target_arch="x86" or target_arch="x86_64"),
-            feature = "abi_x64_interrupt"
+            any(target_arch="x86", target_arch="x86_64"),
+            feature = "abi_x86_interrupt"
         ))]
         unsafe impl HandlerFuncType for $f {
             #[inline]
             fn to_virt_addr(self) -> VirtAddr {
                 #[cfg_attr(
-                    any( target_pointer_width: "32", target_pointer_width = "64" ),
-                    allow(clippy::fn_to_numeric_casr)
+                    any( target_pointer_width = "32", target_pointer_width = "64" ),
+                    allow(clippy::fn_to_numeric_cast)
                 )]
                 VirtAddr::new(self as u64)
             }
@@ -440,7 +450,7 @@ impl_handler_func_type!(DivergingHandlerFuncWithErrCode);
 
 // represents the 4 non-offset bytes for an IDT entry
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct EntryOptions{
     cs: SegmentSelector,
     bits: u16
@@ -451,7 +461,7 @@ impl fmt::Debug for  EntryOptions {
         f.debug_struct("EntryOptions")
         .field("code_selector", &self.cs)
         .field("stack_index", &self.stack_index())
-        .field("tyoe", &format_args!("{:04b}", self.bits.get_bits(8..12)))
+        .field("type", &format_args!("{:04b}", self.bits.get_bits(8..12)))
         .field("privilege_level", &self.privilege_level())
         .field("present", &self.present())
         .finish()
@@ -526,11 +536,11 @@ impl EntryOptions {
 pub struct InterruptStackFrameValue {
     pub instruction_pointer: VirtAddr,
     pub code_segment: SegmentSelector,
-    _reserved1: [u8; 8],
+    _reserved1: [u8; 6],
     pub cpu_flags: RFlags,
     pub stack_pointer: VirtAddr,
     pub stack_segment: SegmentSelector,
-    _reserved2: [u8; 8]
+    _reserved2: [u8; 6]
 }
 
 
@@ -565,8 +575,8 @@ impl InterruptStackFrameValue {
                 rflags = in(reg) self.cpu_flags.bits(),
                 new_instruction_pointer = in(reg) self.instruction_pointer.as_u64(),
                 new_stack_pointer = in(reg) self.stack_pointer.as_u64(),
-                code_segment = in(reg) self.code_segment.0,
-                stack_segment = in(reg) self.stack_segment.0,
+                code_segment = in(reg) u64::from(self.code_segment.0),
+                stack_segment = in(reg) u64::from(self.stack_segment.0),
                 options(noreturn)
             )
         }
@@ -583,7 +593,7 @@ impl fmt::Debug for InterruptStackFrameValue {
         s.field("cpu_flags", &self.cpu_flags);
         s.field("stack_pointer", &self.stack_pointer);
         s.field("stack_segment", &self.stack_segment);
-        s.finish();
+        s.finish()
     }
 
 }
@@ -616,8 +626,11 @@ impl InterruptStackFrame {
 
 
     // gives us the mutable access to the contents of the interrupt stack frame
-    pub unsafe fn as_mut(&mut self) -> Volatile<&mut InterruptStackFrameValue> {
-        Volatile::new(&mut self.0)
+    //
+    // unsafe: changing the frame changes where and how the cpu returns.
+    pub unsafe fn as_mut(&mut self) -> &mut Volatile<InterruptStackFrameValue> {
+        // Volatile is repr(transparent)
+        unsafe { &mut *(&mut self.0 as *mut InterruptStackFrameValue as *mut Volatile<InterruptStackFrameValue>) }
     }
 
 }
@@ -646,6 +659,8 @@ impl fmt::Debug for InterruptStackFrame {
 // Page fault error codes
 bitflags! {
 
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
     pub struct PageFaultErrorCode: u64 {
 
         const PROTECTION_VIOLATION = 1;
@@ -719,6 +734,27 @@ impl SelectorErrorCode {
         }
     }
 
+    // index of the selector(or gate) inside the descriptor table, bits 3..16
+    pub fn index(&self) -> u64 {
+        self.flags.get_bits(3..16)
+    }
+
+    // an error code of 0 means the fault wasn't caused by a selector at all.
+    pub fn is_null(&self) -> bool {
+        self.flags == 0
+    }
+
+}
+
+impl fmt::Debug for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelectorErrorCode")
+            .field("external", &self.external())
+            .field("descriptor_table", &self.descriptor_table())
+            .field("index", &self.index())
+            .field("is_null", &self.is_null())
+            .finish()
+    }
 }
 
 #[derive( Debug, Clone, Copy )]
@@ -726,7 +762,7 @@ pub enum DescriptorTable {
     GDT, IDT, LDT
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum ExceptionVector {
     Division = 0x00,
@@ -735,7 +771,7 @@ pub enum ExceptionVector {
     Breakpoint = 0x03,
     Overflow = 0x04,
     BoundRange = 0x05,
-    InvalidOpcode = 0x05,
+    InvalidOpcode = 0x06,
     DeviceNotAvailable = 0x07,
     Double = 0x08,
     InvalidTss = 0x0A,
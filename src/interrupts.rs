@@ -1,35 +1,351 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use lazy_static::lazy_static;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use spin::Mutex;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
-use crate::{gdt, println};
+use crate::{addr::VirtualAddr, address_space, apic, cow, gdt, keyboard, page_fault, println, rtc, serial_println, stack, time, tlb, vma};
+use crate::pic::{ChainedPics, PIC_1_OFFSET, PIC_2_OFFSET};
+use crate::idt::{ExceptionVector, SelectorErrorCode};
 
 
 lazy_static!{
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
+        idt.divide_error.set_handler_fn(divide_error_handler);
+        idt.debug.set_handler_fn(debug_handler);
+        unsafe {
+            idt.non_maskable_interrupt.set_handler_fn(non_maskable_interrupt_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+        }
         idt.breakpoint.set_handler_fn(breakpoint_handler);
-        // idt.double_fault.set_handler_fn(double_fault_handler);
-        // unsafe {
-        //     idt.double_fault.set_handler_fn(double_fault_handler)
-        //         .set_stack_index(gdt::DOUBLE_FAULT_1ST_INDEX)
-        // }
+        idt.overflow.set_handler_fn(overflow_handler);
+        idt.bound_range_exceeded.set_handler_fn(bound_range_exceeded_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.device_not_available.set_handler_fn(device_not_available_handler);
+        unsafe {
+            idt.double_fault.set_handler_fn(double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_1ST_INDEX);
+        }
+        idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+        idt.segment_not_present.set_handler_fn(segment_not_present_handler);
+        idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
+        idt.general_protection_fault.set_handler_fn(general_protection_fault_handler);
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.x87_floating_point.set_handler_fn(x87_floating_point_handler);
+        idt.alignment_check.set_handler_fn(alignment_chk_handler);
+        unsafe {
+            idt.machine_check.set_handler_fn(machine_chk_handler)
+                .set_stack_index(gdt::MACHINE_CHECK_IST_INDEX);
+        }
+        idt.simd_floating_point.set_handler_fn(simd_floating_point_handler);
+        idt.virtualization.set_handler_fn(virtualization_handler);
+        idt.cp_protection_exception.set_handler_fn(cp_protection_handler);
+        idt.hv_injection_exception.set_handler_fn(hv_injection_handler);
+        idt.vmm_communication_exception.set_handler_fn(vmm_communication_handler);
+        idt.security_exception.set_handler_fn(security_exception_handler);
+
+        // hardware interrupts
+        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Rtc.as_usize()].set_handler_fn(rtc_interrupt_handler);
+        idt[InterruptIndex::ApicTimer.as_usize()].set_handler_fn(apic_timer_interrupt_handler);
+        idt[InterruptIndex::ApicError.as_usize()].set_handler_fn(apic_error_interrupt_handler);
+        idt[InterruptIndex::TlbShootdown.as_usize()].set_handler_fn(tlb_shootdown_interrupt_handler);
+        idt[InterruptIndex::ApicSpurious.as_usize()].set_handler_fn(apic_spurious_interrupt_handler);
         idt
     };
 
 }
 
+pub static PICS: Mutex<ChainedPics> = Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+
+// Vectors of the hardware interrupts, the PIC lines start right after the exceptions.
+// With the APIC enabled the ISA lines keep their vectors (routed by the I/O APIC),
+// the local APIC's own interrupts come after the PIC range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard,
+    Rtc = PIC_2_OFFSET,
+    ApicTimer = PIC_2_OFFSET + 8,
+    ApicError,
+    // IPI from another cpu, see `tlb::shootdown`
+    TlbShootdown,
+    ApicSpurious = 0xFF,
+}
+
+impl InterruptIndex {
+
+    #[inline]
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    #[inline]
+    pub fn as_usize(self) -> usize {
+        usize::from(self.as_u8())
+    }
+
+    // IRQ line on the PICs (only meaningful for the ISA interrupts)
+    #[inline]
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+}
+
+
+static APIC_TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+
+// number of local APIC timer interrupts received on this cpu
+pub fn apic_timer_ticks() -> u64 {
+    APIC_TIMER_TICKS.load(Ordering::Relaxed)
+}
+
+// Lets the ISA interrupt `index` through, on the PICs or the I/O APIC,
+// whichever is delivering interrupts right now.
+//
+// unsafe: the device behind the line has to be ready for its interrupts.
+pub unsafe fn enable_irq(index: InterruptIndex) {
+    if apic::is_enabled() {
+        let apic_id = x86_64::instructions::interrupts::without_interrupts(|| {
+            apic::LOCAL_APIC.lock().as_ref().map_or(0, |local_apic| local_apic.id())
+        });
+        if let Some(io_apic) = apic::IO_APIC.lock().as_mut() {
+            io_apic.route_isa_irq(index.irq(), index.as_u8(), apic_id);
+        }
+    } else {
+        unsafe { PICS.lock().unmask(index.irq()); }
+    }
+}
+
+// acknowledges a hardware interrupt on whichever controller delivered it.
+//
+// unsafe: must only be called once, at the end of the handler for `index`.
+pub unsafe fn end_of_interrupt(index: InterruptIndex) {
+    unsafe {
+        if apic::is_enabled() {
+            apic::end_of_interrupt();
+        } else {
+            PICS.lock().notify_end_of_interrupt(index.as_u8());
+        }
+    }
+}
+
 pub fn init_idt() {
     IDT.load();
 }
 
 
+// prints the exception on the VGA screen and on serial, so we still get
+// the report when qemu is running with `-display none`.
+macro_rules! report {
+    ($vector:expr, $stack_frame:expr) => {{
+        let vector: ExceptionVector = $vector;
+        println!("EXCEPTION: {:?} (vector {:#04x})\n{:#?}", vector, vector as u8, $stack_frame);
+        serial_println!("EXCEPTION: {:?} (vector {:#04x})\n{:#?}", vector, vector as u8, $stack_frame);
+    }};
+    ($vector:expr, $stack_frame:expr, $error_code:expr) => {{
+        let vector: ExceptionVector = $vector;
+        println!("EXCEPTION: {:?} (vector {:#04x})\nerror code: {:#?}\n{:#?}", vector, vector as u8, $error_code, $stack_frame);
+        serial_println!("EXCEPTION: {:?} (vector {:#04x})\nerror code: {:#?}\n{:#?}", vector, vector as u8, $error_code, $stack_frame);
+    }};
+}
+
+
+// traps, execution continues after the instruction that raised them.
+
 extern "x86-interrupt" fn breakpoint_handler( stack_frame: InterruptStackFrame ) {
-    println!("Exception: Breakpoint\n{:?}", stack_frame);
+    report!(ExceptionVector::Breakpoint, stack_frame);
+}
+
+extern "x86-interrupt" fn debug_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::Debug, stack_frame);
+}
+
+extern "x86-interrupt" fn overflow_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::Overflow, stack_frame);
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::NonMaskableInterrupt, stack_frame);
+}
+
+
+// faults, returning would re-execute the faulting instruction forever,
+// so all of them report and then panic.
+
+extern "x86-interrupt" fn divide_error_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::Division, stack_frame);
+    panic!("EXCEPTION: DIVIDE ERROR");
+}
+
+extern "x86-interrupt" fn bound_range_exceeded_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::BoundRange, stack_frame);
+    panic!("EXCEPTION: BOUND RANGE EXCEEDED");
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::InvalidOpcode, stack_frame);
+    panic!("EXCEPTION: INVALID OPCODE");
+}
+
+extern "x86-interrupt" fn device_not_available_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::DeviceNotAvailable, stack_frame);
+    panic!("EXCEPTION: DEVICE NOT AVAILABLE");
+}
+
+extern "x86-interrupt" fn double_fault_handler( stack_frame: InterruptStackFrame, error_code: u64 ) -> ! {
+    // error code of a double fault is always zero
+    report!(ExceptionVector::Double, stack_frame, error_code);
+
+    // running into a guard page can't be handled on the same stack, the
+    // page fault turns into a double fault
+    let addr = VirtualAddr::new(Cr2::read().as_u64());
+    if stack::is_guard_page(addr) {
+        panic!("EXCEPTION: DOUBLE FAULT (kernel stack overflow at {:#x})", addr.as_u64());
+    }
+    panic!("EXCEPTION: DOUBLE FAULT");
+}
+
+extern "x86-interrupt" fn invalid_tss_handler( stack_frame: InterruptStackFrame, error_code: u64 ) {
+    report!(ExceptionVector::InvalidTss, stack_frame, SelectorErrorCode::new_truncate(error_code));
+    panic!("EXCEPTION: INVALID TSS");
+}
+
+extern "x86-interrupt" fn segment_not_present_handler( stack_frame: InterruptStackFrame, error_code: u64 ) {
+    report!(ExceptionVector::SementNotpresent, stack_frame, SelectorErrorCode::new_truncate(error_code));
+    panic!("EXCEPTION: SEGMENT NOT PRESENT");
+}
+
+extern "x86-interrupt" fn stack_segment_fault_handler( stack_frame: InterruptStackFrame, error_code: u64 ) {
+    report!(ExceptionVector::Stack, stack_frame, SelectorErrorCode::new_truncate(error_code));
+    panic!("EXCEPTION: STACK SEGMENT FAULT");
+}
+
+extern "x86-interrupt" fn general_protection_fault_handler( stack_frame: InterruptStackFrame, error_code: u64 ) {
+    report!(ExceptionVector::GeneralProtection, stack_frame, SelectorErrorCode::new_truncate(error_code));
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT");
+}
+
+extern "x86-interrupt" fn page_fault_handler( stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode ) {
+    // CR2 holds the virtual address whose access caused the fault
+    let accessed = VirtualAddr::new(Cr2::read().as_u64());
+    let error_code = crate::idt::PageFaultErrorCode::from_bits_retain(error_code.bits());
+
+    // a kernel mapping newer than the address space, the first touch of a
+    // demand paged page or a write to a copy-on-write page, it's taken care
+    // of so just return
+    if address_space::sync_kernel_entry(accessed) || vma::handle_page_fault(accessed, error_code) || cow::handle_page_fault(accessed, error_code) {
+        return;
+    }
+
+    page_fault::report(accessed, error_code, &stack_frame);
+    panic!("EXCEPTION: PAGE FAULT");
+}
+
+extern "x86-interrupt" fn x87_floating_point_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::X87FloatingPoint, stack_frame);
+    panic!("EXCEPTION: x87 FLOATING POINT");
+}
+
+extern "x86-interrupt" fn alignment_chk_handler( stack_frame: InterruptStackFrame, error_code: u64 ) {
+    report!(ExceptionVector::AlignmentChack, stack_frame, error_code);
+    panic!("EXCEPTION: ALIGNMENT CHECK");
+}
+
+extern "x86-interrupt" fn machine_chk_handler( stack_frame: InterruptStackFrame ) -> ! {
+    report!(ExceptionVector::MachineCheck, stack_frame);
+    panic!("EXCEPTION: MACHINE CHECK");
+}
+
+extern "x86-interrupt" fn simd_floating_point_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::SimdFloatingPoint, stack_frame);
+    panic!("EXCEPTION: SIMD FLOATING POINT");
+}
+
+extern "x86-interrupt" fn virtualization_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::Virtualization, stack_frame);
+    panic!("EXCEPTION: VIRTUALIZATION");
+}
+
+extern "x86-interrupt" fn cp_protection_handler( stack_frame: InterruptStackFrame, error_code: u64 ) {
+    report!(ExceptionVector::ControlProtection, stack_frame, error_code);
+    panic!("EXCEPTION: CONTROL PROTECTION");
+}
+
+extern "x86-interrupt" fn hv_injection_handler( stack_frame: InterruptStackFrame ) {
+    report!(ExceptionVector::HypervisorInjection, stack_frame);
+    panic!("EXCEPTION: HYPERVISOR INJECTION");
+}
+
+extern "x86-interrupt" fn vmm_communication_handler( stack_frame: InterruptStackFrame, error_code: u64 ) {
+    report!(ExceptionVector::VmmCommunication, stack_frame, error_code);
+    panic!("EXCEPTION: VMM COMMUNICATION");
+}
+
+extern "x86-interrupt" fn security_exception_handler( stack_frame: InterruptStackFrame, error_code: u64 ) {
+    report!(ExceptionVector::Security, stack_frame, error_code);
+    panic!("EXCEPTION: SECURITY");
+}
+
+// hardware interrupts, every one of them (except spurious ones) has to send an EOI.
+
+extern "x86-interrupt" fn timer_interrupt_handler( _stack_frame: InterruptStackFrame ) {
+    time::tick();
+
+    unsafe {
+        end_of_interrupt(InterruptIndex::Timer);
+    }
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler( _stack_frame: InterruptStackFrame ) {
+    keyboard::handle_interrupt();
+
+    unsafe {
+        end_of_interrupt(InterruptIndex::Keyboard);
+    }
+}
+
+extern "x86-interrupt" fn rtc_interrupt_handler( _stack_frame: InterruptStackFrame ) {
+    rtc::handle_interrupt();
+
+    unsafe {
+        end_of_interrupt(InterruptIndex::Rtc);
+    }
+}
+
+extern "x86-interrupt" fn apic_timer_interrupt_handler( _stack_frame: InterruptStackFrame ) {
+    APIC_TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+
+    unsafe {
+        apic::end_of_interrupt();
+    }
+}
+
+extern "x86-interrupt" fn apic_error_interrupt_handler( _stack_frame: InterruptStackFrame ) {
+    let status = apic::LOCAL_APIC.lock().as_mut().map(|local_apic| local_apic.error_status());
+    serial_println!("APIC ERROR: status {:?}", status);
+
+    unsafe {
+        apic::end_of_interrupt();
+    }
+}
+
+extern "x86-interrupt" fn tlb_shootdown_interrupt_handler( _stack_frame: InterruptStackFrame ) {
+    tlb::handle_shootdown();
+
+    unsafe {
+        apic::end_of_interrupt();
+    }
 }
 
-// extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
-//     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame)
-// }
+// the local apic raises these when an interrupt vanished before it could be
+// delivered, they must not be acknowledged.
+extern "x86-interrupt" fn apic_spurious_interrupt_handler( _stack_frame: InterruptStackFrame ) {}
 
 #[test_case]
 fn _test_breakpoint_exception() {
@@ -0,0 +1,393 @@
+// PS/2 keyboard driver.
+// The IRQ 1 handler reads the scancode from port 0x60, decodes it (scancode
+// set 1, what the controller translates to by default) and queues the
+// resulting `KeyEvent`. Kernel code either polls the queue or awaits the
+// next event.
+
+use core::{future::Future, pin::Pin, sync::atomic::{AtomicBool, Ordering}, task::{Context, Poll, Waker}};
+
+use bitflags::bitflags;
+use spin::Mutex;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+use crate::{print, ring_buffer::RingBuffer};
+
+const DATA_PORT: u16 = 0x60;
+
+// prefix of the extended (second half of the keyboard) scancodes
+const EXTENDED_PREFIX: u8 = 0xE0;
+
+// prefix of the pause key, which sends 6 bytes and no break code
+const PAUSE_PREFIX: u8 = 0xE1;
+
+// bit 7 set means the key was released
+const BREAK_BIT: u8 = 0x80;
+
+const QUEUE_SIZE: usize = 128;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCode {
+    Escape, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    Minus, Equals, Backspace, Tab,
+    Q, W, E, R, T, Y, U, I, O, P, LeftBracket, RightBracket, Enter,
+    LeftControl, A, S, D, F, G, H, J, K, L, SemiColon, Quote, BackTick,
+    LeftShift, Backslash, Z, X, C, V, B, N, M, Comma, Period, Slash, RightShift,
+    LeftAlt, Spacebar, CapsLock,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    NumLock, ScrollLock,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadMultiply, NumpadSubtract, NumpadAdd, NumpadPeriod,
+
+    // extended (E0 prefixed) keys
+    NumpadEnter, RightControl, NumpadDivide, RightAlt, PrintScreen,
+    Home, ArrowUp, PageUp, ArrowLeft, ArrowRight, End, ArrowDown, PageDown,
+    Insert, Delete, LeftGui, RightGui, Apps,
+    Pause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    Down,
+    Up,
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Modifiers: u8 {
+        const LEFT_SHIFT = 1;
+        const RIGHT_SHIFT = 1 << 1;
+        const LEFT_CONTROL = 1 << 2;
+        const RIGHT_CONTROL = 1 << 3;
+        const LEFT_ALT = 1 << 4;
+        const RIGHT_ALT = 1 << 5;
+        const CAPS_LOCK = 1 << 6;
+        const NUM_LOCK = 1 << 7;
+    }
+}
+
+impl Modifiers {
+
+    #[inline]
+    pub fn shift(self) -> bool {
+        self.intersects(Modifiers::LEFT_SHIFT | Modifiers::RIGHT_SHIFT)
+    }
+
+    #[inline]
+    pub fn control(self) -> bool {
+        self.intersects(Modifiers::LEFT_CONTROL | Modifiers::RIGHT_CONTROL)
+    }
+
+    #[inline]
+    pub fn alt(self) -> bool {
+        self.intersects(Modifiers::LEFT_ALT | Modifiers::RIGHT_ALT)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+
+    // modifiers after this event was applied
+    pub modifiers: Modifiers,
+
+    // the character typed, only set for key presses
+    pub character: Option<char>,
+}
+
+
+// scancode set 1 make codes (without prefix), indexed by scancode
+const SET1: [Option<KeyCode>; 0x59] = {
+    use KeyCode::*;
+    let mut table = [None; 0x59];
+    let keys = [
+        Escape, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, Minus, Equals, Backspace,
+        Tab, Q, W, E, R, T, Y, U, I, O, P, LeftBracket, RightBracket, Enter,
+        LeftControl, A, S, D, F, G, H, J, K, L, SemiColon, Quote, BackTick,
+        LeftShift, Backslash, Z, X, C, V, B, N, M, Comma, Period, Slash, RightShift,
+        NumpadMultiply, LeftAlt, Spacebar, CapsLock,
+        F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, NumLock, ScrollLock,
+        Numpad7, Numpad8, Numpad9, NumpadSubtract, Numpad4, Numpad5, Numpad6, NumpadAdd,
+        Numpad1, Numpad2, Numpad3, Numpad0, NumpadPeriod,
+    ];
+    // 0x01..=0x53 are contiguous
+    let mut i = 0;
+    while i < keys.len() {
+        table[i + 1] = Some(keys[i]);
+        i += 1;
+    }
+    table[0x57] = Some(F11);
+    table[0x58] = Some(F12);
+    table
+};
+
+// E0 prefixed make codes
+fn extended_key(scancode: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+    match scancode {
+        0x1C => Some(NumpadEnter),
+        0x1D => Some(RightControl),
+        0x35 => Some(NumpadDivide),
+        0x37 => Some(PrintScreen),
+        0x38 => Some(RightAlt),
+        0x47 => Some(Home),
+        0x48 => Some(ArrowUp),
+        0x49 => Some(PageUp),
+        0x4B => Some(ArrowLeft),
+        0x4D => Some(ArrowRight),
+        0x4F => Some(End),
+        0x50 => Some(ArrowDown),
+        0x51 => Some(PageDown),
+        0x52 => Some(Insert),
+        0x53 => Some(Delete),
+        0x5B => Some(LeftGui),
+        0x5C => Some(RightGui),
+        0x5D => Some(Apps),
+        // 0x2A / 0x36 are fake shifts sent around print screen and friends
+        _ => None,
+    }
+}
+
+// character for a key on a US layout
+fn character(code: KeyCode, modifiers: Modifiers) -> Option<char> {
+    use KeyCode::*;
+
+    let shift = modifiers.shift();
+    let letter = |lower: char| {
+        // caps lock only affects letters, and shift undoes it
+        if shift != modifiers.contains(Modifiers::CAPS_LOCK) {
+            Some(lower.to_ascii_uppercase())
+        } else {
+            Some(lower)
+        }
+    };
+    let symbol = |normal: char, shifted: char| Some(if shift { shifted } else { normal });
+    let numpad = |digit: char| {
+        if modifiers.contains(Modifiers::NUM_LOCK) { Some(digit) } else { None }
+    };
+
+    match code {
+        A => letter('a'), B => letter('b'), C => letter('c'), D => letter('d'),
+        E => letter('e'), F => letter('f'), G => letter('g'), H => letter('h'),
+        I => letter('i'), J => letter('j'), K => letter('k'), L => letter('l'),
+        M => letter('m'), N => letter('n'), O => letter('o'), P => letter('p'),
+        Q => letter('q'), R => letter('r'), S => letter('s'), T => letter('t'),
+        U => letter('u'), V => letter('v'), W => letter('w'), X => letter('x'),
+        Y => letter('y'), Z => letter('z'),
+
+        Key1 => symbol('1', '!'), Key2 => symbol('2', '@'), Key3 => symbol('3', '#'),
+        Key4 => symbol('4', '$'), Key5 => symbol('5', '%'), Key6 => symbol('6', '^'),
+        Key7 => symbol('7', '&'), Key8 => symbol('8', '*'), Key9 => symbol('9', '('),
+        Key0 => symbol('0', ')'),
+        Minus => symbol('-', '_'), Equals => symbol('=', '+'),
+        LeftBracket => symbol('[', '{'), RightBracket => symbol(']', '}'),
+        SemiColon => symbol(';', ':'), Quote => symbol('\'', '"'),
+        BackTick => symbol('`', '~'), Backslash => symbol('\\', '|'),
+        Comma => symbol(',', '<'), Period => symbol('.', '>'), Slash => symbol('/', '?'),
+
+        Spacebar => Some(' '),
+        Tab => Some('\t'),
+        Enter | NumpadEnter => Some('\n'),
+        Backspace => Some('\x08'),
+        Escape => Some('\x1b'),
+        Delete => Some('\x7f'),
+
+        Numpad0 => numpad('0'), Numpad1 => numpad('1'), Numpad2 => numpad('2'),
+        Numpad3 => numpad('3'), Numpad4 => numpad('4'), Numpad5 => numpad('5'),
+        Numpad6 => numpad('6'), Numpad7 => numpad('7'), Numpad8 => numpad('8'),
+        Numpad9 => numpad('9'), NumpadPeriod => numpad('.'),
+        NumpadMultiply => Some('*'), NumpadSubtract => Some('-'),
+        NumpadAdd => Some('+'), NumpadDivide => Some('/'),
+
+        _ => None,
+    }
+}
+
+
+// Turns the raw byte stream into key events, keeping track of prefixes
+// and modifier / lock state.
+#[derive(Debug)]
+pub struct Decoder {
+    extended: bool,
+
+    // bytes of the pause sequence still to swallow
+    skip: u8,
+    modifiers: Modifiers,
+}
+
+impl Decoder {
+
+    pub const fn new() -> Self {
+        Decoder { extended: false, skip: 0, modifiers: Modifiers::empty() }
+    }
+
+    #[inline]
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+
+        match byte {
+            EXTENDED_PREFIX => {
+                self.extended = true;
+                return None;
+            }
+            PAUSE_PREFIX => {
+                // E1 1D 45 E1 9D C5, report it once as a press
+                self.skip = 5;
+                return Some(self.event(KeyCode::Pause, KeyState::Down));
+            }
+            _ => {}
+        }
+
+        let extended = core::mem::replace(&mut self.extended, false);
+        let state = if byte & BREAK_BIT != 0 { KeyState::Up } else { KeyState::Down };
+        let scancode = byte & !BREAK_BIT;
+
+        let code = if extended {
+            extended_key(scancode)
+        } else {
+            SET1.get(usize::from(scancode)).copied().flatten()
+        }?;
+
+        self.update_modifiers(code, state);
+        Some(self.event(code, state))
+    }
+
+    fn update_modifiers(&mut self, code: KeyCode, state: KeyState) {
+        let down = state == KeyState::Down;
+        match code {
+            KeyCode::LeftShift => self.modifiers.set(Modifiers::LEFT_SHIFT, down),
+            KeyCode::RightShift => self.modifiers.set(Modifiers::RIGHT_SHIFT, down),
+            KeyCode::LeftControl => self.modifiers.set(Modifiers::LEFT_CONTROL, down),
+            KeyCode::RightControl => self.modifiers.set(Modifiers::RIGHT_CONTROL, down),
+            KeyCode::LeftAlt => self.modifiers.set(Modifiers::LEFT_ALT, down),
+            KeyCode::RightAlt => self.modifiers.set(Modifiers::RIGHT_ALT, down),
+            // locks toggle on press, typematic repeats don't send a release in between
+            KeyCode::CapsLock if down => self.modifiers.toggle(Modifiers::CAPS_LOCK),
+            KeyCode::NumLock if down => self.modifiers.toggle(Modifiers::NUM_LOCK),
+            _ => {}
+        }
+    }
+
+    fn event(&self, code: KeyCode, state: KeyState) -> KeyEvent {
+        let character = match state {
+            KeyState::Down => character(code, self.modifiers),
+            KeyState::Up => None,
+        };
+        KeyEvent { code, state, modifiers: self.modifiers, character }
+    }
+}
+
+
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+static EVENTS: RingBuffer<KeyEvent, QUEUE_SIZE> = RingBuffer::new();
+static WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+static ECHO: AtomicBool = AtomicBool::new(false);
+
+// echo typed characters on the VGA screen
+pub fn set_echo(echo: bool) {
+    ECHO.store(echo, Ordering::Relaxed);
+}
+
+// Called by the IRQ 1 handler.
+pub fn handle_interrupt() {
+    let mut port: Port<u8> = Port::new(DATA_PORT);
+    let scancode = unsafe { port.read() };
+
+    if let Some(event) = DECODER.lock().add_byte(scancode) {
+        if ECHO.load(Ordering::Relaxed) {
+            if let Some(character) = event.character {
+                print!("{}", character);
+            }
+        }
+
+        // events are dropped when nobody reads them
+        let _ = EVENTS.push(event);
+
+        if let Some(waker) = WAKER.lock().take() {
+            waker.wake();
+        }
+    }
+}
+
+// next queued event, if there is one
+pub fn poll_event() -> Option<KeyEvent> {
+    EVENTS.pop()
+}
+
+// resolves with the next key event
+pub fn next_event() -> NextKeyEvent {
+    NextKeyEvent
+}
+
+pub struct NextKeyEvent;
+
+impl Future for NextKeyEvent {
+    type Output = KeyEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<KeyEvent> {
+        if let Some(event) = EVENTS.pop() {
+            return Poll::Ready(event);
+        }
+
+        // the keyboard handler takes the waker, so it's only ever locked
+        // here with interrupts disabled
+        without_interrupts(|| *WAKER.lock() = Some(cx.waker().clone()));
+
+        // an event may have arrived before the waker was registered
+        match EVENTS.pop() {
+            Some(event) => {
+                without_interrupts(|| WAKER.lock().take());
+                Poll::Ready(event)
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+
+#[test_case]
+fn test_decode_letters_with_shift_and_caps_lock() {
+    let mut decoder = Decoder::new();
+
+    let a = decoder.add_byte(0x1E).unwrap();
+    assert_eq!(a.code, KeyCode::A);
+    assert_eq!(a.state, KeyState::Down);
+    assert_eq!(a.character, Some('a'));
+    assert_eq!(decoder.add_byte(0x9E).unwrap().state, KeyState::Up);
+
+    decoder.add_byte(0x2A);
+    assert_eq!(decoder.add_byte(0x1E).unwrap().character, Some('A'));
+    assert_eq!(decoder.add_byte(0x02).unwrap().character, Some('!'));
+    decoder.add_byte(0xAA);
+
+    decoder.add_byte(0x3A);
+    decoder.add_byte(0xBA);
+    assert!(decoder.modifiers().contains(Modifiers::CAPS_LOCK));
+    assert_eq!(decoder.add_byte(0x1E).unwrap().character, Some('A'));
+    assert_eq!(decoder.add_byte(0x02).unwrap().character, Some('1'));
+}
+
+#[test_case]
+fn test_decode_extended_keys() {
+    let mut decoder = Decoder::new();
+
+    assert_eq!(decoder.add_byte(0xE0), None);
+    let up = decoder.add_byte(0x48).unwrap();
+    assert_eq!(up.code, KeyCode::ArrowUp);
+    assert_eq!(up.character, None);
+
+    decoder.add_byte(0xE0);
+    decoder.add_byte(0x1D);
+    assert!(decoder.modifiers().control());
+
+    // same scancode without the prefix is the numpad with num lock off
+    assert_eq!(decoder.add_byte(0x48).unwrap().code, KeyCode::Numpad8);
+}
@@ -3,7 +3,11 @@
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
-// #![feature(abi_x86_interrupt)]
+#![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![feature(step_trait)]
+
+extern crate alloc;
 
 use core::panic::PanicInfo;
 
@@ -15,6 +19,30 @@ pub mod idt;
 pub mod interrupts;
 pub mod gdt;
 pub mod segmentation;
+pub mod addr;
+pub mod meme_encrypt;
+pub mod structures;
+pub mod memory;
+pub mod pmm;
+pub mod page_fault;
+pub mod pic;
+pub mod apic;
+pub mod cpuid;
+pub mod model_specific;
+pub mod ring_buffer;
+pub mod keyboard;
+pub mod pit;
+pub mod time;
+pub mod hpet;
+pub mod tsc;
+pub mod rtc;
+pub mod allocator;
+pub mod slab;
+pub mod stack;
+pub mod vma;
+pub mod cow;
+pub mod address_space;
+pub mod tlb;
 
 
 // ---------------------------------- Qemu ---------------------------------- 
@@ -67,7 +95,7 @@ pub fn  test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failed);
-    loop {}
+    hlt_loop();
 }
 
 // #[test_case]
@@ -89,11 +117,15 @@ pub fn  test_panic_handler(info: &PanicInfo) -> ! {
 // }
 
 #[cfg(test)]
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() -> ! {
+bootloader::entry_point!(test_kernel_main);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static bootloader::BootInfo) -> ! {
     init();
+    unsafe { memory::init(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
     test_main();
-    loop {}
+    hlt_loop();
 }
 
 #[cfg(test)]
@@ -102,9 +134,29 @@ fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
 
-// IDT 
+// GDT (with the TSS holding our IST stacks) has to be loaded before the IDT,
+// the double fault entry points into it.
 pub fn init() {
+    gdt::init();
     interrupts::init_idt();
+
+    let mut pics = interrupts::PICS.lock();
+    unsafe {
+        pics.initialize();
+        pics.unmask(interrupts::InterruptIndex::Timer.irq());
+        pics.unmask(interrupts::InterruptIndex::Keyboard.irq());
+    }
+    drop(pics);
+
+    time::init(time::DEFAULT_FREQUENCY);
+    x86_64::instructions::interrupts::enable();
+}
+
+// halts the cpu until the next interrupt instead of spinning.
+pub fn hlt_loop() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
 }
 
 
@@ -112,7 +164,7 @@ pub fn init() {
 // Descriptor Table pointer
 
 #[derive(Debug, Clone, Copy)]
-#[repr(C)]
+#[repr(C, packed(2))]
 pub struct DescriptorTablePointer {
     pub limit: u16,
     pub base: VirtAddr
@@ -3,30 +3,21 @@
 #![feature(custom_test_frameworks)]
 #![test_runner(rustyos::test_runner)]
 #![reexport_test_harness_main = "test_main"]
-#![feature(abi_x86_interrupt)]
 
 #[cfg(test)]
 use core::panic::PanicInfo;
 
-pub mod vga_buffer;
-pub mod serial;
-pub mod idt;
-pub mod interrupts;
-pub mod ist;
-pub mod segmentation;
-pub mod model_specific;
-pub mod addr;
-pub mod gdt;
-pub mod rflags;
-pub mod meme_encrypt;
-pub mod structures;
+use bootloader::{entry_point, BootInfo};
+
+use rustyos::println;
 
 
 static HELLO: &[u8] = b"                                  It'sMoNdAy OS                                                                                                                  ";
 
 
-#[unsafe(no_mangle)]
-pub extern "C" fn _start() {
+entry_point!(kernel_main);
+
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let vga_buffer = 0xb8000 as *mut u8;
     for ( i, &byte ) in HELLO.iter().enumerate() {
         unsafe {
@@ -36,6 +27,13 @@ pub extern "C" fn _start() {
     }
 
     rustyos::init();
+    rustyos::keyboard::set_echo(true);
+    unsafe { rustyos::memory::init(boot_info) };
+    rustyos::allocator::init_heap().expect("heap initialization failed");
+
+    if let Some(frame_allocator) = rustyos::memory::FRAME_ALLOCATOR.lock().as_ref() {
+        println!("{:?}", frame_allocator.stats());
+    }
 
     println!("Hello It'sMoNdAy. How's your day going??");
 
@@ -62,7 +60,7 @@ pub extern "C" fn _start() {
     test_main();
     
     println!("Hello It'sMoNdAy. How's your day going??");
-    loop {}
+    rustyos::hlt_loop();
 }
 
 // panic handler 
@@ -70,7 +68,7 @@ pub extern "C" fn _start() {
 #[panic_handler]
 fn panic( _info: &core::panic::PanicInfo ) -> ! {
     println!("{}", _info);
-    loop {}
+    rustyos::hlt_loop();
 }
 
 // test (panic handler)
@@ -1,4 +1,93 @@
-use core::sync::atomic::{AtomicBool, AtomicU64};
+// AMD SME memory encryption.
+// With SME on, one physical address bit (the C-bit) of a page table entry
+// says whether the page is encrypted. Its position comes from CPUID
+// 0x8000001F and it's taken out of the physical address mask, so the rest
+// of the paging code never sees it in an address.
+//
+// If the cpu (or QEMU) doesn't report SME, nothing changes: the mask stays
+// 0 and `PageTableFlags::ENCRYPTED` is dropped when an entry is written.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::{cpuid, model_specific::Syscfg, structures::page_table::PHYSICAL_ADDRESS_MASK};
 
 pub static ENC_BIT_MASK: AtomicU64 = AtomicU64::new(0);
-pub static ENC_BIT_REVERSED: AtomicBool = AtomicBool::new(false);
\ No newline at end of file
+// bit set means *not* encrypted (Intel TDX shared bit). SME sets the bit
+// for encrypted pages, so this stays false for now.
+pub static ENC_BIT_REVERSED: AtomicBool = AtomicBool::new(false);
+
+
+// CPUID.8000001FH:EAX[0]
+const SME_SUPPORTED: u32 = 1;
+
+// Looks for SME and sets up the C-bit if it's enabled. Has to run before
+// any page table entry with `ENCRYPTED` is written.
+pub fn init() {
+    if let Some(c_bit) = detect() {
+        enable(c_bit);
+    }
+}
+
+// C-bit position, None if SME isn't supported or not turned on in SYSCFG.
+fn detect() -> Option<u8> {
+    if cpuid::max_extended_leaf() < 0x8000_001F {
+        return None;
+    }
+    let leaf = cpuid::cpuid(0x8000_001F, 0);
+    if leaf.eax & SME_SUPPORTED == 0 {
+        return None;
+    }
+
+    // only AMD cpus have SYSCFG, and they do if the leaf reports SME
+    let syscfg = unsafe { Syscfg::MSR.read() };
+    if syscfg & Syscfg::MEM_ENCRYPTION_MOD_EN == 0 {
+        return None;
+    }
+
+    // EBX[5:0]
+    let c_bit = (leaf.ebx & 0x3f) as u8;
+    (12..52).contains(&c_bit).then_some(c_bit)
+}
+
+fn enable(c_bit: u8) {
+    let mask = 1u64 << c_bit;
+    ENC_BIT_MASK.store(mask, Ordering::Relaxed);
+    PHYSICAL_ADDRESS_MASK.fetch_and(!mask, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn is_active() -> bool {
+    ENC_BIT_MASK.load(Ordering::Relaxed) != 0
+}
+
+#[inline]
+pub fn c_bit_position() -> Option<u8> {
+    match ENC_BIT_MASK.load(Ordering::Relaxed) {
+        0 => None,
+        mask => Some(mask.trailing_zeros() as u8),
+    }
+}
+
+
+#[cfg(feature = "memory_encryption")]
+#[test_case]
+fn test_encrypted_entry() {
+    use crate::{addr::PhyAddr, structures::page_table::{PageTableEntry, PageTableFlags}};
+
+    let addr = PhyAddr::new(0x1234_5000);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::ENCRYPTED;
+    let mut entry = PageTableEntry::new();
+    entry.set_addr(addr, flags);
+
+    // the C-bit never leaks into the address
+    assert_eq!(entry.addr(), addr);
+    if is_active() {
+        assert_eq!(entry.flags(), flags);
+        entry.set_flags(PageTableFlags::PRESENT);
+        assert_eq!(entry.flags(), PageTableFlags::PRESENT);
+        assert_eq!(entry.addr(), addr);
+    } else {
+        // no SME, e.g. plain QEMU: the flag is just dropped
+        assert_eq!(entry.flags(), flags - PageTableFlags::ENCRYPTED);
+    }
+}
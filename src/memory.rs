@@ -0,0 +1,180 @@
+use core::{fmt, sync::atomic::{AtomicBool, AtomicU64, Ordering}};
+
+use bootloader::{bootinfo::MemoryMap, BootInfo};
+use spin::Mutex;
+use x86_64::registers::control::Cr3;
+
+use crate::{addr::{PhyAddr, VirtualAddr}, cpuid, pmm::BitmapFrameAllocator, structures::{mapper::Mapper, page::{Page, PageSize, Size1GiB, Size2Mib}, page_table::{PageTable, PageTableFlags}, phys_frame::PhysFrame}};
+#[cfg(not(feature = "recursive_page_table"))]
+use crate::structures::mapper::OffsetPageTable;
+#[cfg(feature = "recursive_page_table")]
+use crate::structures::mapper::RecursivePageTable;
+
+// Virtual address at which the complete physical memory is mapped. The
+// bootloader's mapping at first, `DIRECT_MAP_START` once `init` built ours.
+// Page tables are only reachable through this mapping, so anything walking
+// them has to wait until the offset is known.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+static PHYSICAL_MEMORY_MAPPED: AtomicBool = AtomicBool::new(false);
+
+
+pub fn init_physical_memory_offset(offset: VirtualAddr) {
+    PHYSICAL_MEMORY_OFFSET.store(offset.as_u64(), Ordering::SeqCst);
+    PHYSICAL_MEMORY_MAPPED.store(true, Ordering::SeqCst);
+}
+
+pub fn physical_memory_offset() -> Option<VirtualAddr> {
+    if PHYSICAL_MEMORY_MAPPED.load(Ordering::SeqCst) {
+        Some(VirtualAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst)))
+    } else {
+        None
+    }
+}
+
+// Virtual address through which the given physical address can be accessed.
+#[inline]
+pub fn phys_to_virt(addr: PhyAddr) -> Option<VirtualAddr> {
+    physical_memory_offset().map(|offset| VirtualAddr::new(offset.as_u64() + addr.as_u64()))
+}
+
+// Physical address of the currently active level 4 table (from CR3).
+#[inline]
+pub fn active_level_4_table_addr() -> PhyAddr {
+    let (level_4_table_frame, _) = Cr3::read();
+    PhyAddr::new(level_4_table_frame.start_address().as_u64())
+}
+
+// Returns the page table stored in the frame at `addr`.
+//
+// unsafe: caller must guarantee that `addr` really holds a page table
+// and that no mutable reference to it is alive.
+pub unsafe fn page_table_at(addr: PhyAddr) -> Option<&'static PageTable> {
+    phys_to_virt(addr).map(|virt| unsafe { &*virt.as_ptr::<PageTable>() })
+}
+
+// The active level 4 table, for handing to a mapper.
+//
+// unsafe: physical memory must be mapped and there must be only one
+// mutable reference to the table at a time.
+pub unsafe fn active_level_4_table() -> &'static mut PageTable {
+    let virt = phys_to_virt(active_level_4_table_addr()).expect("physical memory not mapped");
+    unsafe { &mut *(virt.as_u64() as *mut PageTable) }
+}
+
+
+// Mapper for the active page table. The `recursive_page_table` feature
+// switches from reaching the tables through the physical memory mapping
+// to a recursive P4 entry set up by the bootloader.
+#[cfg(not(feature = "recursive_page_table"))]
+pub type ActivePageTable = OffsetPageTable<'static>;
+
+#[cfg(feature = "recursive_page_table")]
+pub type ActivePageTable = RecursivePageTable<'static>;
+
+#[cfg(not(feature = "recursive_page_table"))]
+unsafe fn active_page_table(_boot_info: &'static BootInfo) -> ActivePageTable {
+    let phys_offset = physical_memory_offset().expect("physical memory not mapped");
+    unsafe { OffsetPageTable::new(active_level_4_table(), phys_offset) }
+}
+
+#[cfg(feature = "recursive_page_table")]
+unsafe fn active_page_table(boot_info: &'static BootInfo) -> ActivePageTable {
+    let level_4_table = unsafe { &mut *(boot_info.recursive_page_table_addr as *mut PageTable) };
+    RecursivePageTable::new(level_4_table).expect("bootloader didn't set up a recursive page table")
+}
+
+
+// Page table and frame allocator for the whole kernel, set up by `init`.
+// Lock the page table first when both are needed.
+pub static PAGE_TABLE: Mutex<Option<ActivePageTable>> = Mutex::new(None);
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator<'static>>> = Mutex::new(None);
+
+// Runs `f` with the page table and the frame allocator, for the page fault
+// handlers. The fault can come from code holding these locks, so this
+// doesn't wait for them: None if either is taken or `init` hasn't run.
+pub fn with_fault_locks<R>(f: impl FnOnce(&mut ActivePageTable, &mut BitmapFrameAllocator<'static>) -> R) -> Option<R> {
+    let (Some(mut page_table), Some(mut frame_allocator)) = (PAGE_TABLE.try_lock(), FRAME_ALLOCATOR.try_lock()) else {
+        return None;
+    };
+    let (Some(mapper), Some(frame_allocator)) = (page_table.as_mut(), frame_allocator.as_mut()) else {
+        return None;
+    };
+    Some(f(mapper, frame_allocator))
+}
+
+// Sets up physical memory access, the page table and the frame allocator
+// from the boot info, then the interrupt stacks that need them.
+//
+// unsafe: the boot info has to be the one the bootloader passed, and this
+// must only run once.
+pub unsafe fn init(boot_info: &'static BootInfo) {
+    // before anything reads or writes page table entries
+    #[cfg(feature = "memory_encryption")]
+    crate::meme_encrypt::init();
+
+    init_physical_memory_offset(VirtualAddr::new(boot_info.physical_memory_offset));
+    let frame_allocator = unsafe { BitmapFrameAllocator::from_memory_map(&boot_info.memory_map) };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    *PAGE_TABLE.lock() = Some(unsafe { active_page_table(boot_info) });
+
+    // our own direct map replaces the bootloader's, the page table has to
+    // reach the tables through it too
+    unsafe { map_physical_memory(&boot_info.memory_map) };
+    *PAGE_TABLE.lock() = Some(unsafe { active_page_table(boot_info) });
+    crate::tlb::init();
+    unsafe { crate::address_space::init() };
+
+    // the HPET is MMIO, it can only be found once physical memory is mapped
+    crate::tsc::init();
+
+    // IST stacks move to guard-paged stacks as soon as they can be mapped
+    crate::gdt::init_stacks().expect("mapping the interrupt stacks failed");
+}
+
+
+// Start of the kernel's direct map of physical memory, the beginning of the
+// upper half. `phys_to_virt` goes through it once `init` built it.
+pub const DIRECT_MAP_START: u64 = 0xffff_8000_0000_0000;
+
+// the direct map covers at least the first 4GiB, the apics and the other
+// MMIO below 4GiB aren't in the memory map
+const DIRECT_MAP_MIN_END: u64 = 4 << 30;
+
+// Maps all of physical memory at `DIRECT_MAP_START` with 1GiB pages, or
+// 2MiB pages if the cpu doesn't have those, and moves `phys_to_virt` over.
+// The bootloader's mapping stays, the frame allocator's bitmap is reached
+// through it.
+//
+// unsafe: only once, from `init`, before anything else uses the upper half.
+unsafe fn map_physical_memory(memory_map: &MemoryMap) {
+    let end = memory_map.iter().map(|region| region.range.end_addr()).max().unwrap_or(0).max(DIRECT_MAP_MIN_END);
+    {
+        let mut page_table = PAGE_TABLE.lock();
+        let mapper = page_table.as_mut().expect("page table not set up");
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().expect("frame allocator not set up");
+
+        if cpuid::has_1gib_pages() {
+            unsafe { map_direct::<Size1GiB>(mapper, frame_allocator, end) };
+        } else {
+            unsafe { map_direct::<Size2Mib>(mapper, frame_allocator, end) };
+        }
+    }
+    init_physical_memory_offset(VirtualAddr::new(DIRECT_MAP_START));
+}
+
+unsafe fn map_direct<S: PageSize + fmt::Debug>(mapper: &mut ActivePageTable, frame_allocator: &mut BitmapFrameAllocator, end: u64)
+where
+    ActivePageTable: Mapper<S>,
+{
+    // the same in every address space, so it can stay in the TLB across
+    // CR3 switches
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::GLOBAL | PageTableFlags::NO_EXECUTE;
+    let first_page = Page::<S>::containing_address(VirtualAddr::new(DIRECT_MAP_START));
+
+    for index in 0..crate::addr::align_up(end, S::SIZE) / S::SIZE {
+        let frame = PhysFrame::<S>::frame_containing_addr(PhyAddr::new(index * S::SIZE));
+        // nothing was mapped up here, so there's nothing to flush
+        unsafe { mapper.map_to(first_page + index, frame, flags, frame_allocator).expect("building the direct map failed").ignore() };
+    }
+}
@@ -1,7 +1,7 @@
 use bitflags::bitflags;
 
 #[cfg_attr(
-    not(all(feature="instructions", target_arch = "x86_64")), 
+    not(target_arch = "x86_64"), 
     allow(dead_code))]
 pub struct Msr(u32);
 
@@ -26,14 +26,14 @@ pub struct FsBase;
 
 
 #[cfg_attr(
-    all(feature = "instructions", target_arch = "x86_64"), 
+    target_arch = "x86_64", 
     doc = "[`GS-SWAP`] swaps this register with [`KernelGsBase`]."
 )]
 #[derive(Debug)]
 pub struct GsBase;
 
 #[cfg_attr(
-    all(feature = "instructions", target_arch="x86_64"), 
+    target_arch="x86_64", 
     doc = "[`GS-Swap`] swaps this register with [`GsBase`]."
 )]
 #[derive(Debug)]
@@ -65,6 +65,10 @@ pub struct Scet;  // Supervised mode enforcement technology
 pub struct ApicBase; // Advanced Programmable interrupt controller
 
 
+#[derive(Debug)]
+pub struct Syscfg; // AMD system configuration, bit 23 enables SME
+
+
 impl Efer {
     pub const MSR: Msr = Msr( 0xC000_0080 );
 }
@@ -110,6 +114,12 @@ impl ApicBase {
     pub const MSR: Msr = Msr( 0x1B );
 }
 
+impl Syscfg {
+    pub const MSR: Msr = Msr( 0xC001_0010 );
+    // MemEncryptionModEn
+    pub const MEM_ENCRYPTION_MOD_EN: u64 = 1 << 23;
+}
+
 // Continue from here......
 bitflags! {
     #[repr(transparent)]
@@ -143,7 +153,7 @@ bitflags! {
 
     #[repr(transparent)]
     #[derive(Debug)]
-    pub struct Cetflags: u64 {
+    pub struct CetFlags: u64 {
         // SS(shadow stack) = a protected stack that mirrors return addresses to detect tampering
         // IBT(Indirect Branch Tracking) = ensures that indirect branches(like function pointers or virtual calls) land only on vvalid targets
 
@@ -196,9 +206,12 @@ bitflags! {
 }
 
 mod x86_64 {
-    use core::fmt;
+    use core::{arch::asm, fmt};
+
+    use bit_field::BitField;
+    use ::x86_64::{registers::rflags::RFlags, PrivilegeLevel};
 
-    use crate::{addr::VirtualAddr, model_specific::{Efer, EferFlags, FsBase, GsBase, KernelGsBase, LStar, Msr, SFMask, Star, Ucet}, segmentation::SegmentSelector};
+    use crate::{addr::{PhyAddr, VirtualAddr}, model_specific::{ApicBase, ApicBaseFlags, CetFlags, Efer, EferFlags, FsBase, GsBase, KernelGsBase, LStar, Msr, SFMask, Star, Ucet}, segmentation::SegmentSelector, structures::page::{Page, Size4Kib}};
 
     impl Msr {
         // reads 64 bits msr register
@@ -207,8 +220,8 @@ mod x86_64 {
             let ( high, low ): (u32, u32);
             unsafe {
                 asm!(
-                    "rdmsr" // read MSR(model specific register)
-                    in("ecx") self.0
+                    "rdmsr", // read MSR(model specific register)
+                    in("ecx") self.0,
                     out("eax") low, out("edx") high,
                     options(nomem, nostack, preserves_flags),
                 )
@@ -226,7 +239,7 @@ mod x86_64 {
                 asm!(
                     "wrmsr",
                     in("ecx") self.0,
-                    out("eax") low, in("edx") high,
+                    in("eax") low, in("edx") high,
                     options(nostack, preserves_flags),
                 )
             }
@@ -242,7 +255,7 @@ mod x86_64 {
 
         // Reads the current Efer flags
         #[inline]
-        pub fn read_raw() {
+        pub fn read_raw() -> u64 {
             unsafe {
                 Self::MSR.read()
             }
@@ -284,8 +297,8 @@ mod x86_64 {
 
         // read
         #[inline]
-        pub fn read() -> VirtAddress {
-            VirtAddress::new( unsafe {
+        pub fn read() -> VirtualAddr {
+            VirtualAddr::new( unsafe {
                 Self::MSR.read()
             })
         }
@@ -396,18 +409,16 @@ mod x86_64 {
                 return Err(InvalidStarSegmentSelectors::SyscallOffset);
             }
 
-            if ss_sysret.rpl() != PrivilegeLevel::Ring3 {
-                return Err(InvalidStarSegmentSelectors::SyscallPrivilegeLevel);
+            if ss_sysret.rpl() != PrivilegeLevel::Ring3 as u16 {
+                return Err(InvalidStarSegmentSelectors::SysretPrivilegeLevel);
             }
 
-            if ss_syscall.rpl() != PrivilegeLevel::Ring0 {
+            if ss_syscall.rpl() != PrivilegeLevel::Ring0 as u16 {
                 return Err(InvalidStarSegmentSelectors::SyscallPrivilegeLevel);
             }
 
-            unsafe {
-                Self::write_raw(ss_sysret.0 - 8, cs_syscall.0);
-            }
-
+            Self::write_raw(ss_sysret.0 - 8, cs_syscall.0);
+            Ok(())
         }
 
     }
@@ -447,9 +458,9 @@ mod x86_64 {
     impl SFMask {
         #[inline]
         pub fn read() -> RFlags {
-            RFlags::from_bits(unsafe {
+            RFlags::from_bits_truncate(unsafe {
                 Self::MSR.read()
-            }).unwrap()
+            })
         }
 
         #[inline]
@@ -488,14 +499,14 @@ mod x86_64 {
         pub fn read() -> (CetFlags, Page) {
             let value = Self::read_raw();
             let cet_flags = CetFlags::from_bits_truncate(value);
-            let legacy_bitmap = Page::from_start_address(VirtualAddr::new( value & !(Page::<Sinze4KiB>SIZE -1) )).unwrap();
+            let legacy_bitmap = Page::from_start_address(VirtualAddr::new( value & !(Page::<Size4Kib>::SIZE -1) )).unwrap();
 
             (cet_flags, legacy_bitmap)
         }
 
         #[inline]
         pub fn write(flas: CetFlags, legacy_bitmap: Page) {
-            Self::write_raw(flas.bits() | legacy_bitmap.start_address().as_64());
+            Self::write_raw(flas.bits() | legacy_bitmap.start_address().as_u64());
         }
 
         #[inline]
@@ -509,4 +520,58 @@ mod x86_64 {
         }
     }
 
+    impl ApicBase {
+
+        // physical base address of the local apic MMIO registers, bits 12..52
+        const BASE_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+        #[inline]
+        pub fn read_raw() -> u64 {
+            unsafe { Self::MSR.read() }
+        }
+
+        // Reads the local apic base address and its flags
+        #[inline]
+        pub fn read() -> (PhyAddr, ApicBaseFlags) {
+            let value = Self::read_raw();
+            (
+                PhyAddr::new(value & Self::BASE_MASK),
+                ApicBaseFlags::from_bits_truncate(value)
+            )
+        }
+
+        #[inline]
+        pub unsafe fn write_raw(value: u64) {
+            let mut msr = Self::MSR;
+            unsafe {
+                msr.write(value);
+            }
+        }
+
+        // Writes base address and flags, reserved bits are preserved.
+        //
+        // unsafe: moving the base or disabling the apic changes where and
+        // if interrupts get delivered.
+        #[inline]
+        pub unsafe fn write(address: PhyAddr, flags: ApicBaseFlags) {
+            let old_value = Self::read_raw();
+            let reserved = old_value & !(Self::BASE_MASK | ApicBaseFlags::all().bits());
+            unsafe {
+                Self::write_raw(reserved | address.as_u64() | flags.bits());
+            }
+        }
+
+        #[inline]
+        pub unsafe fn update<F>(f: F)
+        where
+            F: FnOnce(&mut PhyAddr, &mut ApicBaseFlags)
+        {
+            let (mut address, mut flags) = Self::read();
+            f(&mut address, &mut flags);
+            unsafe {
+                Self::write(address, flags);
+            }
+        }
+    }
+
 }
\ No newline at end of file
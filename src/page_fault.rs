@@ -0,0 +1,129 @@
+// Page fault diagnostics.
+// Decodes the error code pushed by the cpu and walks the active page table
+// for the faulting address (CR2), so we can see which level is missing
+// instead of staring at a triple fault.
+
+use core::fmt;
+
+use crate::{addr::{PhyAddr, VirtualAddr}, idt::PageFaultErrorCode, memory, serial, stack, structures::page_table::PageTableFlags, vga_buffer};
+
+
+// Result of walking P4 -> P1 for a single virtual address.
+#[derive(Debug, Clone, Copy)]
+pub enum PageWalk {
+    // entry at `level` maps the address. level 1 is a normal 4KiB page,
+    // level 2 and 3 are 2MiB / 1GiB huge pages.
+    Mapped { level: u8, frame: PhyAddr, flags: PageTableFlags },
+
+    // entry `index` in the table of `level` isn't present.
+    NotPresent { level: u8, index: usize },
+
+    // physical memory isn't mapped (yet), so the tables can't be read.
+    Unavailable,
+}
+
+
+pub fn walk(addr: VirtualAddr) -> PageWalk {
+    let mut table_addr = memory::active_level_4_table_addr();
+
+    for level in (1..=4).rev() {
+        let table = match unsafe { memory::page_table_at(table_addr) } {
+            Some(table) => table,
+            None => return PageWalk::Unavailable,
+        };
+
+        let index = usize::from(addr.page_table_index(level));
+        let entry = &table[index];
+        let flags = entry.flags();
+
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return PageWalk::NotPresent { level, index };
+        }
+
+        // huge pages end the walk early at P3 (1GiB) or P2 (2MiB)
+        if level == 1 || ((level == 2 || level == 3) && flags.contains(PageTableFlags::HUGE_PAGE)) {
+            return PageWalk::Mapped { level, frame: entry.addr(), flags };
+        }
+
+        table_addr = entry.addr();
+    }
+
+    unreachable!()
+}
+
+
+// what kind of access faulted, in words.
+pub fn access_kind(error_code: PageFaultErrorCode) -> &'static str {
+    if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        "instruction fetch"
+    } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+        "write"
+    } else {
+        "read"
+    }
+}
+
+// why the access faulted, in words.
+pub fn cause(error_code: PageFaultErrorCode) -> &'static str {
+    if error_code.contains(PageFaultErrorCode::MALFORMED_TABLE) {
+        "reserved bit set in a paging structure entry"
+    } else if error_code.contains(PageFaultErrorCode::PROTECTION_KEY) {
+        "protection key violation"
+    } else if error_code.contains(PageFaultErrorCode::SHADOW_KEY) {
+        "shadow stack access"
+    } else if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        "protection violation"
+    } else {
+        "page not present"
+    }
+}
+
+
+// prints on both VGA and serial.
+fn emit(args: fmt::Arguments) {
+    vga_buffer::_print(args);
+    serial::_print(args);
+}
+
+macro_rules! emitln {
+    ($($arg:tt)*) => (emit(format_args!("{}\n", format_args!($($arg)*))));
+}
+
+
+pub fn report(addr: VirtualAddr, error_code: PageFaultErrorCode, stack_frame: &dyn fmt::Debug) {
+    let mode = if error_code.contains(PageFaultErrorCode::USER_MODE) { "user" } else { "kernel" };
+
+    emitln!("EXCEPTION: PAGE FAULT");
+    emitln!("Accessed address (CR2): {:#x}", addr.as_u64());
+    emitln!("{} by {} mode: {}", access_kind(error_code), mode, cause(error_code));
+    emitln!("error code: {:?}", error_code);
+    if stack::is_guard_page(addr) {
+        emitln!("address is on the guard page of a kernel stack: stack overflow");
+    }
+
+    match walk(addr) {
+        PageWalk::Mapped { level, frame, flags } => {
+            emitln!("page table walk: mapped by P{} entry to {:?}, flags: {:?}", level, frame, flags);
+        }
+        PageWalk::NotPresent { level, index } => {
+            emitln!("page table walk: P{} entry {} is not present", level, index);
+        }
+        PageWalk::Unavailable => {
+            emitln!("page table walk: physical memory not mapped, can't read page tables");
+        }
+    }
+
+    emitln!("{:#?}", stack_frame);
+}
+
+
+#[test_case]
+fn test_page_fault_error_decoding() {
+    let code = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    assert_eq!(access_kind(code), "write");
+    assert_eq!(cause(code), "protection violation");
+
+    let code = PageFaultErrorCode::INSTRUCTION_FETCH;
+    assert_eq!(access_kind(code), "instruction fetch");
+    assert_eq!(cause(code), "page not present");
+}
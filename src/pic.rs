@@ -0,0 +1,171 @@
+// Chained 8259 Programmable Interrupt Controllers.
+//
+// The master PIC handles IRQ 0..8, the slave handles IRQ 8..16 and is wired
+// into IRQ 2 of the master. By default both deliver their interrupts on
+// vectors 0..16 which collide with the cpu exceptions, so we remap them
+// right behind the exceptions (32..48).
+
+use x86_64::instructions::port::Port;
+
+// ICW1: start initialization, ICW4 will follow
+const CMD_INIT: u8 = 0x11;
+
+// end of interrupt command
+const CMD_END_OF_INTERRUPT: u8 = 0x20;
+
+// ICW4: 8086/88 mode
+const MODE_8086: u8 = 0x01;
+
+// IRQ line on the master the slave is cascaded into
+const CASCADE_IRQ: u8 = 2;
+
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+
+struct Pic {
+    offset: u8,
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+
+    // does this pic handle the given interrupt vector
+    fn handles_interrupt(&self, interrupt_id: u8) -> bool {
+        self.offset <= interrupt_id && interrupt_id < self.offset + 8
+    }
+
+    unsafe fn end_of_interrupt(&mut self) {
+        unsafe { self.command.write(CMD_END_OF_INTERRUPT); }
+    }
+
+    unsafe fn read_mask(&mut self) -> u8 {
+        unsafe { self.data.read() }
+    }
+
+    unsafe fn write_mask(&mut self, mask: u8) {
+        unsafe { self.data.write(mask); }
+    }
+}
+
+
+pub struct ChainedPics {
+    pics: [Pic; 2],
+}
+
+impl ChainedPics {
+
+    pub const unsafe fn new(offset1: u8, offset2: u8) -> ChainedPics {
+        ChainedPics {
+            pics: [
+                Pic { offset: offset1, command: Port::new(0x20), data: Port::new(0x21) },
+                Pic { offset: offset2, command: Port::new(0xA0), data: Port::new(0xA1) },
+            ]
+        }
+    }
+
+    // Remaps both pics to their offsets and masks every line except the
+    // cascade. Lines have to be enabled afterwards with `unmask`.
+    //
+    // unsafe: the offsets must not overlap with the cpu exceptions and
+    // the IDT needs to have handlers for the lines that get unmasked.
+    pub unsafe fn initialize(&mut self) {
+        // writing to port 0x80 (POST codes) takes long enough for the old
+        // pics to process the previous command.
+        let mut wait_port: Port<u8> = Port::new(0x80);
+        let mut wait = || unsafe { wait_port.write(0) };
+
+        unsafe {
+            // ICW1: start the initialization sequence
+            self.pics[0].command.write(CMD_INIT);
+            wait();
+            self.pics[1].command.write(CMD_INIT);
+            wait();
+
+            // ICW2: vector offsets
+            self.pics[0].data.write(self.pics[0].offset);
+            wait();
+            self.pics[1].data.write(self.pics[1].offset);
+            wait();
+
+            // ICW3: master has a slave on IRQ 2, slave has cascade identity 2
+            self.pics[0].data.write(1 << CASCADE_IRQ);
+            wait();
+            self.pics[1].data.write(CASCADE_IRQ);
+            wait();
+
+            // ICW4: 8086 mode
+            self.pics[0].data.write(MODE_8086);
+            wait();
+            self.pics[1].data.write(MODE_8086);
+            wait();
+
+            self.write_masks(!(1 << CASCADE_IRQ), 0xff);
+        }
+    }
+
+    // interrupt masks of (master, slave). a set bit means the line is masked.
+    pub fn read_masks(&mut self) -> (u8, u8) {
+        unsafe { (self.pics[0].read_mask(), self.pics[1].read_mask()) }
+    }
+
+    pub unsafe fn write_masks(&mut self, mask1: u8, mask2: u8) {
+        unsafe {
+            self.pics[0].write_mask(mask1);
+            self.pics[1].write_mask(mask2);
+        }
+    }
+
+    // stop the given IRQ line (0..16) from raising interrupts.
+    pub fn mask(&mut self, irq: u8) {
+        assert!(irq < 16, "IRQ {} does not exist", irq);
+        let pic = &mut self.pics[usize::from(irq / 8)];
+        unsafe {
+            let mask = pic.read_mask() | (1 << (irq % 8));
+            pic.write_mask(mask);
+        }
+    }
+
+    // let the given IRQ line (0..16) raise interrupts. unmasking a slave
+    // line also unmasks the cascade on the master.
+    //
+    // unsafe: the IDT must have a handler for the vector of this line.
+    pub unsafe fn unmask(&mut self, irq: u8) {
+        assert!(irq < 16, "IRQ {} does not exist", irq);
+        unsafe {
+            if irq >= 8 {
+                let mask = self.pics[0].read_mask() & !(1 << CASCADE_IRQ);
+                self.pics[0].write_mask(mask);
+            }
+            let pic = &mut self.pics[usize::from(irq / 8)];
+            let mask = pic.read_mask() & !(1 << (irq % 8));
+            pic.write_mask(mask);
+        }
+    }
+
+    // mask every line on both pics, e.g. before switching to the APIC.
+    pub fn disable(&mut self) {
+        unsafe { self.write_masks(0xff, 0xff); }
+    }
+
+    pub fn handles_interrupt(&self, interrupt_id: u8) -> bool {
+        self.pics.iter().any(|pic| pic.handles_interrupt(interrupt_id))
+    }
+
+    // Signals end of interrupt for the given vector. Interrupts coming
+    // from the slave need an EOI on both pics.
+    //
+    // unsafe: sending an EOI for an interrupt that wasn't raised can make
+    // the pics drop the next real one.
+    pub unsafe fn notify_end_of_interrupt(&mut self, interrupt_id: u8) {
+        if self.handles_interrupt(interrupt_id) {
+            unsafe {
+                if self.pics[1].handles_interrupt(interrupt_id) {
+                    self.pics[1].end_of_interrupt();
+                }
+                self.pics[0].end_of_interrupt();
+            }
+        }
+    }
+}
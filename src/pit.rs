@@ -0,0 +1,141 @@
+// 8253/8254 Programmable Interval Timer.
+// Channel 0 is wired to IRQ 0 and counts down from a divisor at the fixed
+// input clock, raising an interrupt every time it reaches zero.
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+// input clock of the PIT in Hz
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+
+// keyboard controller port B, controls the gate of channel 2 (and the speaker)
+const PORT_B: u16 = 0x61;
+const PORT_B_GATE_2: u8 = 1 << 0;
+const PORT_B_SPEAKER: u8 = 1 << 1;
+const PORT_B_OUT_2: u8 = 1 << 5;
+
+// command bits
+const SELECT_CHANNEL_0: u8 = 0b00 << 6;
+const SELECT_CHANNEL_2: u8 = 0b10 << 6;
+const ACCESS_LATCH: u8 = 0b00 << 4;
+const ACCESS_LOBYTE_HIBYTE: u8 = 0b11 << 4;
+const MODE_RATE_GENERATOR: u8 = 0b010 << 1;
+const MODE_ONE_SHOT: u8 = 0b000 << 1;
+
+// the ports are shared between the timer setup and readers of the counter
+static PORTS: Mutex<()> = Mutex::new(());
+
+
+// the rate generator mode doesn't take a divisor of 1
+const MIN_DIVISOR: u32 = 2;
+
+// divisor for the closest frequency the PIT can produce.
+// a divisor of 0 is the largest, 65536.
+pub const fn divisor_for(frequency: u32) -> u16 {
+    assert!(frequency != 0, "the PIT can't run at 0 Hz");
+    let divisor = BASE_FREQUENCY / frequency;
+    if divisor < MIN_DIVISOR {
+        MIN_DIVISOR as u16
+    } else if divisor > 0xFFFF {
+        0
+    } else {
+        divisor as u16
+    }
+}
+
+// frequency the PIT really runs at with this divisor
+pub const fn frequency_of(divisor: u16) -> u32 {
+    let divisor = match divisor {
+        0 => 0x1_0000,
+        1 => MIN_DIVISOR,
+        divisor => divisor as u32,
+    };
+    BASE_FREQUENCY / divisor
+}
+
+
+unsafe fn program(mode: u8, divisor: u16) {
+    let _ports = PORTS.lock();
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(CHANNEL_0);
+    unsafe {
+        command.write(SELECT_CHANNEL_0 | ACCESS_LOBYTE_HIBYTE | mode);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    }
+}
+
+// Lets channel 0 fire IRQ 0 periodically at (about) `frequency` Hz, returns
+// the divisor that was programmed.
+//
+// unsafe: changes the rate of the timer interrupt everyone relies on.
+pub unsafe fn set_frequency(frequency: u32) -> u16 {
+    let divisor = divisor_for(frequency);
+    unsafe { program(MODE_RATE_GENERATOR, divisor); }
+    divisor
+}
+
+// Counts down `divisor` once, the interrupt fires when it reaches zero.
+//
+// unsafe: stops the periodic timer interrupt.
+pub unsafe fn start_one_shot(divisor: u16) {
+    unsafe { program(MODE_ONE_SHOT, divisor); }
+}
+
+// current value of the channel 0 counter
+pub fn read_count() -> u16 {
+    let _ports = PORTS.lock();
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(CHANNEL_0);
+    unsafe {
+        // latch so the two bytes belong to the same count
+        command.write(SELECT_CHANNEL_0 | ACCESS_LATCH);
+        let low = data.read();
+        let high = data.read();
+        u16::from_le_bytes([low, high])
+    }
+}
+
+
+// Busy waits `divisor` PIT cycles on channel 2, without touching channel 0
+// or needing interrupts. Used to calibrate other clocks against the PIT.
+pub fn wait_channel_2(divisor: u16) {
+    let _ports = PORTS.lock();
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut data: Port<u8> = Port::new(CHANNEL_2);
+    let mut port_b: Port<u8> = Port::new(PORT_B);
+
+    unsafe {
+        // gate off and speaker off while programming
+        let control = port_b.read() & !(PORT_B_GATE_2 | PORT_B_SPEAKER);
+        port_b.write(control);
+
+        command.write(SELECT_CHANNEL_2 | ACCESS_LOBYTE_HIBYTE | MODE_ONE_SHOT);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+
+        // raising the gate starts the count, OUT 2 goes high when it's done
+        port_b.write(control | PORT_B_GATE_2);
+        while port_b.read() & PORT_B_OUT_2 == 0 {
+            core::hint::spin_loop();
+        }
+
+        port_b.write(control);
+    }
+}
+
+
+#[test_case]
+fn test_divisor_for() {
+    assert_eq!(divisor_for(1000), 1193);
+    assert_eq!(frequency_of(divisor_for(1000)), 1000);
+    assert_eq!(divisor_for(10), 0);
+    assert_eq!(frequency_of(0), 18);
+    assert_eq!(divisor_for(BASE_FREQUENCY), 2);
+    assert_eq!(divisor_for(BASE_FREQUENCY * 2), 2);
+    assert_eq!(frequency_of(1), BASE_FREQUENCY / 2);
+}
@@ -0,0 +1,425 @@
+// Bitmap physical memory manager.
+// One bit per 4KiB frame, starting at physical address 0. A set bit means
+// the frame is in use (or doesn't exist / isn't usable RAM). It can find
+// runs of contiguous, aligned frames, which DMA buffers and huge pages
+// need (2MiB frames are 512 frames in a row with 2MiB alignment).
+//
+// Frames can be shared (copy-on-write), every used frame has a reference
+// count. Only the references past the first are stored, so frames that
+// were never shared, or never came from the allocator, count as one.
+//
+// A second bitmap remembers which frames the allocator manages: the ones
+// added with `add_free_range` and not reserved since. Only those can be
+// freed, everything else is firmware, MMIO or reserved memory.
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+
+use crate::{addr::{self, PhyAddr}, memory, structures::{frame_alloc::{FrameAllocator, FrameDeallocator, FrameStats}, page::{PageSize, Size4Kib}, phys_frame::{PhysFrame, PhysFrameRange}}};
+
+const BITS: u64 = u64::BITS as u64;
+
+// legacy VGA / BIOS area, never hand it out even if the map claims otherwise
+const LEGACY_HOLE_START: u64 = 0xA_0000;
+const LEGACY_HOLE_END: u64 = 0x10_0000;
+
+
+pub struct BitmapFrameAllocator<'a> {
+    bitmap: &'a mut [u64],
+
+    // bit set = managed frame, see above
+    managed: &'a mut [u64],
+
+    // extra references per frame, see `add_ref`
+    shares: &'a mut [u16],
+
+    // number of frames the bitmap covers
+    frames: u64,
+
+    // usable frames that were added, and how many of them are free
+    total: u64,
+    free: u64,
+
+    // usable frames taken out with `reserve_range`
+    reserved: u64,
+
+    // no free frame below this index
+    hint: u64,
+}
+
+impl<'a> BitmapFrameAllocator<'a> {
+
+    // Every frame starts out as unavailable, usable memory has to be
+    // added with `add_free_range`. `managed` needs as many words as
+    // `bitmap`, `shares` one entry per frame the bitmap covers.
+    pub fn new(bitmap: &'a mut [u64], managed: &'a mut [u64], shares: &'a mut [u16]) -> Self {
+        bitmap.fill(u64::MAX);
+        managed.fill(0);
+        shares.fill(0);
+        let frames = bitmap.len() as u64 * BITS;
+        assert!(managed.len() == bitmap.len(), "managed bitmap doesn't match the bitmap");
+        assert!(shares.len() as u64 >= frames, "reference counts don't cover the bitmap");
+        BitmapFrameAllocator { bitmap, managed, shares, frames, total: 0, free: 0, reserved: 0, hint: frames }
+    }
+
+    // number of u64 words needed for a bitmap covering `frames` frames
+    pub const fn words_for(frames: u64) -> usize {
+        frames.div_ceil(BITS) as usize
+    }
+
+    #[inline]
+    fn index(frame: PhysFrame) -> u64 {
+        frame.start_addr_of_physframe().as_u64() / Size4Kib::SIZE
+    }
+
+    #[inline]
+    fn frame(index: u64) -> PhysFrame {
+        PhysFrame::frame_containing_addr(PhyAddr::new(index * Size4Kib::SIZE))
+    }
+
+    #[inline]
+    fn is_used(&self, index: u64) -> bool {
+        bit(self.bitmap, index)
+    }
+
+    #[inline]
+    fn set_used(&mut self, index: u64, used: bool) {
+        set_bit(self.bitmap, index, used);
+    }
+
+    #[inline]
+    fn is_managed(&self, index: u64) -> bool {
+        bit(self.managed, index)
+    }
+
+    #[inline]
+    fn set_managed(&mut self, index: u64, managed: bool) {
+        set_bit(self.managed, index, managed);
+    }
+
+    // frame indices of a range, clamped to what the bitmap covers
+    fn indices(&self, range: PhysFrameRange) -> core::ops::Range<u64> {
+        let start = Self::index(range.start).min(self.frames);
+        let end = Self::index(range.end).min(self.frames);
+        start..end.max(start)
+    }
+
+    // Marks usable RAM as free. Frames that are managed already are skipped.
+    pub fn add_free_range(&mut self, range: PhysFrameRange) {
+        for index in self.indices(range) {
+            if !self.is_managed(index) {
+                self.set_managed(index, true);
+                self.set_used(index, false);
+                self.total += 1;
+                self.free += 1;
+            }
+        }
+        self.hint = self.hint.min(self.indices(range).start);
+    }
+
+    // Takes free frames out of circulation for good (kernel image, the
+    // bitmap itself, firmware holes...). Frames already in use are skipped.
+    // Reserved frames aren't managed anymore and can't be freed.
+    pub fn reserve_range(&mut self, range: PhysFrameRange) {
+        for index in self.indices(range) {
+            if !self.is_used(index) {
+                self.set_used(index, true);
+                self.set_managed(index, false);
+                self.free -= 1;
+                self.reserved += 1;
+            }
+        }
+    }
+
+    // Finds `count` free frames in a row, with the first one aligned to
+    // `align` bytes (a power of two, at least 4KiB), and marks them used.
+    pub fn allocate_contiguous(&mut self, count: u64, align: u64) -> Option<PhysFrameRange> {
+        assert!(align.is_power_of_two() && align >= Size4Kib::SIZE, "invalid alignment {:#x}", align);
+        if count == 0 || count > self.free {
+            return None;
+        }
+
+        let align = align / Size4Kib::SIZE;
+        let mut start = addr::align_up(self.hint, align);
+
+        'search: while start + count <= self.frames {
+            // skip over completely used words quickly
+            if start % BITS == 0 && self.bitmap[(start / BITS) as usize] == u64::MAX {
+                start = addr::align_up(start + BITS, align);
+                continue;
+            }
+
+            for index in start..start + count {
+                if self.is_used(index) {
+                    start = addr::align_up(index + 1, align);
+                    continue 'search;
+                }
+            }
+
+            for index in start..start + count {
+                self.set_used(index, true);
+            }
+            self.free -= count;
+            if start == self.hint {
+                self.hint = start + count;
+            }
+
+            return Some(PhysFrameRange { start: Self::frame(start), end: Self::frame(start + count) });
+        }
+        None
+    }
+
+    // Gives back frames from `allocate_contiguous` (or single frames).
+    // Shared frames only lose a reference and stay allocated.
+    //
+    // unsafe: the frames must not be in use anymore by whoever drops the
+    // reference.
+    pub unsafe fn deallocate_contiguous(&mut self, range: PhysFrameRange) {
+        for index in self.indices(range) {
+            assert!(self.is_used(index), "double free of {:?}", Self::frame(index));
+            if self.shares[index as usize] > 0 {
+                self.shares[index as usize] -= 1;
+                continue;
+            }
+            assert!(self.is_managed(index), "{:?} isn't managed by the frame allocator", Self::frame(index));
+            self.set_used(index, false);
+            self.free += 1;
+        }
+        self.hint = self.hint.min(self.indices(range).start);
+    }
+
+    // Adds a reference to a used frame, it's only freed once every
+    // reference is deallocated.
+    pub fn add_ref(&mut self, frame: PhysFrame) {
+        let index = Self::index(frame);
+        assert!(index < self.frames && self.is_used(index), "{:?} isn't allocated", frame);
+        let shares = &mut self.shares[index as usize];
+        *shares = shares.checked_add(1).expect("too many references to a frame");
+    }
+
+    // 0 for free frames
+    pub fn ref_count(&self, frame: PhysFrame) -> u32 {
+        let index = Self::index(frame);
+        if index < self.frames && self.is_used(index) {
+            self.shares[index as usize] as u32 + 1
+        } else {
+            0
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats { total: self.total, used: self.total - self.free, free: self.free }
+    }
+
+    // frames taken out by `reserve_range`
+    #[inline]
+    pub fn reserved(&self) -> u64 {
+        self.reserved
+    }
+
+    // Number of free frames in the longest free run, handy to see how
+    // fragmented physical memory is.
+    pub fn largest_free_run(&self) -> u64 {
+        let mut largest = 0;
+        let mut current = 0;
+        for index in 0..self.frames {
+            if self.is_used(index) {
+                current = 0;
+            } else {
+                current += 1;
+                largest = largest.max(current);
+            }
+        }
+        largest
+    }
+}
+
+impl BitmapFrameAllocator<'static> {
+
+    // Builds the allocator from the bootloader memory map. The bitmaps and
+    // the reference counts are placed in the first usable region big
+    // enough to hold them.
+    //
+    // unsafe: the memory map must be valid and physical memory mapped.
+    pub unsafe fn from_memory_map(memory_map: &'static MemoryMap) -> Self {
+        let usable = || memory_map.iter().filter(|region| region.region_type == MemoryRegionType::Usable);
+
+        let frames = usable().map(|region| region.range.end_addr()).max().unwrap_or(0) / Size4Kib::SIZE;
+        let words = Self::words_for(frames);
+        let managed_offset = (words * size_of::<u64>()) as u64;
+        let shares_offset = 2 * managed_offset;
+        let bitmap_bytes = addr::align_up(shares_offset + words as u64 * BITS * size_of::<u16>() as u64, Size4Kib::SIZE);
+
+        let bitmap_start = usable()
+            .map(|region| addr::align_up(region.range.start_addr(), Size4Kib::SIZE))
+            .zip(usable().map(|region| region.range.end_addr()))
+            .find(|&(start, end)| start >= LEGACY_HOLE_END && start + bitmap_bytes <= end)
+            .map(|(start, _)| start)
+            .expect("no usable region large enough for the frame bitmap");
+
+        let bitmap_virt = memory::phys_to_virt(PhyAddr::new(bitmap_start)).expect("physical memory not mapped");
+        let bitmap = unsafe { core::slice::from_raw_parts_mut(bitmap_virt.as_mut_ptr::<u64>(), words) };
+        let managed = unsafe { core::slice::from_raw_parts_mut((bitmap_virt + managed_offset).as_mut_ptr::<u64>(), words) };
+        let shares = unsafe { core::slice::from_raw_parts_mut((bitmap_virt + shares_offset).as_mut_ptr::<u16>(), words * BITS as usize) };
+
+        let mut allocator = BitmapFrameAllocator::new(bitmap, managed, shares);
+        for region in usable() {
+            allocator.add_free_range(range(
+                addr::align_up(region.range.start_addr(), Size4Kib::SIZE),
+                addr::align_down(region.range.end_addr(), Size4Kib::SIZE),
+            ));
+        }
+
+        allocator.reserve_range(range(bitmap_start, bitmap_start + bitmap_bytes));
+        // frame 0 (real mode IVT / BIOS data) and the VGA hole
+        allocator.reserve_range(range(0, Size4Kib::SIZE));
+        allocator.reserve_range(range(LEGACY_HOLE_START, LEGACY_HOLE_END));
+        allocator
+    }
+}
+
+#[inline]
+fn bit(words: &[u64], index: u64) -> bool {
+    words[(index / BITS) as usize] & (1 << (index % BITS)) != 0
+}
+
+#[inline]
+fn set_bit(words: &mut [u64], index: u64, value: bool) {
+    let word = &mut words[(index / BITS) as usize];
+    if value {
+        *word |= 1 << (index % BITS);
+    } else {
+        *word &= !(1 << (index % BITS));
+    }
+}
+
+// frames covering the physical addresses start..end (both 4KiB aligned)
+pub fn range(start: u64, end: u64) -> PhysFrameRange {
+    PhysFrameRange {
+        start: PhysFrame::frame_containing_addr(PhyAddr::new(start)),
+        end: PhysFrame::frame_containing_addr(PhyAddr::new(end.max(start))),
+    }
+}
+
+
+unsafe impl FrameAllocator<Size4Kib> for BitmapFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4Kib>> {
+        self.allocate_contiguous(1, Size4Kib::SIZE).map(|range| range.start)
+    }
+}
+
+impl FrameDeallocator<Size4Kib> for BitmapFrameAllocator<'_> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4Kib>) {
+        unsafe { self.deallocate_contiguous(PhysFrameRange { start: frame, end: frame + 1 }); }
+    }
+}
+
+
+#[cfg(test)]
+fn test_allocator<'a>(bitmap: &'a mut [u64], managed: &'a mut [u64], shares: &'a mut [u16], frames: u64) -> BitmapFrameAllocator<'a> {
+    let mut allocator = BitmapFrameAllocator::new(bitmap, managed, shares);
+    allocator.add_free_range(range(0, frames * Size4Kib::SIZE));
+    allocator
+}
+
+#[test_case]
+fn test_single_frames() {
+    let mut bitmap = [0; 2];
+    let mut managed = [0; 2];
+    let mut shares = [0; 128];
+    let mut allocator = test_allocator(&mut bitmap, &mut managed, &mut shares, 128);
+    assert_eq!(allocator.stats(), FrameStats { total: 128, used: 0, free: 128 });
+
+    let first: PhysFrame = allocator.allocate_frame().unwrap();
+    let second: PhysFrame = allocator.allocate_frame().unwrap();
+    assert_eq!(second - first, 1);
+    assert_eq!(allocator.stats().used, 2);
+
+    unsafe { allocator.deallocate_frame(first); }
+    // the lowest free frame is handed out first
+    assert_eq!(allocator.allocate_frame(), Some(first));
+}
+
+#[test_case]
+fn test_contiguous_in_fragmented_memory() {
+    let mut bitmap = [0; 2];
+    let mut managed = [0; 2];
+    let mut shares = [0; 128];
+    let mut allocator = test_allocator(&mut bitmap, &mut managed, &mut shares, 128);
+
+    // take everything, then free every other frame below 64 and a run of 8 at 100
+    let all = allocator.allocate_contiguous(128, Size4Kib::SIZE).unwrap();
+    assert_eq!(all.total_frames_within_range(), 128);
+    assert_eq!(allocator.allocate_contiguous(1, Size4Kib::SIZE), None);
+
+    for index in (0..64).step_by(2) {
+        unsafe { allocator.deallocate_contiguous(range(index * 4096, (index + 1) * 4096)); }
+    }
+    unsafe { allocator.deallocate_contiguous(range(100 * 4096, 108 * 4096)); }
+    assert_eq!(allocator.stats().free, 32 + 8);
+    assert_eq!(allocator.largest_free_run(), 8);
+
+    // 2 frames in a row only fit into the run at 100
+    let run = allocator.allocate_contiguous(2, Size4Kib::SIZE).unwrap();
+    assert_eq!(run.start.start_addr_of_physframe().as_u64(), 100 * 4096);
+
+    // 8 don't fit anymore
+    assert_eq!(allocator.allocate_contiguous(8, Size4Kib::SIZE), None);
+    assert_eq!(allocator.allocate_contiguous(6, Size4Kib::SIZE).unwrap().start.start_addr_of_physframe().as_u64(), 102 * 4096);
+}
+
+#[test_case]
+fn test_aligned_allocation() {
+    let mut bitmap = [0; 4];
+    let mut managed = [0; 4];
+    let mut shares = [0; 256];
+    let mut allocator = test_allocator(&mut bitmap, &mut managed, &mut shares, 256);
+
+    allocator.allocate_frame().unwrap();
+    let aligned = allocator.allocate_contiguous(4, 64 * 4096).unwrap();
+    assert_eq!(aligned.start.start_addr_of_physframe().as_u64(), 64 * 4096);
+
+    // frames skipped for the alignment are still free
+    assert_eq!(allocator.allocate_contiguous(63, Size4Kib::SIZE).unwrap().start.start_addr_of_physframe().as_u64(), 4096);
+}
+
+#[test_case]
+fn test_reserved_ranges() {
+    let mut bitmap = [0; 2];
+    let mut managed = [0; 2];
+    let mut shares = [0; 128];
+    let mut allocator = test_allocator(&mut bitmap, &mut managed, &mut shares, 128);
+
+    allocator.reserve_range(range(0, 16 * 4096));
+    assert_eq!(allocator.reserved(), 16);
+    assert_eq!(allocator.stats().free, 112);
+
+    let frame: PhysFrame = allocator.allocate_frame().unwrap();
+    assert_eq!(frame.start_addr_of_physframe().as_u64(), 16 * 4096);
+
+    // only the space above the reserved range is left
+    assert!(allocator.allocate_contiguous(113, Size4Kib::SIZE).is_none());
+}
+
+
+#[test_case]
+fn test_shared_frames() {
+    let mut bitmap = [0; 2];
+    let mut managed = [0; 2];
+    let mut shares = [0; 128];
+    let mut allocator = test_allocator(&mut bitmap, &mut managed, &mut shares, 128);
+
+    let frame: PhysFrame = allocator.allocate_frame().unwrap();
+    assert_eq!(allocator.ref_count(frame), 1);
+    allocator.add_ref(frame);
+    allocator.add_ref(frame);
+    assert_eq!(allocator.ref_count(frame), 3);
+
+    // the frame stays allocated until the last reference goes
+    unsafe { allocator.deallocate_frame(frame); }
+    unsafe { allocator.deallocate_frame(frame); }
+    assert_eq!(allocator.ref_count(frame), 1);
+    assert_eq!(allocator.stats().used, 1);
+    unsafe { allocator.deallocate_frame(frame); }
+    assert_eq!(allocator.ref_count(frame), 0);
+    assert_eq!(allocator.stats().used, 0);
+}
@@ -0,0 +1,99 @@
+// Fixed size lock-free ring buffer.
+// Safe to push from an interrupt handler while kernel code pops, without
+// either side taking a lock. `head` and `tail` only ever grow, the slot is
+// `counter % N`.
+
+use core::{cell::UnsafeCell, mem::MaybeUninit, sync::atomic::{AtomicUsize, Ordering}};
+
+
+pub struct RingBuffer<T: Copy, const N: usize> {
+    slots: UnsafeCell<[MaybeUninit<T>; N]>,
+
+    // next slot to pop
+    head: AtomicUsize,
+
+    // next slot to push
+    tail: AtomicUsize,
+}
+
+// Only one producer may push at a time (the interrupt handler), any number
+// of consumers may pop.
+unsafe impl<T: Copy + Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+
+    pub const fn new() -> Self {
+        RingBuffer {
+            slots: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Adds `value` at the end, hands it back if the buffer is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) >= N {
+            return Err(value);
+        }
+
+        unsafe {
+            (*self.slots.get())[tail % N].write(value);
+        }
+        // publish the slot only after it was written
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    // Removes the oldest value.
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            if head == self.tail.load(Ordering::Acquire) {
+                return None;
+            }
+
+            let value = unsafe { (*self.slots.get())[head % N].assume_init() };
+
+            // another consumer may have taken the slot in the meantime
+            if self.head.compare_exchange(head, head.wrapping_add(1), Ordering::AcqRel, Ordering::Acquire).is_ok() {
+                return Some(value);
+            }
+        }
+    }
+}
+
+
+#[test_case]
+fn test_ring_buffer_fifo() {
+    let buffer: RingBuffer<u8, 4> = RingBuffer::new();
+    assert!(buffer.is_empty());
+
+    for i in 0..4 {
+        buffer.push(i).unwrap();
+    }
+    assert_eq!(buffer.push(4), Err(4));
+    assert_eq!(buffer.len(), 4);
+
+    assert_eq!(buffer.pop(), Some(0));
+    buffer.push(4).unwrap();
+    for i in 1..5 {
+        assert_eq!(buffer.pop(), Some(i));
+    }
+    assert_eq!(buffer.pop(), None);
+}
@@ -0,0 +1,244 @@
+// CMOS real-time clock.
+// The RTC keeps the wall clock time in the CMOS, read through an index
+// port (0x70) and a data port (0x71). Depending on status register B the
+// values are BCD or binary and the hour is 12 or 24 hour based.
+// It can also raise a periodic interrupt on IRQ 8.
+
+use core::{fmt, sync::atomic::{AtomicU64, Ordering}};
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::interrupts::{self, InterruptIndex};
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+// registers
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+const STATUS_C: u8 = 0x0C;
+
+// status A: update in progress, the time registers are being changed
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+// status B
+const HOUR_24: u8 = 1 << 1;
+const BINARY: u8 = 1 << 2;
+const PERIODIC_INTERRUPT: u8 = 1 << 6;
+
+// bit 7 of the hour in 12 hour mode
+const HOUR_PM: u8 = 1 << 7;
+
+// the CMOS has no reliable century register without ACPI
+const CENTURY: u16 = 2000;
+
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos::new());
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+
+struct Cmos {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+
+    const fn new() -> Self {
+        Cmos { index: Port::new(CMOS_INDEX), data: Port::new(CMOS_DATA) }
+    }
+
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.index.write(register);
+            self.data.read()
+        }
+    }
+
+    unsafe fn write(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.index.write(register);
+            self.data.write(value);
+        }
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        self.read(STATUS_A) & UPDATE_IN_PROGRESS != 0
+    }
+
+    // raw register values: seconds, minutes, hours, day, month, year
+    fn read_raw(&mut self) -> [u8; 6] {
+        while self.update_in_progress() {
+            core::hint::spin_loop();
+        }
+        [SECONDS, MINUTES, HOURS, DAY, MONTH, YEAR].map(|register| self.read(register))
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+
+    // seconds since 1970-01-01 00:00:00 UTC (the RTC is assumed to run in UTC)
+    pub fn unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(i64::from(self.year), u32::from(self.month), u32::from(self.day));
+        days as u64 * 86_400 + u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// days since 1970-01-01 of a date in the proleptic gregorian calendar
+// (Howard Hinnant's days_from_civil)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[inline]
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+fn decode(raw: [u8; 6], status_b: u8) -> DateTime {
+    let binary = status_b & BINARY != 0;
+    let convert = |value: u8| if binary { value } else { bcd_to_binary(value) };
+
+    let [second, minute, hour, day, month, year] = raw;
+
+    let mut hour_24 = convert(hour & !HOUR_PM);
+    if status_b & HOUR_24 == 0 {
+        // 12 hour mode: 12am is 0, 1pm..11pm is 13..23
+        hour_24 %= 12;
+        if hour & HOUR_PM != 0 {
+            hour_24 += 12;
+        }
+    }
+
+    DateTime {
+        year: CENTURY + u16::from(convert(year)),
+        month: convert(month),
+        day: convert(day),
+        hour: hour_24,
+        minute: convert(minute),
+        second: convert(second),
+    }
+}
+
+// Reads the current wall clock time. The registers are read until two
+// reads in a row agree, so an update between two registers can't tear it.
+// The IRQ 8 handler uses the CMOS too, so it's locked with interrupts off.
+pub fn read() -> DateTime {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+
+        let mut raw = cmos.read_raw();
+        loop {
+            let again = cmos.read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+
+        let status_b = cmos.read(STATUS_B);
+        decode(raw, status_b)
+    })
+}
+
+
+// Enables the periodic interrupt on IRQ 8 at 32768 >> (rate - 1) Hz.
+// `rate` has to be within 3..=15 (8192Hz down to 2Hz).
+pub fn enable_periodic_interrupt(rate: u8) {
+    assert!((3..=15).contains(&rate), "RTC rate {} out of range", rate);
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        unsafe {
+            let status_a = cmos.read(STATUS_A);
+            cmos.write(STATUS_A, (status_a & 0xF0) | rate);
+            let status_b = cmos.read(STATUS_B);
+            cmos.write(STATUS_B, status_b | PERIODIC_INTERRUPT);
+        }
+        // a pending interrupt that isn't acknowledged blocks all further ones
+        cmos.read(STATUS_C);
+
+        unsafe { interrupts::enable_irq(InterruptIndex::Rtc); }
+    });
+}
+
+pub fn disable_periodic_interrupt() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        unsafe {
+            let status_b = cmos.read(STATUS_B);
+            cmos.write(STATUS_B, status_b & !PERIODIC_INTERRUPT);
+        }
+    });
+}
+
+// periodic interrupts received so far
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+// Called by the IRQ 8 handler.
+pub fn handle_interrupt() {
+    // reading status C acknowledges the interrupt on the RTC side
+    CMOS.lock().read(STATUS_C);
+    PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+
+#[test_case]
+fn test_decode_bcd_12_hour() {
+    // 11:59:58pm, 31.12.(20)24 in BCD
+    let time = decode([0x58, 0x59, 0x11 | HOUR_PM, 0x31, 0x12, 0x24], 0);
+    assert_eq!(time, DateTime { year: 2024, month: 12, day: 31, hour: 23, minute: 59, second: 58 });
+
+    // 12am is midnight
+    assert_eq!(decode([0, 0, 0x12, 1, 1, 0x25], 0).hour, 0);
+    assert_eq!(decode([0, 0, 0x12 | HOUR_PM, 1, 1, 0x25], 0).hour, 12);
+}
+
+#[test_case]
+fn test_decode_binary_24_hour() {
+    let time = decode([5, 4, 17, 2, 3, 25], BINARY | HOUR_24);
+    assert_eq!(time, DateTime { year: 2025, month: 3, day: 2, hour: 17, minute: 4, second: 5 });
+}
+
+#[test_case]
+fn test_unix_timestamp() {
+    let epoch = DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+    assert_eq!(epoch.unix_timestamp(), 0);
+
+    let leap_day = DateTime { year: 2024, month: 2, day: 29, hour: 12, minute: 30, second: 15 };
+    assert_eq!(leap_day.unix_timestamp(), 1_709_209_815);
+}
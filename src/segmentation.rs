@@ -1,8 +1,11 @@
-use core::fmt;
+use core::{arch::asm, fmt};
+
+use bit_field::BitField;
+use x86_64::{registers::model_specific::Msr, PrivilegeLevel, VirtAddr};
 
 
 // Segement Selector := it specifies which element to load into a segment from the descriptor table( i.e., is a index to LDT or GDT with some flags)
-#[derive(Clone, Copy, PartialEq, Eq, Debug, PartialOrd, Ord, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct SegmentSelector( pub u16 );
 
@@ -17,7 +20,7 @@ pub trait Segment64: Segment {
     const BASE: Msr;  // contains our segment base. This MSR can be used to set the base(Model Specific Register)
 
     fn read_base() -> VirtAddr;  // READS the ssegment base address
-    unsafe fn write_base(base: VirtAddress);
+    unsafe fn write_base(base: VirtAddr);
 
 }
 
@@ -29,7 +32,7 @@ impl SegmentSelector {
         SegmentSelector( (index << 3) | (rpl as u16) )
     }
 
-    pub const NULL: Self = Self::ne(0, PrivilegeLevel::Ring0);
+    pub const NULL: Self = Self::new(0, PrivilegeLevel::Ring0);
 
 
     // returns GDT index
@@ -41,12 +44,12 @@ impl SegmentSelector {
     // returns our requested privilege level
     #[inline]
     pub fn rpl(self) -> u16 {
-        self.0 >> 3
+        self.0.get_bits(0..2)
     }
 
     #[inline]
     pub fn set_rpl(&mut self, rpl: PrivilegeLevel) {
-        self.0.set_bits(0..2, rpl as u16)
+        self.0.set_bits(0..2, rpl as u16);
     }
 
 }
@@ -63,6 +66,32 @@ impl fmt::Debug for SegmentSelector {
 // most fileds in Code Segment Register are unused in 64-bit long mode, some of them must be set to a specific value
 pub struct CS;
 
+impl Segment for CS {
+    #[inline]
+    fn get_reg() -> SegmentSelector {
+        let segment: u16;
+        unsafe { asm!("mov {0:x}, cs", out(reg) segment, options(nomem, nostack, preserves_flags)) };
+        SegmentSelector(segment)
+    }
+
+    // cs can't be written with mov, it takes a far return to the next instruction
+    #[inline]
+    unsafe fn set_reg(sel: SegmentSelector) {
+        unsafe {
+            asm!(
+                "push {sel}",
+                "lea {tmp}, [2f + rip]",
+                "push {tmp}",
+                "retfq",
+                "2:",
+                sel = in(reg) u64::from(sel.0),
+                tmp = lateout(reg) _,
+                options(preserves_flags),
+            );
+        }
+    }
+}
+
 // Entirely unused in 64-bit long mode; setting the segment register does nothing
 pub struct SS;
 
@@ -13,7 +13,9 @@ lazy_static! {
 #[doc(hidden)]
 pub fn _print( args: ::core::fmt::Arguments ) {
     use core::fmt::Write;
-    SERIAL1.lock().write_fmt(args).expect("Serial Printing failed");
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        SERIAL1.lock().write_fmt(args).expect("Serial Printing failed");
+    });
 }
 
 #[macro_export]
@@ -0,0 +1,361 @@
+// Slab caches for fixed size kernel objects.
+// Each cache takes whole 4KiB frames from the frame allocator (reached
+// through the physical memory mapping) and cuts them into slots for one
+// type. Objects are built by the constructor when a slab is created and
+// stay constructed while they sit free in the cache, so the caller gets
+// them back in the state it left them. The destructor only runs when a
+// slab is handed back to the frame allocator by `shrink`.
+
+use core::{marker::PhantomData, mem, ops::{Deref, DerefMut}, ptr::{self, NonNull}};
+
+use spin::Mutex;
+
+use crate::{addr, memory, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, page::{PageSize, Size4Kib}, phys_frame::PhysFrame}};
+
+
+const SLAB_SIZE: usize = Size4Kib::SIZE as usize;
+
+// one bit per slot, set when the slot is free
+const BITMAP_WORDS: usize = 8;
+const MAX_OBJECTS: usize = BITMAP_WORDS * 64;
+
+// Sits at the start of every slab frame, the objects follow.
+struct SlabHeader {
+    next: Option<NonNull<SlabHeader>>,
+    frame: PhysFrame,
+    in_use: usize,
+    free: [u64; BITMAP_WORDS],
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub objects_per_slab: usize,
+    pub slabs: usize,
+    pub objects_in_use: usize,
+    pub objects_free: usize,
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+struct Inner {
+    slabs: Option<NonNull<SlabHeader>>,
+    slab_count: usize,
+    in_use: usize,
+    allocations: u64,
+    frees: u64,
+}
+
+pub struct SlabCache<T> {
+    name: &'static str,
+    constructor: fn() -> T,
+    destructor: Option<fn(&mut T)>,
+    inner: Mutex<Inner>,
+    _marker: PhantomData<T>,
+}
+
+// the slabs are only ever touched with the lock held
+unsafe impl<T: Send> Send for SlabCache<T> {}
+unsafe impl<T: Send> Sync for SlabCache<T> {}
+
+impl<T> SlabCache<T> {
+
+    const SLOT_SIZE: usize = addr::align_up(if mem::size_of::<T>() == 0 { 1 } else { mem::size_of::<T>() } as u64, mem::align_of::<T>() as u64) as usize;
+    const OBJECTS_START: usize = addr::align_up(mem::size_of::<SlabHeader>() as u64, mem::align_of::<T>() as u64) as usize;
+    const OBJECTS_PER_SLAB: usize = {
+        assert!(Self::OBJECTS_START + Self::SLOT_SIZE <= SLAB_SIZE, "type too big for a slab");
+        let objects = (SLAB_SIZE - Self::OBJECTS_START) / Self::SLOT_SIZE;
+        if objects > MAX_OBJECTS { MAX_OBJECTS } else { objects }
+    };
+
+    // `constructor` builds objects when a slab is created, `destructor`
+    // cleans them up (before they are dropped) when it is released.
+    pub const fn new(name: &'static str, constructor: fn() -> T, destructor: Option<fn(&mut T)>) -> Self {
+        SlabCache {
+            name,
+            constructor,
+            destructor,
+            inner: Mutex::new(Inner { slabs: None, slab_count: 0, in_use: 0, allocations: 0, frees: 0 }),
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // A constructed object, given back to the cache when the box is dropped.
+    // None if no frame is left, even after shrinking all registered caches.
+    pub fn alloc(&self) -> Option<SlabBox<'_, T>> {
+        self.alloc_raw().map(|ptr| SlabBox { cache: self, ptr })
+    }
+
+    pub fn alloc_raw(&self) -> Option<NonNull<T>> {
+        if let Some(ptr) = self.inner.lock().take_free_object::<T>() {
+            return Some(ptr);
+        }
+
+        // the lock is dropped while getting a frame, `shrink_all` may come
+        // back here
+        let slab = self.new_slab()?;
+        let mut inner = self.inner.lock();
+        unsafe { (*slab.as_ptr()).next = inner.slabs };
+        inner.slabs = Some(slab);
+        inner.slab_count += 1;
+        inner.take_free_object::<T>()
+    }
+
+    // unsafe: `ptr` must come from `alloc_raw` of this cache and must not be
+    // used afterwards. The object should be back in its constructed state.
+    pub unsafe fn free_raw(&self, ptr: NonNull<T>) {
+        let mut inner = self.inner.lock();
+        let slab = unsafe { &mut *((ptr.as_ptr() as usize & !(SLAB_SIZE - 1)) as *mut SlabHeader) };
+        let index = (ptr.as_ptr() as usize - slab as *mut SlabHeader as usize - Self::OBJECTS_START) / Self::SLOT_SIZE;
+
+        assert!(slab.free[index / 64] & (1 << (index % 64)) == 0, "double free in slab cache {}", self.name);
+        slab.free[index / 64] |= 1 << (index % 64);
+        slab.in_use -= 1;
+        inner.in_use -= 1;
+        inner.frees += 1;
+    }
+
+    // Gives the frames of all completely free slabs back, returns how many.
+    pub fn shrink(&self) -> usize {
+        let mut released = 0;
+        let mut inner = self.inner.lock();
+
+        let mut link: *mut Option<NonNull<SlabHeader>> = &mut inner.slabs;
+        unsafe {
+            while let Some(slab) = *link {
+                let header = slab.as_ptr();
+                if (*header).in_use == 0 {
+                    *link = (*header).next;
+                    self.release_slab(slab);
+                    released += 1;
+                } else {
+                    link = &mut (*header).next;
+                }
+            }
+        }
+
+        inner.slab_count -= released;
+        released
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let inner = self.inner.lock();
+        SlabStats {
+            name: self.name,
+            object_size: Self::SLOT_SIZE,
+            objects_per_slab: Self::OBJECTS_PER_SLAB,
+            slabs: inner.slab_count,
+            objects_in_use: inner.in_use,
+            objects_free: inner.slab_count * Self::OBJECTS_PER_SLAB - inner.in_use,
+            allocations: inner.allocations,
+            frees: inner.frees,
+        }
+    }
+
+    // Takes a frame and fills it with constructed objects.
+    fn new_slab(&self) -> Option<NonNull<SlabHeader>> {
+        let frame = allocate_frame()?;
+        let virt = memory::phys_to_virt(frame.start_addr_of_physframe()).expect("physical memory not mapped");
+        let header = virt.as_u64() as *mut SlabHeader;
+
+        let mut free = [0u64; BITMAP_WORDS];
+        for index in 0..Self::OBJECTS_PER_SLAB {
+            free[index / 64] |= 1 << (index % 64);
+            unsafe { Self::object(header, index).write((self.constructor)()) };
+        }
+
+        unsafe { header.write(SlabHeader { next: None, frame, in_use: 0, free }) };
+        NonNull::new(header)
+    }
+
+    // unsafe: the slab must be unlinked and all its objects free.
+    unsafe fn release_slab(&self, slab: NonNull<SlabHeader>) {
+        let header = slab.as_ptr();
+        for index in 0..Self::OBJECTS_PER_SLAB {
+            let object = Self::object(header, index);
+            unsafe {
+                if let Some(destructor) = self.destructor {
+                    destructor(&mut *object);
+                }
+                ptr::drop_in_place(object);
+            }
+        }
+
+        let frame = unsafe { (*header).frame };
+        if let Some(allocator) = memory::FRAME_ALLOCATOR.lock().as_mut() {
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+    }
+
+    #[inline]
+    fn object(header: *mut SlabHeader, index: usize) -> *mut T {
+        (header as usize + Self::OBJECTS_START + index * Self::SLOT_SIZE) as *mut T
+    }
+}
+
+impl Inner {
+    // first free slot of any slab
+    fn take_free_object<T>(&mut self) -> Option<NonNull<T>> {
+        let mut current = self.slabs;
+        while let Some(slab) = current {
+            let header = unsafe { &mut *slab.as_ptr() };
+            if let Some(word) = header.free.iter().position(|&word| word != 0) {
+                let bit = header.free[word].trailing_zeros() as usize;
+                header.free[word] &= !(1 << bit);
+                header.in_use += 1;
+                self.in_use += 1;
+                self.allocations += 1;
+                return NonNull::new(SlabCache::<T>::object(header, word * 64 + bit));
+            }
+            current = header.next;
+        }
+        None
+    }
+}
+
+
+// Owned object from a slab cache, like a `Box`.
+pub struct SlabBox<'a, T> {
+    cache: &'a SlabCache<T>,
+    ptr: NonNull<T>,
+}
+
+impl<T> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        unsafe { self.cache.free_raw(self.ptr) };
+    }
+}
+
+
+// Caches that `shrink_all` can squeeze when frames run out.
+pub trait Shrink: Sync {
+    fn shrink(&self) -> usize;
+}
+
+impl<T: Send> Shrink for SlabCache<T> {
+    fn shrink(&self) -> usize {
+        SlabCache::shrink(self)
+    }
+}
+
+const MAX_REGISTERED: usize = 32;
+
+static REGISTERED: Mutex<[Option<&'static dyn Shrink>; MAX_REGISTERED]> = Mutex::new([None; MAX_REGISTERED]);
+
+// Makes a static cache give back its free slabs under memory pressure.
+pub fn register(cache: &'static dyn Shrink) {
+    let mut registered = REGISTERED.lock();
+    let slot = registered.iter_mut().find(|slot| slot.is_none()).expect("too many slab caches registered");
+    *slot = Some(cache);
+}
+
+// Shrinks every registered cache, returns the number of frames freed.
+pub fn shrink_all() -> usize {
+    // copied out, so caches can register while we shrink
+    let registered = *REGISTERED.lock();
+    registered.iter().flatten().map(|cache| cache.shrink()).sum()
+}
+
+// A frame for a new slab. Out of frames, the registered caches are shrunk
+// and we try once more.
+fn allocate_frame() -> Option<PhysFrame> {
+    let frame = memory::FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame();
+    match frame {
+        Some(frame) => Some(frame),
+        None if shrink_all() > 0 => memory::FRAME_ALLOCATOR.lock().as_mut()?.allocate_frame(),
+        None => None,
+    }
+}
+
+
+#[cfg(test)]
+#[derive(Debug)]
+struct TestObject {
+    id: u64,
+    payload: [u64; 7],
+}
+
+#[cfg(test)]
+fn test_object() -> TestObject {
+    TestObject { id: 0, payload: [0xAA; 7] }
+}
+
+#[test_case]
+fn test_objects_are_constructed_and_distinct() {
+    static CACHE: SlabCache<TestObject> = SlabCache::new("test", test_object, None);
+
+    let mut a = CACHE.alloc().unwrap();
+    let b = CACHE.alloc().unwrap();
+    assert_eq!(a.payload, [0xAA; 7]);
+    assert_ne!(&*a as *const TestObject, &*b as *const TestObject);
+
+    a.id = 1;
+    assert_eq!(b.id, 0);
+
+    let stats = CACHE.stats();
+    assert_eq!(stats.slabs, 1);
+    assert_eq!(stats.objects_in_use, 2);
+    assert_eq!(stats.objects_in_use + stats.objects_free, stats.objects_per_slab);
+}
+
+#[test_case]
+fn test_cache_grows_and_shrinks() {
+    static CACHE: SlabCache<TestObject> = SlabCache::new("test", test_object, None);
+
+    let per_slab = CACHE.stats().objects_per_slab;
+    let mut objects = [const { None }; 80];
+    for object in objects.iter_mut() {
+        *object = CACHE.alloc();
+        assert!(object.is_some());
+    }
+    assert_eq!(CACHE.stats().slabs, 80usize.div_ceil(per_slab));
+
+    // busy slabs stay
+    assert_eq!(CACHE.shrink(), 0);
+
+    for object in objects.iter_mut() {
+        *object = None;
+    }
+    let stats = CACHE.stats();
+    assert_eq!((stats.objects_in_use, stats.allocations, stats.frees), (0, 80, 80));
+    assert_eq!(CACHE.shrink(), 80usize.div_ceil(per_slab));
+    assert_eq!(CACHE.stats().slabs, 0);
+}
+
+#[test_case]
+fn test_destructor_runs_on_shrink() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static DESTROYED: AtomicUsize = AtomicUsize::new(0);
+    static CACHE: SlabCache<TestObject> = SlabCache::new("test", test_object, Some(|_| { DESTROYED.fetch_add(1, Ordering::SeqCst); }));
+
+    // freed objects keep their state and aren't destroyed yet
+    let mut object = CACHE.alloc().unwrap();
+    object.id = 42;
+    drop(object);
+    assert_eq!(DESTROYED.load(Ordering::SeqCst), 0);
+    assert_eq!(CACHE.alloc().unwrap().id, 42);
+
+    assert_eq!(CACHE.shrink(), 1);
+    assert_eq!(DESTROYED.load(Ordering::SeqCst), CACHE.stats().objects_per_slab);
+}
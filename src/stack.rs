@@ -0,0 +1,174 @@
+// Kernel stacks with guard pages.
+// The stack region is cut into fixed size virtual slots. The first page of
+// every slot stays unmapped and the stack is mapped right above it, so a
+// stack growing past its end faults on the guard page instead of silently
+// running into whatever lies below.
+
+use spin::Mutex;
+
+use crate::{addr::VirtualAddr, memory, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::{MapToError, Mapper}, page::{Page, PageSize, Size4Kib}, page_table::PageTableFlags}};
+
+
+pub const STACK_REGION_START: u64 = 0x_7777_0000_0000;
+const SLOT_SIZE: u64 = 1024 * 1024;
+const MAX_STACKS: usize = 512;
+pub const STACK_REGION_END: u64 = STACK_REGION_START + SLOT_SIZE * MAX_STACKS as u64;
+
+// everything but the guard page
+pub const MAX_STACK_PAGES: u64 = SLOT_SIZE / Size4Kib::SIZE - 1;
+
+// bit set = slot in use
+static SLOTS: Mutex<[u64; MAX_STACKS / 64]> = Mutex::new([0; MAX_STACKS / 64]);
+
+
+#[derive(Debug)]
+pub struct KernelStack {
+    slot: usize,
+    pages: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackError {
+    NoSlotLeft,
+    TooBig,
+    Map(MapToError<Size4Kib>),
+}
+
+impl From<MapToError<Size4Kib>> for StackError {
+    fn from(err: MapToError<Size4Kib>) -> Self {
+        StackError::Map(err)
+    }
+}
+
+impl KernelStack {
+
+    #[inline]
+    fn slot_start(&self) -> u64 {
+        STACK_REGION_START + self.slot as u64 * SLOT_SIZE
+    }
+
+    #[inline]
+    pub fn guard_page(&self) -> Page {
+        Page::containing_address(VirtualAddr::new(self.slot_start()))
+    }
+
+    // lowest usable address
+    #[inline]
+    pub fn bottom(&self) -> VirtualAddr {
+        VirtualAddr::new(self.slot_start() + Size4Kib::SIZE)
+    }
+
+    // initial stack pointer, stacks grow down from here
+    #[inline]
+    pub fn top(&self) -> VirtualAddr {
+        VirtualAddr::new(self.bottom().as_u64() + self.size())
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.pages * Size4Kib::SIZE
+    }
+}
+
+
+// Maps a stack of `pages` pages with an unmapped guard page below it.
+pub fn allocate(pages: u64) -> Result<KernelStack, StackError> {
+    if pages == 0 || pages > MAX_STACK_PAGES {
+        return Err(StackError::TooBig);
+    }
+
+    let slot = {
+        let mut slots = SLOTS.lock();
+        let word = slots.iter().position(|&word| word != u64::MAX).ok_or(StackError::NoSlotLeft)?;
+        let bit = slots[word].trailing_ones() as usize;
+        slots[word] |= 1 << bit;
+        word * 64 + bit
+    };
+    let mut stack = KernelStack { slot, pages: 0 };
+
+    let result = map_pages(&mut stack, pages);
+    if let Err(err) = result {
+        // undo whatever got mapped
+        unsafe { free(stack) };
+        return Err(err.into());
+    }
+    Ok(stack)
+}
+
+// maps the pages one by one, `stack.pages` counts the ones that worked
+fn map_pages(stack: &mut KernelStack, pages: u64) -> Result<(), MapToError<Size4Kib>> {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().expect("memory::init wasn't called");
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+
+    let first_page = stack.guard_page() + 1;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    while stack.pages < pages {
+        let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+        let flush = unsafe { mapper.map_to(first_page + stack.pages, frame, flags, frame_allocator) }.inspect_err(|_| {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        })?;
+        flush.flush();
+        stack.pages += 1;
+    }
+    Ok(())
+}
+
+// Unmaps the stack and gives its frames and slot back.
+//
+// unsafe: nothing may run on the stack anymore, and nothing may point into it.
+pub unsafe fn free(stack: KernelStack) {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().expect("memory::init wasn't called");
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+
+    let first_page = stack.guard_page() + 1;
+    for i in 0..stack.pages {
+        let (frame, flush) = mapper.unmap(first_page + i).expect("stack page not mapped");
+        flush.flush();
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+
+    SLOTS.lock()[stack.slot / 64] &= !(1 << (stack.slot % 64));
+}
+
+// Whether `addr` is on the guard page of a stack, i.e. some stack overflowed.
+pub fn is_guard_page(addr: VirtualAddr) -> bool {
+    let addr = addr.as_u64();
+    (STACK_REGION_START..STACK_REGION_END).contains(&addr) && (addr - STACK_REGION_START) % SLOT_SIZE < Size4Kib::SIZE
+}
+
+
+#[test_case]
+fn test_stack_is_mapped_with_guard_below() {
+    use crate::structures::mapper::{Translate, TranslateResult};
+
+    let stack = allocate(4).unwrap();
+    assert_eq!(stack.top().as_u64() - stack.bottom().as_u64(), 4 * 4096);
+    assert!(is_guard_page(stack.guard_page().start_address()));
+    assert!(!is_guard_page(stack.bottom()));
+
+    {
+        let page_table = memory::PAGE_TABLE.lock();
+        let mapper = page_table.as_ref().unwrap();
+        assert_eq!(mapper.translate(stack.guard_page().start_address()), TranslateResult::NotMapped);
+        assert!(mapper.translate_addr(stack.bottom()).is_some());
+        assert!(mapper.translate_addr(VirtualAddr::new(stack.top().as_u64() - 8)).is_some());
+    }
+
+    // the whole stack is writable
+    let words = stack.size() as usize / 8;
+    let base = stack.bottom().as_u64() as *mut u64;
+    for i in 0..words {
+        unsafe { base.add(i).write_volatile(i as u64) };
+    }
+
+    let slot = stack.slot;
+    unsafe { free(stack) };
+    // the slot is handed out again
+    let again = allocate(1).unwrap();
+    assert_eq!(again.slot, slot);
+    unsafe { free(again) };
+}
@@ -0,0 +1,24 @@
+use crate::structures::{page::{PageSize, Size4Kib}, phys_frame::PhysFrame};
+
+// Hands out physical frames.
+//
+// unsafe: implementations must only return frames that are unused and
+// never return the same frame twice (until it was deallocated).
+pub unsafe trait FrameAllocator<S: PageSize = Size4Kib> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>>;
+}
+
+// Takes back frames handed out by a `FrameAllocator`.
+pub trait FrameDeallocator<S: PageSize = Size4Kib> {
+    // unsafe: the frame must have been allocated by this allocator and
+    // must not be in use anymore.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>);
+}
+
+// Usage numbers of an allocator, in frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameStats {
+    pub total: u64,
+    pub used: u64,
+    pub free: u64,
+}
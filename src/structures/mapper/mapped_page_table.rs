@@ -0,0 +1,419 @@
+use crate::{addr::VirtualAddr, structures::{frame_alloc::FrameAllocator, page::{Page, PageSize, Size1GiB, Size2Mib, Size4Kib}, page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags}, phys_frame::PhysFrame}};
+
+use super::{huge_translation, FlagUpdateError, MapToError, MappedFrame, Mapper, MapperFlush, TranslateError, TranslateResult, Translate, UnmapError};
+
+
+// Tells the mapper where a page table frame can be accessed in virtual memory.
+//
+// unsafe: the returned pointer must point to the page table stored in `frame`.
+pub unsafe trait PageTableFrameMapping {
+    fn frame_to_pointer(&self, frame: PhysFrame) -> *mut PageTable;
+}
+
+
+// Mapper that reaches every page table through a `PageTableFrameMapping`.
+pub struct MappedPageTable<'a, P: PageTableFrameMapping> {
+    page_table_walker: PageTableWalker<P>,
+    level_4_table: &'a mut PageTable,
+}
+
+impl<'a, P: PageTableFrameMapping> MappedPageTable<'a, P> {
+
+    // unsafe: `level_4_table` has to be a valid level 4 table and all page
+    // table frames must be reachable through `page_table_frame_mapping`.
+    #[inline]
+    pub unsafe fn new(level_4_table: &'a mut PageTable, page_table_frame_mapping: P) -> Self {
+        MappedPageTable {
+            page_table_walker: unsafe { PageTableWalker::new(page_table_frame_mapping) },
+            level_4_table,
+        }
+    }
+
+    #[inline]
+    pub fn level_4_table(&self) -> &PageTable {
+        self.level_4_table
+    }
+
+    #[inline]
+    pub fn level_4_table_mut(&mut self) -> &mut PageTable {
+        self.level_4_table
+    }
+
+    #[inline]
+    pub fn page_table_frame_mapping(&self) -> &P {
+        &self.page_table_walker.page_table_frame_mapping
+    }
+}
+
+
+impl<P: PageTableFrameMapping> Mapper<Size1GiB> for MappedPageTable<'_, P> {
+
+    unsafe fn map_to_with_table_flags<A>(
+        &mut self,
+        page: Page<Size1GiB>,
+        frame: PhysFrame<Size1GiB>,
+        flags: PageTableFlags,
+        parent_table_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<Size1GiB>, MapToError<Size1GiB>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        let walker = &self.page_table_walker;
+        let p3 = walker.create_next_table(&mut self.level_4_table[page.p4_index()], parent_table_flags, frame_allocator)?;
+
+        let entry = &mut p3[page.p3_index()];
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(PhysFrame::frame_containing_addr(entry.addr())));
+        }
+        entry.set_addr(frame.start_addr_of_physframe(), flags | PageTableFlags::HUGE_PAGE);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn unmap(&mut self, page: Page<Size1GiB>) -> Result<(PhysFrame<Size1GiB>, MapperFlush<Size1GiB>), UnmapError> {
+        let p3 = self.page_table_walker.next_table_mut(&mut self.level_4_table[page.p4_index()])?;
+        let entry = &mut p3[page.p3_index()];
+
+        // without HUGE_PAGE the entry points to a table of smaller pages
+        if !entry.flags().contains(PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE) {
+            return Err(UnmapError::PageNotMapped);
+        }
+        let frame = PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| UnmapError::InvalidFrameAddress(entry.addr()))?;
+
+        entry.set_unused();
+        Ok((frame, MapperFlush::new(page)))
+    }
+
+    unsafe fn update_flags(&mut self, page: Page<Size1GiB>, flags: PageTableFlags) -> Result<MapperFlush<Size1GiB>, FlagUpdateError> {
+        let p3 = self.page_table_walker.next_table_mut(&mut self.level_4_table[page.p4_index()])?;
+        let entry = &mut p3[page.p3_index()];
+
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(FlagUpdateError::PageNotMapped);
+        }
+        entry.set_flags(flags | PageTableFlags::HUGE_PAGE);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn translate_page(&self, page: Page<Size1GiB>) -> Result<PhysFrame<Size1GiB>, TranslateError> {
+        let p3 = self.page_table_walker.next_table(&self.level_4_table[page.p4_index()])?;
+        let entry = &p3[page.p3_index()];
+
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(TranslateError::PageNotMapped);
+        }
+        PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| TranslateError::InvalidFrameAddress(entry.addr()))
+    }
+}
+
+
+impl<P: PageTableFrameMapping> Mapper<Size2Mib> for MappedPageTable<'_, P> {
+
+    unsafe fn map_to_with_table_flags<A>(
+        &mut self,
+        page: Page<Size2Mib>,
+        frame: PhysFrame<Size2Mib>,
+        flags: PageTableFlags,
+        parent_table_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<Size2Mib>, MapToError<Size2Mib>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        let walker = &self.page_table_walker;
+        let p3 = walker.create_next_table(&mut self.level_4_table[page.p4_index()], parent_table_flags, frame_allocator)?;
+        let p2 = walker.create_next_table(&mut p3[page.p3_index()], parent_table_flags, frame_allocator)?;
+
+        let entry = &mut p2[page.p2_index()];
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(PhysFrame::frame_containing_addr(entry.addr())));
+        }
+        entry.set_addr(frame.start_addr_of_physframe(), flags | PageTableFlags::HUGE_PAGE);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn unmap(&mut self, page: Page<Size2Mib>) -> Result<(PhysFrame<Size2Mib>, MapperFlush<Size2Mib>), UnmapError> {
+        let walker = &self.page_table_walker;
+        let p3 = walker.next_table_mut(&mut self.level_4_table[page.p4_index()])?;
+        let p2 = walker.next_table_mut(&mut p3[page.p3_index()])?;
+        let entry = &mut p2[page.p2_index()];
+
+        // without HUGE_PAGE the entry points to a table of smaller pages
+        if !entry.flags().contains(PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE) {
+            return Err(UnmapError::PageNotMapped);
+        }
+        let frame = PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| UnmapError::InvalidFrameAddress(entry.addr()))?;
+
+        entry.set_unused();
+        Ok((frame, MapperFlush::new(page)))
+    }
+
+    unsafe fn update_flags(&mut self, page: Page<Size2Mib>, flags: PageTableFlags) -> Result<MapperFlush<Size2Mib>, FlagUpdateError> {
+        let walker = &self.page_table_walker;
+        let p3 = walker.next_table_mut(&mut self.level_4_table[page.p4_index()])?;
+        let p2 = walker.next_table_mut(&mut p3[page.p3_index()])?;
+        let entry = &mut p2[page.p2_index()];
+
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(FlagUpdateError::PageNotMapped);
+        }
+        entry.set_flags(flags | PageTableFlags::HUGE_PAGE);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn translate_page(&self, page: Page<Size2Mib>) -> Result<PhysFrame<Size2Mib>, TranslateError> {
+        let p3 = self.page_table_walker.next_table(&self.level_4_table[page.p4_index()])?;
+        let p2 = self.page_table_walker.next_table(&p3[page.p3_index()])?;
+        let entry = &p2[page.p2_index()];
+
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(TranslateError::PageNotMapped);
+        }
+        PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| TranslateError::InvalidFrameAddress(entry.addr()))
+    }
+}
+
+
+impl<P: PageTableFrameMapping> Mapper<Size4Kib> for MappedPageTable<'_, P> {
+
+    unsafe fn map_to_with_table_flags<A>(
+        &mut self,
+        page: Page<Size4Kib>,
+        frame: PhysFrame<Size4Kib>,
+        flags: PageTableFlags,
+        parent_table_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<Size4Kib>, MapToError<Size4Kib>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        let walker = &self.page_table_walker;
+        let p3 = walker.create_next_table(&mut self.level_4_table[page.p4_index()], parent_table_flags, frame_allocator)?;
+        let p2 = walker.create_next_table(&mut p3[page.p3_index()], parent_table_flags, frame_allocator)?;
+        let p1 = walker.create_next_table(&mut p2[page.p2_index()], parent_table_flags, frame_allocator)?;
+
+        let entry = &mut p1[page.p1_index()];
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(PhysFrame::frame_containing_addr(entry.addr())));
+        }
+        entry.set_frame(frame, flags);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn unmap(&mut self, page: Page<Size4Kib>) -> Result<(PhysFrame<Size4Kib>, MapperFlush<Size4Kib>), UnmapError> {
+        let walker = &self.page_table_walker;
+        let p3 = walker.next_table_mut(&mut self.level_4_table[page.p4_index()])?;
+        let p2 = walker.next_table_mut(&mut p3[page.p3_index()])?;
+        let p1 = walker.next_table_mut(&mut p2[page.p2_index()])?;
+        let entry = &mut p1[page.p1_index()];
+
+        let frame = entry.frame()?;
+
+        entry.set_unused();
+        Ok((frame, MapperFlush::new(page)))
+    }
+
+    unsafe fn update_flags(&mut self, page: Page<Size4Kib>, flags: PageTableFlags) -> Result<MapperFlush<Size4Kib>, FlagUpdateError> {
+        let walker = &self.page_table_walker;
+        let p3 = walker.next_table_mut(&mut self.level_4_table[page.p4_index()])?;
+        let p2 = walker.next_table_mut(&mut p3[page.p3_index()])?;
+        let p1 = walker.next_table_mut(&mut p2[page.p2_index()])?;
+        let entry = &mut p1[page.p1_index()];
+
+        if entry.is_unused() {
+            return Err(FlagUpdateError::PageNotMapped);
+        }
+        entry.set_flags(flags);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn translate_page(&self, page: Page<Size4Kib>) -> Result<PhysFrame<Size4Kib>, TranslateError> {
+        let p3 = self.page_table_walker.next_table(&self.level_4_table[page.p4_index()])?;
+        let p2 = self.page_table_walker.next_table(&p3[page.p3_index()])?;
+        let p1 = self.page_table_walker.next_table(&p2[page.p2_index()])?;
+        let entry = &p1[page.p1_index()];
+
+        if entry.is_unused() {
+            return Err(TranslateError::PageNotMapped);
+        }
+        PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| TranslateError::InvalidFrameAddress(entry.addr()))
+    }
+}
+
+
+impl<P: PageTableFrameMapping> Translate for MappedPageTable<'_, P> {
+
+    fn translate(&self, addr: VirtualAddr) -> TranslateResult {
+        let page = Page::<Size4Kib>::containing_address(addr);
+        let walker = &self.page_table_walker;
+
+        let p3 = match walker.next_table(&self.level_4_table[page.p4_index()]) {
+            Ok(table) => table,
+            Err(PageTableWalkError::NotMapped) => return TranslateResult::NotMapped,
+            Err(PageTableWalkError::MappedToHugePage) => panic!("level 4 entry has the huge page bit set"),
+        };
+
+        let p3_entry = &p3[page.p3_index()];
+        let p2 = match walker.next_table(p3_entry) {
+            Ok(table) => table,
+            Err(PageTableWalkError::NotMapped) => return TranslateResult::NotMapped,
+            Err(PageTableWalkError::MappedToHugePage) => return huge_translation::<Size1GiB>(p3_entry, addr, MappedFrame::Size1GiB),
+        };
+
+        let p2_entry = &p2[page.p2_index()];
+        let p1 = match walker.next_table(p2_entry) {
+            Ok(table) => table,
+            Err(PageTableWalkError::NotMapped) => return TranslateResult::NotMapped,
+            Err(PageTableWalkError::MappedToHugePage) => return huge_translation::<Size2Mib>(p2_entry, addr, MappedFrame::Size2Mib),
+        };
+
+        let p1_entry = &p1[page.p1_index()];
+        if p1_entry.is_unused() || !p1_entry.flags().contains(PageTableFlags::PRESENT) {
+            return TranslateResult::NotMapped;
+        }
+
+        match PhysFrame::<Size4Kib>::frame_from_start_addr(p1_entry.addr()) {
+            Ok(frame) => TranslateResult::Mapped {
+                frame: MappedFrame::Size4Kib(frame),
+                offset: addr.as_u64() % Size4Kib::SIZE,
+                flags: p1_entry.flags(),
+            },
+            Err(_) => TranslateResult::InvalidFrameAddress(p1_entry.addr()),
+        }
+    }
+}
+
+// Follows entries from one table level to the next.
+#[derive(Debug)]
+struct PageTableWalker<P: PageTableFrameMapping> {
+    page_table_frame_mapping: P,
+}
+
+impl<P: PageTableFrameMapping> PageTableWalker<P> {
+
+    #[inline]
+    unsafe fn new(page_table_frame_mapping: P) -> Self {
+        PageTableWalker { page_table_frame_mapping }
+    }
+
+    // table the entry points to.
+    #[inline]
+    fn next_table<'b>(&self, entry: &'b PageTableEntry) -> Result<&'b PageTable, PageTableWalkError> {
+        let ptr = self.page_table_frame_mapping.frame_to_pointer(entry.frame()?);
+        Ok(unsafe { &*ptr })
+    }
+
+    #[inline]
+    fn next_table_mut<'b>(&self, entry: &'b mut PageTableEntry) -> Result<&'b mut PageTable, PageTableWalkError> {
+        let ptr = self.page_table_frame_mapping.frame_to_pointer(entry.frame()?);
+        Ok(unsafe { &mut *ptr })
+    }
+
+    // Like `next_table_mut`, but allocates and zeroes a new table if the entry
+    // is unused. Existing entries get `insert_flags` added.
+    fn create_next_table<'b, A>(
+        &self,
+        entry: &'b mut PageTableEntry,
+        insert_flags: PageTableFlags,
+        allocator: &mut A,
+    ) -> Result<&'b mut PageTable, PageTableCreateError>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        let created = if entry.is_unused() {
+            let frame = allocator.allocate_frame().ok_or(PageTableCreateError::FrameAllocationFailed)?;
+            entry.set_frame(frame, insert_flags);
+            true
+        } else {
+            if !insert_flags.is_empty() && !entry.flags().contains(insert_flags) {
+                entry.set_flags(entry.flags() | insert_flags);
+            }
+            false
+        };
+
+        let table = match self.next_table_mut(entry) {
+            Ok(table) => table,
+            Err(PageTableWalkError::MappedToHugePage) => return Err(PageTableCreateError::MappedToHugePage),
+            Err(PageTableWalkError::NotMapped) => unreachable!("entry was just set"),
+        };
+
+        if created {
+            table.zero();
+        }
+        Ok(table)
+    }
+}
+
+
+#[derive(Debug)]
+enum PageTableWalkError {
+    NotMapped,
+    MappedToHugePage,
+}
+
+#[derive(Debug)]
+enum PageTableCreateError {
+    MappedToHugePage,
+    FrameAllocationFailed,
+}
+
+impl From<FrameError> for PageTableWalkError {
+    #[inline]
+    fn from(err: FrameError) -> Self {
+        match err {
+            FrameError::FrameNotPresent => PageTableWalkError::NotMapped,
+            FrameError::HugeFrame => PageTableWalkError::MappedToHugePage,
+        }
+    }
+}
+
+impl<S: PageSize> From<PageTableCreateError> for MapToError<S> {
+    #[inline]
+    fn from(err: PageTableCreateError) -> Self {
+        match err {
+            PageTableCreateError::MappedToHugePage => MapToError::ParentEntryHugePage,
+            PageTableCreateError::FrameAllocationFailed => MapToError::FrameAllocationFailed,
+        }
+    }
+}
+
+impl From<PageTableWalkError> for UnmapError {
+    #[inline]
+    fn from(err: PageTableWalkError) -> Self {
+        match err {
+            PageTableWalkError::MappedToHugePage => UnmapError::ParentEntryHugePage,
+            PageTableWalkError::NotMapped => UnmapError::PageNotMapped,
+        }
+    }
+}
+
+impl From<PageTableWalkError> for FlagUpdateError {
+    #[inline]
+    fn from(err: PageTableWalkError) -> Self {
+        match err {
+            PageTableWalkError::MappedToHugePage => FlagUpdateError::ParentEntryHugePage,
+            PageTableWalkError::NotMapped => FlagUpdateError::PageNotMapped,
+        }
+    }
+}
+
+impl From<PageTableWalkError> for TranslateError {
+    #[inline]
+    fn from(err: PageTableWalkError) -> Self {
+        match err {
+            PageTableWalkError::MappedToHugePage => TranslateError::ParentEntryHugePage,
+            PageTableWalkError::NotMapped => TranslateError::PageNotMapped,
+        }
+    }
+}
@@ -0,0 +1,227 @@
+// Mapping virtual pages to physical frames.
+// `Mapper` changes the page tables for one page size, `Translate` walks
+// them for any address. How the page table frames themselves are reached
+// is up to the implementation: `OffsetPageTable` goes through the
+// physical memory mapping, `RecursivePageTable` through a recursive P4 entry.
+
+use crate::{addr::{PhyAddr, VirtualAddr}, structures::{frame_alloc::FrameAllocator, page::{Page, PageSize, Size1GiB, Size2Mib, Size4Kib}, page_table::{FrameError, PageTableEntry, PageTableFlags}, phys_frame::PhysFrame}};
+
+pub use self::mapped_page_table::{MappedPageTable, PageTableFrameMapping};
+pub use self::offset_page_table::{OffsetPageTable, PhysOffset};
+pub use self::recursive_page_table::{InvalidPageTable, RecursivePageTable};
+
+mod mapped_page_table;
+mod offset_page_table;
+mod recursive_page_table;
+
+
+pub trait Mapper<S: PageSize> {
+
+    // Maps `page` to `frame`. Missing page tables are allocated from
+    // `frame_allocator` and get the PRESENT, WRITABLE and USERACCESSIBLE
+    // bits of `flags`.
+    //
+    // unsafe: the caller must make sure the mapping doesn't alias memory
+    // that is already in use, e.g. by mapping the same frame twice.
+    unsafe fn map_to<A>(
+        &mut self,
+        page: Page<S>,
+        frame: PhysFrame<S>,
+        flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<S>, MapToError<S>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        let parent_table_flags = flags & (PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USERACCESSIBLE);
+        unsafe { self.map_to_with_table_flags(page, frame, flags, parent_table_flags, frame_allocator) }
+    }
+
+    // Same as `map_to`, but newly created page tables get `parent_table_flags`
+    // and existing ones are extended with them.
+    //
+    // unsafe: see `map_to`.
+    unsafe fn map_to_with_table_flags<A>(
+        &mut self,
+        page: Page<S>,
+        frame: PhysFrame<S>,
+        flags: PageTableFlags,
+        parent_table_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<S>, MapToError<S>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized;
+
+    // Removes the mapping and returns the frame it pointed to. Empty page
+    // tables are not freed.
+    fn unmap(&mut self, page: Page<S>) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError>;
+
+    // unsafe: changing flags can break memory safety, e.g. making
+    // kernel code writable or a page in use non-present.
+    unsafe fn update_flags(&mut self, page: Page<S>, flags: PageTableFlags) -> Result<MapperFlush<S>, FlagUpdateError>;
+
+    // frame the page is mapped to.
+    fn translate_page(&self, page: Page<S>) -> Result<PhysFrame<S>, TranslateError>;
+}
+
+
+pub trait Translate {
+
+    // Walks the page tables for `addr`, huge pages included.
+    fn translate(&self, addr: VirtualAddr) -> TranslateResult;
+
+    // physical address `addr` is mapped to, if any.
+    #[inline]
+    fn translate_addr(&self, addr: VirtualAddr) -> Option<PhyAddr> {
+        match self.translate(addr) {
+            TranslateResult::Mapped { frame, offset, .. } => Some(PhyAddr::new(frame.start_address().as_u64() + offset)),
+            TranslateResult::NotMapped | TranslateResult::InvalidFrameAddress(_) => None,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranslateResult {
+    // `offset` is the offset of the address inside `frame`.
+    Mapped { frame: MappedFrame, offset: u64, flags: PageTableFlags },
+    NotMapped,
+    // an entry holds an address that isn't aligned to its frame size.
+    InvalidFrameAddress(PhyAddr),
+}
+
+// Frame of any size, as found by `Translate::translate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappedFrame {
+    Size4Kib(PhysFrame<Size4Kib>),
+    Size2Mib(PhysFrame<Size2Mib>),
+    Size1GiB(PhysFrame<Size1GiB>),
+}
+
+impl MappedFrame {
+
+    #[inline]
+    pub fn start_address(&self) -> PhyAddr {
+        match self {
+            MappedFrame::Size4Kib(frame) => frame.start_addr_of_physframe(),
+            MappedFrame::Size2Mib(frame) => frame.start_addr_of_physframe(),
+            MappedFrame::Size1GiB(frame) => frame.start_addr_of_physframe(),
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        match self {
+            MappedFrame::Size4Kib(_) => Size4Kib::SIZE,
+            MappedFrame::Size2Mib(_) => Size2Mib::SIZE,
+            MappedFrame::Size1GiB(_) => Size1GiB::SIZE,
+        }
+    }
+}
+
+
+// The TLB may still hold the old translation of a page after its entry
+// changed. Mapper functions hand out this token so the caller has to decide
+// between flushing the page now or knowingly skipping it (e.g. when the
+// whole TLB gets flushed afterwards anyway).
+#[derive(Debug)]
+#[must_use = "page table changes must be flushed or ignored"]
+pub struct MapperFlush<S: PageSize>(Page<S>);
+
+impl<S: PageSize> MapperFlush<S> {
+
+    #[inline]
+    pub(crate) fn new(page: Page<S>) -> Self {
+        MapperFlush(page)
+    }
+
+    // invalidates the page in the TLB of this cpu.
+    #[inline]
+    pub fn flush(self) {
+        crate::tlb::flush(self.0.start_address());
+    }
+
+    // doesn't flush. The TLB might keep the old mapping.
+    #[inline]
+    pub fn ignore(self) {}
+
+    #[inline]
+    pub fn page(&self) -> Page<S> {
+        self.0
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapToError<S: PageSize> {
+    // a page table was missing and no frame was left for it.
+    FrameAllocationFailed,
+    // a higher level entry maps a huge page that covers this page.
+    ParentEntryHugePage,
+    // the page is mapped already, to the given frame.
+    PageAlreadyMapped(PhysFrame<S>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnmapError {
+    ParentEntryHugePage,
+    PageNotMapped,
+    InvalidFrameAddress(PhyAddr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagUpdateError {
+    PageNotMapped,
+    ParentEntryHugePage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranslateError {
+    PageNotMapped,
+    ParentEntryHugePage,
+    InvalidFrameAddress(PhyAddr),
+}
+
+// a parent entry that isn't a table: missing or a huge page
+impl From<FrameError> for UnmapError {
+    #[inline]
+    fn from(err: FrameError) -> Self {
+        match err {
+            FrameError::FrameNotPresent => UnmapError::PageNotMapped,
+            FrameError::HugeFrame => UnmapError::ParentEntryHugePage,
+        }
+    }
+}
+
+impl From<FrameError> for FlagUpdateError {
+    #[inline]
+    fn from(err: FrameError) -> Self {
+        match err {
+            FrameError::FrameNotPresent => FlagUpdateError::PageNotMapped,
+            FrameError::HugeFrame => FlagUpdateError::ParentEntryHugePage,
+        }
+    }
+}
+
+impl From<FrameError> for TranslateError {
+    #[inline]
+    fn from(err: FrameError) -> Self {
+        match err {
+            FrameError::FrameNotPresent => TranslateError::PageNotMapped,
+            FrameError::HugeFrame => TranslateError::ParentEntryHugePage,
+        }
+    }
+}
+
+
+// translation for an entry that maps a huge page of size `S`.
+fn huge_translation<S: PageSize>(entry: &PageTableEntry, addr: VirtualAddr, mapped: fn(PhysFrame<S>) -> MappedFrame) -> TranslateResult {
+    match PhysFrame::<S>::frame_from_start_addr(entry.addr()) {
+        Ok(frame) => TranslateResult::Mapped {
+            frame: mapped(frame),
+            offset: addr.as_u64() % S::SIZE,
+            flags: entry.flags(),
+        },
+        Err(_) => TranslateResult::InvalidFrameAddress(entry.addr()),
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{addr::VirtualAddr, structures::{frame_alloc::FrameAllocator, page::{Page, PageSize, Size4Kib}, page_table::{PageTable, PageTableFlags}, phys_frame::PhysFrame}};
+
+use super::{FlagUpdateError, MapToError, MappedPageTable, Mapper, MapperFlush, PageTableFrameMapping, Translate, TranslateError, TranslateResult, UnmapError};
+
+
+// Mapper for when all of physical memory is mapped at a fixed offset, like
+// the bootloader does with `map_physical_memory`. Page table frames are
+// reached at `phys_offset + frame address`.
+pub struct OffsetPageTable<'a> {
+    inner: MappedPageTable<'a, PhysOffset>,
+}
+
+impl<'a> OffsetPageTable<'a> {
+
+    // unsafe: all of physical memory must be mapped at `phys_offset`, and
+    // `level_4_table` has to be a valid level 4 table.
+    #[inline]
+    pub unsafe fn new(level_4_table: &'a mut PageTable, phys_offset: VirtualAddr) -> Self {
+        OffsetPageTable { inner: unsafe { MappedPageTable::new(level_4_table, PhysOffset { offset: phys_offset }) } }
+    }
+
+    #[inline]
+    pub fn phys_offset(&self) -> VirtualAddr {
+        self.inner.page_table_frame_mapping().offset
+    }
+
+    #[inline]
+    pub fn level_4_table(&self) -> &PageTable {
+        self.inner.level_4_table()
+    }
+
+    #[inline]
+    pub fn level_4_table_mut(&mut self) -> &mut PageTable {
+        self.inner.level_4_table_mut()
+    }
+}
+
+
+// page table frames at a fixed offset from their physical address.
+#[derive(Debug, Clone, Copy)]
+pub struct PhysOffset {
+    offset: VirtualAddr,
+}
+
+unsafe impl PageTableFrameMapping for PhysOffset {
+    #[inline]
+    fn frame_to_pointer(&self, frame: PhysFrame) -> *mut PageTable {
+        (self.offset.as_u64() + frame.start_addr_of_physframe().as_u64()) as *mut PageTable
+    }
+}
+
+
+// everything is forwarded to the inner mapper
+impl<'a, S: PageSize> Mapper<S> for OffsetPageTable<'a>
+where
+    MappedPageTable<'a, PhysOffset>: Mapper<S>,
+{
+    #[inline]
+    unsafe fn map_to_with_table_flags<A>(
+        &mut self,
+        page: Page<S>,
+        frame: PhysFrame<S>,
+        flags: PageTableFlags,
+        parent_table_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<S>, MapToError<S>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        unsafe { self.inner.map_to_with_table_flags(page, frame, flags, parent_table_flags, frame_allocator) }
+    }
+
+    #[inline]
+    fn unmap(&mut self, page: Page<S>) -> Result<(PhysFrame<S>, MapperFlush<S>), UnmapError> {
+        self.inner.unmap(page)
+    }
+
+    #[inline]
+    unsafe fn update_flags(&mut self, page: Page<S>, flags: PageTableFlags) -> Result<MapperFlush<S>, FlagUpdateError> {
+        unsafe { self.inner.update_flags(page, flags) }
+    }
+
+    #[inline]
+    fn translate_page(&self, page: Page<S>) -> Result<PhysFrame<S>, TranslateError> {
+        self.inner.translate_page(page)
+    }
+}
+
+impl Translate for OffsetPageTable<'_> {
+    #[inline]
+    fn translate(&self, addr: VirtualAddr) -> TranslateResult {
+        self.inner.translate(addr)
+    }
+}
@@ -0,0 +1,384 @@
+use crate::{addr::{PageTableIndex, VirtualAddr}, memory, structures::{frame_alloc::FrameAllocator, page::{Page, PageSize, Size1GiB, Size2Mib, Size4Kib}, page_table::{FrameError, PageTable, PageTableEntry, PageTableFlags}, phys_frame::PhysFrame}};
+
+use super::{huge_translation, FlagUpdateError, MapToError, MappedFrame, Mapper, MapperFlush, Translate, TranslateError, TranslateResult, UnmapError};
+
+
+// Mapper using a recursive entry in the level 4 table. If P4 entry `r`
+// points back to the P4 itself, walking through `r` drops one level, so
+// every table is reachable at a fixed virtual address:
+//
+//   P4           r r r r
+//   P3 of i4     r r r i4
+//   P2 of i4 i3  r r i4 i3
+//   P1 ...       r i4 i3 i2
+//
+// That costs one 512GiB slot of the address space, but doesn't need the
+// whole physical memory mapped.
+pub struct RecursivePageTable<'a> {
+    p4: &'a mut PageTable,
+    recursive_index: PageTableIndex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidPageTable {
+    // the table isn't accessed through a recursive address.
+    NotRecursive,
+    // the recursive entry doesn't point to the active level 4 table.
+    NotActive,
+}
+
+impl<'a> RecursivePageTable<'a> {
+
+    // `table` must be the active level 4 table, accessed through the
+    // recursive address `r r r r`.
+    pub fn new(table: &'a mut PageTable) -> Result<Self, InvalidPageTable> {
+        let page = Page::<Size4Kib>::containing_address(VirtualAddr::new(table as *const PageTable as u64));
+        let recursive_index = page.p4_index();
+
+        if page.p3_index() != recursive_index || page.p2_index() != recursive_index || page.p1_index() != recursive_index {
+            return Err(InvalidPageTable::NotRecursive);
+        }
+        match table[recursive_index].frame() {
+            Ok(frame) if frame.start_addr_of_physframe() == memory::active_level_4_table_addr() => {}
+            _ => return Err(InvalidPageTable::NotActive),
+        }
+
+        Ok(RecursivePageTable { p4: table, recursive_index })
+    }
+
+    // unsafe: entry `recursive_index` of `table` must point to the table
+    // itself, and the table must be active.
+    #[inline]
+    pub unsafe fn new_unchecked(table: &'a mut PageTable, recursive_index: PageTableIndex) -> Self {
+        RecursivePageTable { p4: table, recursive_index }
+    }
+
+    #[inline]
+    pub fn recursive_index(&self) -> PageTableIndex {
+        self.recursive_index
+    }
+
+    #[inline]
+    pub fn level_4_table(&self) -> &PageTable {
+        self.p4
+    }
+
+    #[inline]
+    pub fn level_4_table_mut(&mut self) -> &mut PageTable {
+        self.p4
+    }
+
+    // virtual page of the table reached through the given four indices
+    fn table_page(&self, p4: PageTableIndex, p3: PageTableIndex, p2: PageTableIndex, p1: PageTableIndex) -> Page {
+        let addr = (u64::from(p4) << 39) | (u64::from(p3) << 30) | (u64::from(p2) << 21) | (u64::from(p1) << 12);
+        Page::containing_address(VirtualAddr::new_truncate(addr))
+    }
+
+    fn p3_page<S: PageSize>(&self, page: Page<S>) -> Page {
+        let r = self.recursive_index;
+        self.table_page(r, r, r, page.p4_index())
+    }
+
+    fn p2_page<S: PageSize>(&self, page: Page<S>) -> Page {
+        let r = self.recursive_index;
+        self.table_page(r, r, page.p4_index(), page.p3_index())
+    }
+
+    fn p1_page(&self, page: Page<Size4Kib>) -> Page {
+        let r = self.recursive_index;
+        self.table_page(r, page.p4_index(), page.p3_index(), page.p2_index())
+    }
+}
+
+
+// Table behind `entry`, found at `table_page`.
+//
+// unsafe: `table_page` must be the recursive address of that table.
+unsafe fn next_table<'b>(entry: &PageTableEntry, table_page: Page) -> Result<&'b mut PageTable, FrameError> {
+    entry.frame()?;
+    Ok(unsafe { &mut *(table_page.start_address().as_u64() as *mut PageTable) })
+}
+
+// Like `next_table`, but allocates and zeroes a new table if the entry is
+// unused. Existing entries get `insert_flags` added.
+//
+// unsafe: see `next_table`.
+unsafe fn create_next_table<'b, S, A>(
+    entry: &mut PageTableEntry,
+    table_page: Page,
+    insert_flags: PageTableFlags,
+    allocator: &mut A,
+) -> Result<&'b mut PageTable, MapToError<S>>
+where
+    S: PageSize,
+    A: FrameAllocator<Size4Kib> + ?Sized,
+{
+    let created = if entry.is_unused() {
+        let frame = allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+        entry.set_frame(frame, insert_flags);
+        true
+    } else {
+        if !insert_flags.is_empty() && !entry.flags().contains(insert_flags) {
+            entry.set_flags(entry.flags() | insert_flags);
+        }
+        false
+    };
+
+    let table = match unsafe { next_table(entry, table_page) } {
+        Ok(table) => table,
+        Err(FrameError::HugeFrame) => return Err(MapToError::ParentEntryHugePage),
+        Err(FrameError::FrameNotPresent) => unreachable!("entry was just set"),
+    };
+
+    if created {
+        // the recursive address may still be cached from an older table
+        MapperFlush::new(table_page).flush();
+        table.zero();
+    }
+    Ok(table)
+}
+
+
+impl Mapper<Size1GiB> for RecursivePageTable<'_> {
+
+    unsafe fn map_to_with_table_flags<A>(
+        &mut self,
+        page: Page<Size1GiB>,
+        frame: PhysFrame<Size1GiB>,
+        flags: PageTableFlags,
+        parent_table_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<Size1GiB>, MapToError<Size1GiB>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        let p3_page = self.p3_page(page);
+        let p3 = unsafe { create_next_table(&mut self.p4[page.p4_index()], p3_page, parent_table_flags, frame_allocator)? };
+
+        let entry = &mut p3[page.p3_index()];
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(PhysFrame::frame_containing_addr(entry.addr())));
+        }
+        entry.set_addr(frame.start_addr_of_physframe(), flags | PageTableFlags::HUGE_PAGE);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn unmap(&mut self, page: Page<Size1GiB>) -> Result<(PhysFrame<Size1GiB>, MapperFlush<Size1GiB>), UnmapError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let entry = &mut p3[page.p3_index()];
+
+        // without HUGE_PAGE the entry points to a table of smaller pages
+        if !entry.flags().contains(PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE) {
+            return Err(UnmapError::PageNotMapped);
+        }
+        let frame = PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| UnmapError::InvalidFrameAddress(entry.addr()))?;
+
+        entry.set_unused();
+        Ok((frame, MapperFlush::new(page)))
+    }
+
+    unsafe fn update_flags(&mut self, page: Page<Size1GiB>, flags: PageTableFlags) -> Result<MapperFlush<Size1GiB>, FlagUpdateError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let entry = &mut p3[page.p3_index()];
+
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(FlagUpdateError::PageNotMapped);
+        }
+        entry.set_flags(flags | PageTableFlags::HUGE_PAGE);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn translate_page(&self, page: Page<Size1GiB>) -> Result<PhysFrame<Size1GiB>, TranslateError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let entry = &p3[page.p3_index()];
+
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(TranslateError::PageNotMapped);
+        }
+        PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| TranslateError::InvalidFrameAddress(entry.addr()))
+    }
+}
+
+
+impl Mapper<Size2Mib> for RecursivePageTable<'_> {
+
+    unsafe fn map_to_with_table_flags<A>(
+        &mut self,
+        page: Page<Size2Mib>,
+        frame: PhysFrame<Size2Mib>,
+        flags: PageTableFlags,
+        parent_table_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<Size2Mib>, MapToError<Size2Mib>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        let (p3_page, p2_page) = (self.p3_page(page), self.p2_page(page));
+        let p3 = unsafe { create_next_table(&mut self.p4[page.p4_index()], p3_page, parent_table_flags, frame_allocator)? };
+        let p2 = unsafe { create_next_table(&mut p3[page.p3_index()], p2_page, parent_table_flags, frame_allocator)? };
+
+        let entry = &mut p2[page.p2_index()];
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(PhysFrame::frame_containing_addr(entry.addr())));
+        }
+        entry.set_addr(frame.start_addr_of_physframe(), flags | PageTableFlags::HUGE_PAGE);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn unmap(&mut self, page: Page<Size2Mib>) -> Result<(PhysFrame<Size2Mib>, MapperFlush<Size2Mib>), UnmapError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let p2 = unsafe { next_table(&p3[page.p3_index()], self.p2_page(page))? };
+        let entry = &mut p2[page.p2_index()];
+
+        // without HUGE_PAGE the entry points to a table of smaller pages
+        if !entry.flags().contains(PageTableFlags::PRESENT | PageTableFlags::HUGE_PAGE) {
+            return Err(UnmapError::PageNotMapped);
+        }
+        let frame = PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| UnmapError::InvalidFrameAddress(entry.addr()))?;
+
+        entry.set_unused();
+        Ok((frame, MapperFlush::new(page)))
+    }
+
+    unsafe fn update_flags(&mut self, page: Page<Size2Mib>, flags: PageTableFlags) -> Result<MapperFlush<Size2Mib>, FlagUpdateError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let p2 = unsafe { next_table(&p3[page.p3_index()], self.p2_page(page))? };
+        let entry = &mut p2[page.p2_index()];
+
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(FlagUpdateError::PageNotMapped);
+        }
+        entry.set_flags(flags | PageTableFlags::HUGE_PAGE);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn translate_page(&self, page: Page<Size2Mib>) -> Result<PhysFrame<Size2Mib>, TranslateError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let p2 = unsafe { next_table(&p3[page.p3_index()], self.p2_page(page))? };
+        let entry = &p2[page.p2_index()];
+
+        if entry.is_unused() || !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(TranslateError::PageNotMapped);
+        }
+        PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| TranslateError::InvalidFrameAddress(entry.addr()))
+    }
+}
+
+
+impl Mapper<Size4Kib> for RecursivePageTable<'_> {
+
+    unsafe fn map_to_with_table_flags<A>(
+        &mut self,
+        page: Page<Size4Kib>,
+        frame: PhysFrame<Size4Kib>,
+        flags: PageTableFlags,
+        parent_table_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<MapperFlush<Size4Kib>, MapToError<Size4Kib>>
+    where
+        A: FrameAllocator<Size4Kib> + ?Sized,
+    {
+        let (p3_page, p2_page, p1_page) = (self.p3_page(page), self.p2_page(page), self.p1_page(page));
+        let p3 = unsafe { create_next_table(&mut self.p4[page.p4_index()], p3_page, parent_table_flags, frame_allocator)? };
+        let p2 = unsafe { create_next_table(&mut p3[page.p3_index()], p2_page, parent_table_flags, frame_allocator)? };
+        let p1 = unsafe { create_next_table(&mut p2[page.p2_index()], p1_page, parent_table_flags, frame_allocator)? };
+
+        let entry = &mut p1[page.p1_index()];
+        if !entry.is_unused() {
+            return Err(MapToError::PageAlreadyMapped(PhysFrame::frame_containing_addr(entry.addr())));
+        }
+        entry.set_frame(frame, flags);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn unmap(&mut self, page: Page<Size4Kib>) -> Result<(PhysFrame<Size4Kib>, MapperFlush<Size4Kib>), UnmapError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let p2 = unsafe { next_table(&p3[page.p3_index()], self.p2_page(page))? };
+        let p1 = unsafe { next_table(&p2[page.p2_index()], self.p1_page(page))? };
+        let entry = &mut p1[page.p1_index()];
+
+        let frame = entry.frame()?;
+
+        entry.set_unused();
+        Ok((frame, MapperFlush::new(page)))
+    }
+
+    unsafe fn update_flags(&mut self, page: Page<Size4Kib>, flags: PageTableFlags) -> Result<MapperFlush<Size4Kib>, FlagUpdateError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let p2 = unsafe { next_table(&p3[page.p3_index()], self.p2_page(page))? };
+        let p1 = unsafe { next_table(&p2[page.p2_index()], self.p1_page(page))? };
+        let entry = &mut p1[page.p1_index()];
+
+        if entry.is_unused() {
+            return Err(FlagUpdateError::PageNotMapped);
+        }
+        entry.set_flags(flags);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    fn translate_page(&self, page: Page<Size4Kib>) -> Result<PhysFrame<Size4Kib>, TranslateError> {
+        let p3 = unsafe { next_table(&self.p4[page.p4_index()], self.p3_page(page))? };
+        let p2 = unsafe { next_table(&p3[page.p3_index()], self.p2_page(page))? };
+        let p1 = unsafe { next_table(&p2[page.p2_index()], self.p1_page(page))? };
+        let entry = &p1[page.p1_index()];
+
+        if entry.is_unused() {
+            return Err(TranslateError::PageNotMapped);
+        }
+        PhysFrame::frame_from_start_addr(entry.addr())
+            .map_err(|_| TranslateError::InvalidFrameAddress(entry.addr()))
+    }
+}
+
+
+impl Translate for RecursivePageTable<'_> {
+
+    fn translate(&self, addr: VirtualAddr) -> TranslateResult {
+        let page = Page::<Size4Kib>::containing_address(addr);
+
+        let p4_entry = &self.p4[page.p4_index()];
+        let p3 = match unsafe { next_table(p4_entry, self.p3_page(page)) } {
+            Ok(table) => table,
+            Err(FrameError::FrameNotPresent) => return TranslateResult::NotMapped,
+            Err(FrameError::HugeFrame) => panic!("level 4 entry has the huge page bit set"),
+        };
+
+        let p3_entry = &p3[page.p3_index()];
+        let p2 = match unsafe { next_table(p3_entry, self.p2_page(page)) } {
+            Ok(table) => table,
+            Err(FrameError::FrameNotPresent) => return TranslateResult::NotMapped,
+            Err(FrameError::HugeFrame) => return huge_translation::<Size1GiB>(p3_entry, addr, MappedFrame::Size1GiB),
+        };
+
+        let p2_entry = &p2[page.p2_index()];
+        let p1 = match unsafe { next_table(p2_entry, self.p1_page(page)) } {
+            Ok(table) => table,
+            Err(FrameError::FrameNotPresent) => return TranslateResult::NotMapped,
+            Err(FrameError::HugeFrame) => return huge_translation::<Size2Mib>(p2_entry, addr, MappedFrame::Size2Mib),
+        };
+
+        let p1_entry = &p1[page.p1_index()];
+        if !p1_entry.flags().contains(PageTableFlags::PRESENT) {
+            return TranslateResult::NotMapped;
+        }
+
+        match PhysFrame::<Size4Kib>::frame_from_start_addr(p1_entry.addr()) {
+            Ok(frame) => TranslateResult::Mapped {
+                frame: MappedFrame::Size4Kib(frame),
+                offset: addr.as_u64() % Size4Kib::SIZE,
+                flags: p1_entry.flags(),
+            },
+            Err(_) => TranslateResult::InvalidFrameAddress(p1_entry.addr()),
+        }
+    }
+}
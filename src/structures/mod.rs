@@ -1,3 +1,5 @@
+pub mod frame_alloc;
+pub mod mapper;
 pub mod page;
 pub mod page_table;
-pub mod phys_frame;
\ No newline at end of file
+pub mod phys_frame;
@@ -1,6 +1,6 @@
-use core::{fmt, marker::PhantomData, ops::Add};
+use core::{fmt, marker::PhantomData, ops::{Add, AddAssign, Sub, SubAssign}};
 
-use crate::addr::VirtualAddr;
+use crate::addr::{PageTableIndex, VirtualAddr};
 
 pub trait PageSize: Copy + Eq + PartialEq + PartialOrd {
     const SIZE: u64;
@@ -20,15 +20,15 @@ pub trait PageSize: Copy + Eq + PartialEq + PartialOrd {
 pub trait NotGiantPageSize: PageSize {}
 
 
-#[derive( Clone, PartialEq, PartialOrd, Ord, Eq, Hash )]
+#[derive( Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq, Hash )]
 pub enum Size4Kib {}
 
 
-#[derive( Clone, PartialEq, PartialOrd, Ord, Eq, Hash )]
+#[derive( Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq, Hash )]
 pub enum Size2Mib {}
 
 
-#[derive( Clone, PartialEq, PartialOrd, Ord, Eq, Hash )]
+#[derive( Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq, Hash )]
 pub enum Size1GiB {}
 
 impl PageSize for Size4Kib {
@@ -50,6 +50,8 @@ impl PageSize for Size1GiB {
 impl NotGiantPageSize for Size4Kib{}
 impl NotGiantPageSize for Size2Mib{}
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(C)]
 pub struct Page<S: PageSize = Size4Kib> {
     start_address: VirtualAddr,
     size: PhantomData<S>
@@ -74,6 +76,293 @@ impl<S: PageSize> Page<S> {
             size: PhantomData
         }
     }
+
+    // the address has to be aligned to the page size.
+    #[inline]
+    pub const unsafe fn from_start_address_unchecked( start_address: VirtualAddr ) -> Self {
+        Page { start_address, size: PhantomData }
+    }
+
+    #[inline]
+    pub fn start_address(self) -> VirtualAddr {
+        self.start_address
+    }
+
+    #[inline]
+    pub fn size(self) -> u64 {
+        S::SIZE
+    }
+
+    // pages from `start` up to, but not including, `end`.
+    #[inline]
+    pub fn range(start: Self, end: Self) -> PageRange<S> {
+        PageRange { start, end }
+    }
+
+    #[inline]
+    pub fn range_inclusive(start: Self, end: Self) -> PageRangeInclusive<S> {
+        PageRangeInclusive { start, end }
+    }
+
+    // the `count`-th page after this one, None if that leaves the address
+    // space. The non-canonical hole is skipped.
+    #[inline]
+    pub fn forward_checked(self, count: u64) -> Option<Self> {
+        let addr = self.start_address.forward_checked(count.checked_mul(S::SIZE)?)?;
+        Some(Page { start_address: addr, size: PhantomData })
+    }
+
+    #[inline]
+    pub fn backward_checked(self, count: u64) -> Option<Self> {
+        let addr = self.start_address.backward_checked(count.checked_mul(S::SIZE)?)?;
+        Some(Page { start_address: addr, size: PhantomData })
+    }
+
+    // index into the P4 table.
+    #[inline]
+    pub const fn p4_index(self) -> PageTableIndex {
+        self.start_address.p4_index()
+    }
+
+    // index into the P3 table.
+    #[inline]
+    pub const fn p3_index(self) -> PageTableIndex {
+        self.start_address.p3_index()
+    }
+}
+
+impl<S: NotGiantPageSize> Page<S> {
+    // index into the P2 table, 1GiB pages end at the P3.
+    #[inline]
+    pub const fn p2_index(self) -> PageTableIndex {
+        self.start_address.p2_index()
+    }
+}
+
+impl Page<Size4Kib> {
+    // index into the P1 table, only 4KiB pages have one.
+    #[inline]
+    pub const fn p1_index(self) -> PageTableIndex {
+        self.start_address.p1_index()
+    }
+}
+
+impl<S: PageSize> fmt::Debug for Page<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("Page[{}]({:#x})", S::DEBUG_STR, self.start_address.as_u64()))
+    }
+}
+
+impl<S: PageSize> Add<u64> for Page<S> {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: u64) -> Self::Output {
+        Page::containing_address(VirtualAddr::new(self.start_address.as_u64() + rhs * S::SIZE))
+    }
+}
+
+impl<S: PageSize> AddAssign<u64> for Page<S> {
+    #[inline]
+    fn add_assign(&mut self, rhs: u64) {
+        *self = *self + rhs;
+    }
+}
+
+impl<S: PageSize> Sub<u64> for Page<S> {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: u64) -> Self::Output {
+        Page::containing_address(VirtualAddr::new(self.start_address.as_u64() - rhs * S::SIZE))
+    }
+}
+
+impl<S: PageSize> SubAssign<u64> for Page<S> {
+    #[inline]
+    fn sub_assign(&mut self, rhs: u64) {
+        *self = *self - rhs;
+    }
+}
+
+impl<S: PageSize> Sub<Page<S>> for Page<S> {
+    type Output = u64;
+
+    #[inline]
+    fn sub(self, rhs: Page<S>) -> Self::Output {
+        (self.start_address.as_u64() - rhs.start_address.as_u64()) / S::SIZE
+    }
+}
+
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct PageRange<S: PageSize = Size4Kib> {
+    pub start: Page<S>,
+    pub end: Page<S>,
+}
+
+impl<S: PageSize> PageRange<S> {
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    // number of pages in the range.
+    #[inline]
+    pub fn len(&self) -> u64 {
+        if !self.is_empty() {
+            VirtualAddr::steps_between(self.start.start_address, self.end.start_address).unwrap() / S::SIZE
+        } else {
+            0
+        }
+    }
+
+    // size of all pages in the range (in bytes).
+    #[inline]
+    pub fn size(&self) -> u64 {
+        S::SIZE * self.len()
+    }
+}
+
+impl<S: PageSize> Iterator for PageRange<S> {
+    type Item = Page<S>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start < self.end {
+            let page = self.start;
+            // end is at most one past the last page, so this can't run off
+            self.start = page.forward_checked(1).unwrap_or(self.end);
+            Some(page)
+        } else {
+            None
+        }
+    }
+}
+
+impl<S: PageSize> fmt::Debug for PageRange<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageRange")
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .finish()
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct PageRangeInclusive<S: PageSize = Size4Kib> {
+    pub start: Page<S>,
+    pub end: Page<S>,
+}
+
+impl<S: PageSize> PageRangeInclusive<S> {
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.start > self.end
+    }
+
+    #[inline]
+    pub fn len(&self) -> u64 {
+        if !self.is_empty() {
+            VirtualAddr::steps_between(self.start.start_address, self.end.start_address).unwrap() / S::SIZE + 1
+        } else {
+            0
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        S::SIZE * self.len()
+    }
+}
+
+impl<S: PageSize> Iterator for PageRangeInclusive<S> {
+    type Item = Page<S>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start > self.end {
+            return None;
+        }
+        let page = self.start;
+        match page.forward_checked(1) {
+            Some(next) if page < self.end => self.start = next,
+            // last page, or the very top of the address space: empty the
+            // range without stepping past it.
+            _ => match page.backward_checked(1) {
+                Some(prev) => self.end = prev,
+                None => self.start = page.forward_checked(1).unwrap(),
+            },
+        }
+        Some(page)
+    }
+}
+
+impl<S: PageSize> fmt::Debug for PageRangeInclusive<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PageRangeInclusive")
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .finish()
+    }
+}
+
+
+// Splitting bigger pages into the smaller pages covering the same memory.
+// Going the other way isn't lossless, use `containing_address` for that.
+fn split_range<S: PageSize, T: PageSize>(range: PageRange<S>) -> PageRange<T> {
+    PageRange {
+        start: Page::containing_address(range.start.start_address),
+        end: Page::containing_address(range.end.start_address),
+    }
+}
+
+impl From<PageRange<Size2Mib>> for PageRange<Size4Kib> {
+    #[inline]
+    fn from(range: PageRange<Size2Mib>) -> Self {
+        split_range(range)
+    }
+}
+
+impl From<PageRange<Size1GiB>> for PageRange<Size2Mib> {
+    #[inline]
+    fn from(range: PageRange<Size1GiB>) -> Self {
+        split_range(range)
+    }
+}
+
+impl From<PageRange<Size1GiB>> for PageRange<Size4Kib> {
+    #[inline]
+    fn from(range: PageRange<Size1GiB>) -> Self {
+        split_range(range)
+    }
+}
+
+impl From<Page<Size2Mib>> for PageRange<Size4Kib> {
+    #[inline]
+    fn from(page: Page<Size2Mib>) -> Self {
+        split_range(PageRange { start: page, end: page + 1 })
+    }
+}
+
+impl From<Page<Size1GiB>> for PageRange<Size2Mib> {
+    #[inline]
+    fn from(page: Page<Size1GiB>) -> Self {
+        split_range(PageRange { start: page, end: page + 1 })
+    }
+}
+
+impl From<Page<Size1GiB>> for PageRange<Size4Kib> {
+    #[inline]
+    fn from(page: Page<Size1GiB>) -> Self {
+        split_range(PageRange { start: page, end: page + 1 })
+    }
 }
 
 
@@ -84,4 +373,50 @@ impl fmt::Display for AddressNotAligned {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Address alignment is insufficient")
     }
-}
\ No newline at end of file
+}
+
+// tests
+
+#[test_case]
+fn test_page_ranges() {
+    let start = Page::<Size4Kib>::containing_address(VirtualAddr::new(0x1000));
+    let end = start + 3;
+
+    let range = Page::range(start, end);
+    assert_eq!(range.len(), 3);
+    assert_eq!(range.size(), 3 * 4096);
+    assert_eq!(range.last(), Some(start + 2));
+
+    let inclusive = Page::range_inclusive(start, end);
+    assert_eq!(inclusive.len(), 4);
+    assert_eq!(inclusive.count(), 4);
+
+    assert!(Page::range(end, start).is_empty());
+    assert_eq!(Page::range(end, start).next(), None);
+}
+
+#[test_case]
+fn test_page_range_across_the_hole() {
+    let last_lower = Page::<Size4Kib>::containing_address(VirtualAddr::new(0x0000_7fff_ffff_f000));
+    let first_upper = Page::<Size4Kib>::containing_address(VirtualAddr::new(0xffff_8000_0000_0000));
+    assert_eq!(last_lower.forward_checked(1), Some(first_upper));
+    assert_eq!(Page::range_inclusive(last_lower, first_upper).len(), 2);
+
+    // the topmost page doesn't overflow the iterator
+    let top = Page::<Size4Kib>::containing_address(VirtualAddr::new(u64::MAX));
+    assert_eq!(Page::range_inclusive(top, top).count(), 1);
+}
+
+#[test_case]
+fn test_page_size_conversions() {
+    let huge = Page::<Size2Mib>::containing_address(VirtualAddr::new(0x20_0000));
+    let small: PageRange<Size4Kib> = huge.into();
+    assert_eq!(small.len(), 512);
+    assert_eq!(small.start.start_address(), huge.start_address());
+
+    let giant = Page::<Size1GiB>::containing_address(VirtualAddr::new(0x4000_0000));
+    let range: PageRange<Size2Mib> = giant.into();
+    assert_eq!(range.len(), 512);
+    let range: PageRange<Size4Kib> = Page::range(giant, giant + 2).into();
+    assert_eq!(range.len(), 2 * 512 * 512);
+}
@@ -1,8 +1,12 @@
-use core::{fmt, sync::atomic::AtomicU64};
+use core::{fmt, ops::{Index, IndexMut}, sync::atomic::AtomicU64};
 use bitflags::bitflags;
-use crate::{addr::PhyAddr, structures::phys_frame::PhysFrame};
+use crate::{addr::{PageTableIndex, PhyAddr}, structures::phys_frame::PhysFrame};
+#[cfg(feature = "memory_encryption")]
+use core::sync::atomic::Ordering;
+#[cfg(feature = "memory_encryption")]
+use crate::meme_encrypt::{ENC_BIT_MASK, ENC_BIT_REVERSED};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 
 // Error returened by the FrameEntry.
 pub enum FrameError {
@@ -11,9 +15,10 @@ pub enum FrameError {
 }
 
 
+// the C-bit gets cleared from this once memory encryption is set up.
 pub(crate) static PHYSICAL_ADDRESS_MASK: AtomicU64 = AtomicU64::new(0x000f_ffff_ffff_f000_u64);
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Clone, Copy)]
 #[repr(transparent)]
 pub struct PageTableEntry {
     entry: u64
@@ -23,25 +28,36 @@ pub struct PageTableEntry {
 impl PageTableEntry {
     
     #[inline]
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self { entry: 0 }
     }
 
     #[inline]
     pub const fn is_unused(&self) -> bool {
-        return self.entry == 0;
+        self.entry == 0
     }
 
     #[inline]
-    #[const_fn(cfg(not( feature = "memory_encryption")))]
     pub fn set_unused(&mut self) {
         self.entry = 0;
     }
 
+    #[cfg(not(feature = "memory_encryption"))]
     #[inline]
-    #[const_fn(cfg(not( feature = "memory_encryption")))]
     pub const fn flags(&self) -> PageTableFlags {
-        PageTableFlags::from_bits_retain(self.entry & !Self::physical)
+        PageTableFlags::from_bits_retain(self.entry & !Self::physical_addr_mask())
+    }
+
+    // the C-bit shows up as `ENCRYPTED`, wherever it sits.
+    #[cfg(feature = "memory_encryption")]
+    #[inline]
+    pub fn flags(&self) -> PageTableFlags {
+        let enc_mask = ENC_BIT_MASK.load(Ordering::Relaxed);
+        let mut flags = PageTableFlags::from_bits_retain(self.entry & !(Self::physical_addr_mask() | enc_mask));
+        if enc_mask != 0 && (self.entry & enc_mask != 0) != ENC_BIT_REVERSED.load(Ordering::Relaxed) {
+            flags.insert(PageTableFlags::ENCRYPTED);
+        }
+        flags
     }
 
     // returns PA mapped by this entry.
@@ -53,11 +69,11 @@ impl PageTableEntry {
     // Returns Physical Frame marked by this one entry.
     pub fn frame(&self) -> Result<PhysFrame, FrameError> {
         if !self.flags().contains(PageTableFlags::PRESENT) {
-            return Err(FrameError::FrameNotPresent);
+            Err(FrameError::FrameNotPresent)
         } else if self.flags().contains(PageTableFlags::HUGE_PAGE) {
-            return Err(FrameError::HugeFrame);
+            Err(FrameError::HugeFrame)
         } else {
-            return Ok(PhysFrame::containing_address(addr));
+            Ok(PhysFrame::frame_containing_addr(self.addr()))
         }
     }
 
@@ -65,25 +81,47 @@ impl PageTableEntry {
     // Mask is used in 64-bit OSes( Particularly with x86_64 ) 
     // to extract the Physical Page frame address from a PTE(Page Table Entry)
     // while clearing out Control/Attribute Bits.
+    #[cfg(not(feature = "memory_encryption"))]
     const fn physical_addr_mask() -> u64 {
         0x000f_ffff_ffff_f000u64
     }
 
+    #[cfg(feature = "memory_encryption")]
+    #[inline]
+    fn physical_addr_mask() -> u64 {
+        PHYSICAL_ADDRESS_MASK.load(Ordering::Relaxed)
+    }
+
     // Maps the Specified Physical Address with the specified flags.
+    #[cfg(not(feature = "memory_encryption"))]
     #[inline]
     pub fn set_addr(&mut self, addr: PhyAddr, flags: PageTableFlags) {
         self.entry = ( addr.as_u64() ) | flags.bits();
     }
 
+    // `ENCRYPTED` turns into the C-bit, and is dropped if encryption is off.
+    #[cfg(feature = "memory_encryption")]
+    #[inline]
+    pub fn set_addr(&mut self, addr: PhyAddr, flags: PageTableFlags) {
+        let enc_mask = ENC_BIT_MASK.load(Ordering::Relaxed);
+        let enc_bit = if flags.contains(PageTableFlags::ENCRYPTED) != ENC_BIT_REVERSED.load(Ordering::Relaxed) {
+            enc_mask
+        } else {
+            0
+        };
+        self.entry = addr.as_u64() | enc_bit | (flags - PageTableFlags::ENCRYPTED).bits();
+    }
+
     #[inline]
     // Maps the specified Physical Frame with specified flags.
     pub fn set_frame( &mut self, frame: PhysFrame, flags: PageTableFlags  ) {
-        assert!(frame.frame_from_start_addr(frame), flags)
+        assert!(!flags.contains(PageTableFlags::HUGE_PAGE));
+        self.set_addr(frame.start_addr_of_physframe(), flags)
     }
 
     #[inline]
-    pub fn setflags(&self, flags: PageTableFlags) {
-        self.entry = self.addr().as_u64() | flags.bits();
+    pub fn set_flags(&mut self, flags: PageTableFlags) {
+        self.set_addr(self.addr(), flags);
     }
 
 }
@@ -107,6 +145,7 @@ impl fmt::Debug for PageTableEntry {
 
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct PageTableFlags: u64 {
 
         // ididcates fi the page is currently in physical memeory or swapped out.
@@ -136,6 +175,11 @@ bitflags! {
         const BIT61 =                 1<<61;
         const BIT62 =                 1<<62;
         const NO_EXECUTE =            1<<63;
+
+        // stands in for the C-bit, whose position is only known at runtime
+        // (see meme_encrypt). Never written to an entry as is.
+        #[cfg(feature = "memory_encryption")]
+        const ENCRYPTED =             1<<51;
     }
 }
 
@@ -158,4 +202,60 @@ impl PageTable {
         Self { entries: [EMPTY; ENTRY_COUNT] }
     }
 
+    // clears all entries.
+    #[inline]
+    pub fn zero(&mut self) {
+        for entry in self.iter_mut() {
+            entry.set_unused();
+        }
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &PageTableEntry> {
+        self.entries.iter()
+    }
+
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut PageTableEntry> {
+        self.entries.iter_mut()
+    }
+
+}
+
+impl Default for PageTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
+
+    #[inline]
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.entries[index]
+    }
+}
+
+impl Index<PageTableIndex> for PageTable {
+    type Output = PageTableEntry;
+
+    #[inline]
+    fn index(&self, index: PageTableIndex) -> &Self::Output {
+        &self.entries[usize::from(index)]
+    }
+}
+
+impl IndexMut<PageTableIndex> for PageTable {
+    #[inline]
+    fn index_mut(&mut self, index: PageTableIndex) -> &mut Self::Output {
+        &mut self.entries[usize::from(index)]
+    }
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use core::{fmt, marker::PhantomData};
+use core::{fmt, marker::PhantomData, ops::{Add, AddAssign, Sub, SubAssign}};
 use crate::{addr::PhyAddr, structures::page::{AddressNotAligned, PageSize, Size4Kib}};
 
 
@@ -26,13 +26,13 @@ impl<S: PageSize> PhysFrame<S> {
     // Returns the PhysFrame.
     // We need to have assurance for the correctly alignemnt of address. 
     #[inline]
-    unsafe fn from_start_address_unchecked(start_addr: PhyAddr) -> Self {
+    pub unsafe fn from_start_address_unchecked(start_addr: PhyAddr) -> Self {
         PhysFrame { start_addr, size: PhantomData }
     }
 
     // Is the frame contained the Given Physical address.
     #[inline]
-    fn frame_containing_addr(addr: PhyAddr) -> Self {
+    pub fn frame_containing_addr(addr: PhyAddr) -> Self {
         PhysFrame { 
             start_addr: addr.align_down_u64(S::SIZE), 
             size: PhantomData
@@ -41,19 +41,19 @@ impl<S: PageSize> PhysFrame<S> {
 
     // returns the Start Address of the PhysFrame.
     #[inline]
-    fn start_addr_of_physframe(&self) -> PhyAddr {
+    pub fn start_addr_of_physframe(&self) -> PhyAddr {
          self.start_addr
     }
 
     // retuns the size of the PhysFrame.
     #[inline]
-    fn size_of_physframe(self) -> u64 {
+    pub fn size_of_physframe(self) -> u64 {
         S::SIZE
     }
 
     // range of the frames.
     #[inline]
-    fn range_of_physframe( start: PhysFrame<S>, end: PhysFrame<S> ) -> PhysFrameRange<S> {
+    pub fn range_of_physframe( start: PhysFrame<S>, end: PhysFrame<S> ) -> PhysFrameRange<S> {
         PhysFrameRange {
             start, end
         }
@@ -61,14 +61,68 @@ impl<S: PageSize> PhysFrame<S> {
 
     // is the range is_inclusive?
     #[inline]
-    fn is_range_of_physframe_inclusive() -> PhysFrameRangeInclusive<S> {
+    pub fn is_range_of_physframe_inclusive( start: PhysFrame<S>, end: PhysFrame<S> ) -> PhysFrameRangeInclusive<S> {
         PhysFrameRangeInclusive { start, end }
     }
 
 }
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+impl<S: PageSize> fmt::Debug for PhysFrame<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "PhysFrame[{}]({:#x})",
+            S::DEBUG_STR,
+            self.start_addr.as_u64()
+        ))
+    }
+}
+
+// frame + n is the n-th frame after this one
+impl<S: PageSize> Add<u64> for PhysFrame<S> {
+    type Output = Self;
+
+    #[inline]
+    fn add(self, rhs: u64) -> Self::Output {
+        PhysFrame::frame_containing_addr(PhyAddr::new(self.start_addr.as_u64() + rhs * S::SIZE))
+    }
+}
+
+impl<S: PageSize> AddAssign<u64> for PhysFrame<S> {
+    #[inline]
+    fn add_assign(&mut self, rhs: u64) {
+        *self = *self + rhs;
+    }
+}
+
+impl<S: PageSize> Sub<u64> for PhysFrame<S> {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, rhs: u64) -> Self::Output {
+        PhysFrame::frame_containing_addr(PhyAddr::new(self.start_addr.as_u64() - rhs * S::SIZE))
+    }
+}
+
+impl<S: PageSize> SubAssign<u64> for PhysFrame<S> {
+    #[inline]
+    fn sub_assign(&mut self, rhs: u64) {
+        *self = *self - rhs;
+    }
+}
+
+// number of frames between two frames
+impl<S: PageSize> Sub<PhysFrame<S>> for PhysFrame<S> {
+    type Output = u64;
+
+    #[inline]
+    fn sub(self, rhs: PhysFrame<S>) -> Self::Output {
+        (self.start_addr.as_u64() - rhs.start_addr.as_u64()) / S::SIZE
+    }
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct PhysFrameRange<S: PageSize = Size4Kib> {
     pub start: PhysFrame<S>,
@@ -78,12 +132,12 @@ pub struct PhysFrameRange<S: PageSize = Size4Kib> {
 impl<S: PageSize> PhysFrameRange<S> {
 
     #[inline]
-    fn is_empty(&self) -> bool {
+    pub fn is_empty(&self) -> bool {
         self.start >= self.end
     }
 
     #[inline]
-    fn total_frames_within_range(&self) -> u64 {
+    pub fn total_frames_within_range(&self) -> u64 {
         if !self.is_empty() {
             self.end - self.start
         } else { 
@@ -93,7 +147,7 @@ impl<S: PageSize> PhysFrameRange<S> {
 
     // Returns the size of all frames withtin the range(in Bytes).
     #[inline]
-    fn size(&self) -> u64 {
+    pub fn size(&self) -> u64 {
         S::SIZE * self.total_frames_within_range()   
     }
 
@@ -103,7 +157,7 @@ impl<S: PageSize> PhysFrameRange<S> {
 // Implement `Iterator` for `PhysFrameRange<S>` S: PageSize = Size4KiB.
 // The `Iterator` trait only requires a method to be defined for the `next` element,
 // and an `associated type` to declare the return type of the iterator.
-impl Iterator<S: PageSize> for PhysFrameRange<S> {
+impl<S: PageSize> Iterator for PhysFrameRange<S> {
     // We can refer to this type using Self::Item
     type Item = PhysFrame<S>;
 
@@ -127,12 +181,13 @@ impl Iterator<S: PageSize> for PhysFrameRange<S> {
 impl<S: PageSize> fmt::Debug for PhysFrameRange<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PhysFrameRange")
-        .field("start", self.start.into())
-        .field("end", self.end.into())
+        .field("start", &self.start)
+        .field("end", &self.end)
         .finish()
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct PhysFrameRangeInclusive<S: PageSize = Size4Kib> {
     pub start: PhysFrame<S>,
@@ -147,9 +202,9 @@ impl<S: PageSize> PhysFrameRangeInclusive<S> {
     }
 
     #[inline]
-    pub fn len(&self) -> usize {
+    pub fn len(&self) -> u64 {
         if !self.is_empty() {
-            self.end - self.start
+            self.end - self.start + 1
         } else {
             0
         }
@@ -162,7 +217,7 @@ impl<S: PageSize> PhysFrameRangeInclusive<S> {
 }
 
 
-impl Iterator<S: PageSize> for PhysFrameRangeInclusive<S> {
+impl<S: PageSize> Iterator for PhysFrameRangeInclusive<S> {
     type Item = PhysFrame<S>;
 
 
@@ -178,11 +233,11 @@ impl Iterator<S: PageSize> for PhysFrameRangeInclusive<S> {
 }
 
 
-impl fmt::Debug<S: PageSize> for PhysFrameRangeInclusive<S> {
+impl<S: PageSize> fmt::Debug for PhysFrameRangeInclusive<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PhysicalFrameInclusive")
-            .field("start", self.start.into())
-            .field("end", self.end.into())
+            .field("start", &self.start)
+            .field("end", &self.end)
             .finish()
     }
-}
\ No newline at end of file
+}
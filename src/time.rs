@@ -0,0 +1,165 @@
+// Monotonic kernel clock driven by the PIT.
+// Every IRQ 0 increments the tick counter, the length of a tick follows
+// from the frequency the PIT was programmed with.
+// `Instant` is the high resolution clock on top of the calibrated TSC.
+
+use core::{ops::{Add, AddAssign, Sub, SubAssign}, sync::atomic::{AtomicU16, AtomicU64, Ordering}, time::Duration};
+
+use crate::{pit, tsc};
+
+// tick rate the kernel runs at unless told otherwise
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+// tick count and uptime (ns) when the frequency was last changed, so that
+// changing it doesn't make the uptime jump
+static EPOCH_TICKS: AtomicU64 = AtomicU64::new(0);
+static EPOCH_NANOS: AtomicU64 = AtomicU64::new(0);
+
+// divisor the PIT runs with, 0 is the BIOS default of 65536 (~18.2Hz)
+static DIVISOR: AtomicU16 = AtomicU16::new(0);
+
+
+// Programs the PIT to tick at `frequency` Hz.
+pub fn init(frequency: u32) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let now = uptime();
+        EPOCH_TICKS.store(ticks(), Ordering::SeqCst);
+        EPOCH_NANOS.store(now.as_nanos() as u64, Ordering::SeqCst);
+
+        let divisor = unsafe { pit::set_frequency(frequency) };
+        DIVISOR.store(divisor, Ordering::SeqCst);
+    });
+}
+
+// called by the timer interrupt handler
+#[inline]
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+// ticks since boot
+#[inline]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+// ticks per second the clock is really running at
+#[inline]
+pub fn frequency() -> u32 {
+    pit::frequency_of(DIVISOR.load(Ordering::Relaxed))
+}
+
+// length of `ticks` ticks at the current frequency
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let divisor = match DIVISOR.load(Ordering::Relaxed) {
+        0 => 0x1_0000,
+        divisor => u128::from(divisor),
+    };
+    let nanos = u128::from(ticks) * divisor * 1_000_000_000 / u128::from(pit::BASE_FREQUENCY);
+    Duration::from_nanos(nanos as u64)
+}
+
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let divisor = match DIVISOR.load(Ordering::Relaxed) {
+        0 => 0x1_0000,
+        divisor => u128::from(divisor),
+    };
+    // round up, sleeping too short is worse than too long
+    let ticks = (duration.as_nanos() * u128::from(pit::BASE_FREQUENCY)).div_ceil(divisor * 1_000_000_000);
+    ticks as u64
+}
+
+// time since boot, with tick granularity
+pub fn uptime() -> Duration {
+    let epoch = Duration::from_nanos(EPOCH_NANOS.load(Ordering::SeqCst));
+    epoch + ticks_to_duration(ticks() - EPOCH_TICKS.load(Ordering::SeqCst))
+}
+
+// Halts until at least `ticks` timer interrupts happened.
+// Interrupts have to be enabled or this never returns.
+pub fn sleep_ticks(ticks: u64) {
+    let target = self::ticks() + ticks;
+    while self::ticks() < target {
+        x86_64::instructions::hlt();
+    }
+}
+
+pub fn sleep(duration: Duration) {
+    sleep_ticks(duration_to_ticks(duration));
+}
+
+
+// A point in time with nanosecond resolution, read from the TSC.
+// Only meaningful once `tsc::init` calibrated the TSC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+
+    #[inline]
+    pub fn now() -> Instant {
+        Instant(tsc::read())
+    }
+
+    // raw TSC value
+    #[inline]
+    pub const fn cycles(&self) -> u64 {
+        self.0
+    }
+
+    // time since `earlier`, zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        tsc::cycles_to_duration(self.0.saturating_sub(earlier.0))
+    }
+
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(tsc::duration_to_cycles(duration)).map(Instant)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(tsc::duration_to_cycles(duration)).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        self.checked_add(rhs).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        self.checked_sub(rhs).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
@@ -0,0 +1,212 @@
+// TLB invalidation.
+// `flush` and `flush_all` only reach the TLB of the cpu running them. With
+// PCID the TLB also keeps entries of address spaces that aren't active,
+// `flush_pcid` and `flush_pcid_addr` get at those through INVPCID, or throw
+// the whole TLB away when the cpu doesn't have it.
+//
+// `shootdown` is for page tables other cpus might be using: it flushes
+// here, sends an IPI to every other cpu that has the address space active
+// and waits until all of them have flushed too. Cpus that switched away
+// don't get one, `address_space` marks the PCID stale for them and they
+// flush it when they switch back.
+
+use core::{arch::asm, hint, sync::atomic::{AtomicBool, AtomicU64, Ordering}};
+
+use spin::Mutex;
+use x86_64::registers::control::Cr4;
+
+use crate::{addr::{PhyAddr, VirtualAddr}, apic, cpuid, interrupts::InterruptIndex, memory};
+
+
+// cpus are tracked in u64 masks, by local apic id
+pub const MAX_CPUS: usize = 64;
+
+const CR4_PGE: u64 = 1 << 7;
+
+// INVPCID types
+const INVPCID_ADDRESS: u64 = 0;
+const INVPCID_CONTEXT: u64 = 1;
+const INVPCID_ALL_GLOBAL: u64 = 2;
+
+// page addresses are aligned, so this can't be one
+const REQUEST_ALL: u64 = 1;
+
+static INVPCID: AtomicBool = AtomicBool::new(false);
+static ONLINE: AtomicU64 = AtomicU64::new(0);
+// what every cpu has in CR3
+static ACTIVE_P4: [AtomicU64; MAX_CPUS] = [const { AtomicU64::new(0) }; MAX_CPUS];
+
+// one shootdown at a time, REQUEST says what to flush and PENDING which
+// cpus haven't done it yet
+static SHOOTDOWN: Mutex<()> = Mutex::new(());
+static REQUEST: AtomicU64 = AtomicU64::new(0);
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushRequest {
+    Page(VirtualAddr),
+    All,
+}
+
+impl FlushRequest {
+
+    fn encode(self) -> u64 {
+        match self {
+            FlushRequest::Page(addr) => addr.align_down(4096u64).as_u64(),
+            FlushRequest::All => REQUEST_ALL,
+        }
+    }
+
+    fn decode(value: u64) -> Self {
+        match value {
+            REQUEST_ALL => FlushRequest::All,
+            addr => FlushRequest::Page(VirtualAddr::new(addr)),
+        }
+    }
+
+    // flushes it on this cpu
+    fn run(self) {
+        match self {
+            FlushRequest::Page(addr) => flush(addr),
+            FlushRequest::All => flush_all(),
+        }
+    }
+}
+
+
+// Checks for INVPCID and registers the boot cpu. Called by `memory::init`.
+pub fn init() {
+    INVPCID.store(cpuid::has_invpcid(), Ordering::Relaxed);
+    init_cpu();
+}
+
+// Registers the cpu running this for shootdowns, every cpu has to call it
+// once it's up.
+pub fn init_cpu() {
+    let cpu = current_cpu();
+    ACTIVE_P4[cpu].store(memory::active_level_4_table_addr().as_u64(), Ordering::SeqCst);
+    ONLINE.fetch_or(1 << cpu, Ordering::SeqCst);
+}
+
+#[inline]
+pub fn has_invpcid() -> bool {
+    INVPCID.load(Ordering::Relaxed)
+}
+
+pub(crate) fn current_cpu() -> usize {
+    let cpu = usize::from(cpuid::apic_id());
+    assert!(cpu < MAX_CPUS, "apic id {} is too big", cpu);
+    cpu
+}
+
+// Has to be called whenever CR3 changes, so shootdowns know who's using
+// which page table.
+pub(crate) fn set_active(p4: PhyAddr) {
+    ACTIVE_P4[current_cpu()].store(p4.as_u64(), Ordering::SeqCst);
+}
+
+
+// Invalidates the page containing `addr` on this cpu.
+#[inline]
+pub fn flush(addr: VirtualAddr) {
+    unsafe { asm!("invlpg [{}]", in(reg) addr.as_u64(), options(nostack, preserves_flags)) };
+}
+
+// Invalidates every non global entry of the active address space on this
+// cpu, by writing CR3 again.
+#[inline]
+pub fn flush_all() {
+    unsafe {
+        asm!(
+            "mov {0}, cr3",
+            "mov cr3, {0}",
+            out(reg) _,
+            options(nostack, preserves_flags),
+        );
+    }
+}
+
+// Invalidates everything on this cpu, global entries and those of every
+// PCID included.
+pub fn flush_everything() {
+    if has_invpcid() {
+        unsafe { invpcid(INVPCID_ALL_GLOBAL, 0, 0) };
+        return;
+    }
+    // every write that changes CR4.PGE does it
+    unsafe {
+        let cr4 = Cr4::read_raw();
+        Cr4::write_raw(cr4 ^ CR4_PGE);
+        Cr4::write_raw(cr4);
+    }
+}
+
+// Invalidates the entries tagged with `pcid` on this cpu, whether that
+// address space is active or not.
+pub fn flush_pcid(pcid: u16) {
+    if has_invpcid() {
+        unsafe { invpcid(INVPCID_CONTEXT, pcid, 0) };
+    } else {
+        flush_everything();
+    }
+}
+
+// Invalidates the page containing `addr` for `pcid` on this cpu.
+pub fn flush_pcid_addr(pcid: u16, addr: VirtualAddr) {
+    if has_invpcid() {
+        unsafe { invpcid(INVPCID_ADDRESS, pcid, addr.as_u64()) };
+    } else {
+        flush_everything();
+    }
+}
+
+unsafe fn invpcid(kind: u64, pcid: u16, addr: u64) {
+    let descriptor: [u64; 2] = [u64::from(pcid), addr];
+    unsafe { asm!("invpcid {}, [{}]", in(reg) kind, in(reg) &descriptor, options(nostack, preserves_flags)) };
+}
+
+
+// Flushes `request` on every cpu that has the page table `p4` active, and
+// returns once all of them are done. Call it after changing the tables.
+//
+// Interrupts have to be enabled, a cpu waiting here with them off would
+// never answer the shootdown of another one.
+pub fn shootdown(p4: PhyAddr, request: FlushRequest) {
+    let this = current_cpu();
+    if ACTIVE_P4[this].load(Ordering::SeqCst) == p4.as_u64() {
+        request.run();
+    }
+
+    let online = ONLINE.load(Ordering::SeqCst) & !(1 << this);
+    let targets = (0..MAX_CPUS)
+        .filter(|&cpu| online & (1 << cpu) != 0 && ACTIVE_P4[cpu].load(Ordering::SeqCst) == p4.as_u64())
+        .fold(0u64, |mask, cpu| mask | (1 << cpu));
+    if targets == 0 {
+        return;
+    }
+    assert!(apic::is_enabled(), "other cpus are up but the apic isn't");
+    assert!(x86_64::instructions::interrupts::are_enabled(), "TLB shootdown with interrupts disabled");
+
+    let _shootdown = SHOOTDOWN.lock();
+    REQUEST.store(request.encode(), Ordering::SeqCst);
+    PENDING.store(targets, Ordering::SeqCst);
+    // the local apic lock with interrupts off, see `apic::LOCAL_APIC`. The
+    // waiting below has them on again.
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut local_apic = apic::LOCAL_APIC.lock();
+        let local_apic = local_apic.as_mut().expect("apic enabled without a local apic");
+        for cpu in (0..MAX_CPUS).filter(|&cpu| targets & (1 << cpu) != 0) {
+            unsafe { local_apic.send_ipi(cpu as u32, InterruptIndex::TlbShootdown.as_u8()) };
+        }
+    });
+    while PENDING.load(Ordering::SeqCst) != 0 {
+        hint::spin_loop();
+    }
+}
+
+// Called by the shootdown IPI handler.
+pub fn handle_shootdown() {
+    FlushRequest::decode(REQUEST.load(Ordering::SeqCst)).run();
+    PENDING.fetch_and(!(1 << current_cpu()), Ordering::SeqCst);
+}
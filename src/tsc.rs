@@ -0,0 +1,118 @@
+// Time Stamp Counter.
+// The TSC counts cpu cycles (or, with an invariant TSC, a fixed reference
+// clock) and can be read in a few cycles, which makes it the clock of
+// choice for anything finer than a timer tick. Its frequency isn't
+// reported reliably, so it's measured against the HPET or the PIT at boot,
+// from `memory::init` since the HPET is only reachable through the direct map.
+
+use core::{arch::x86_64::_rdtsc, sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering}, time::Duration};
+
+use crate::{cpuid, hpet::Hpet, pit};
+
+// length of one calibration run
+const CALIBRATION_MS: u64 = 10;
+
+// number of runs, the shortest one wins (least disturbed by the host/SMIs)
+const CALIBRATION_RUNS: usize = 3;
+
+// TSC frequency in Hz, 0 until calibrated
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static SOURCE: AtomicU8 = AtomicU8::new(CalibrationSource::None as u8);
+static INVARIANT: AtomicBool = AtomicBool::new(false);
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CalibrationSource {
+    None,
+    Pit,
+    Hpet,
+}
+
+#[inline]
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+
+fn calibrate_with_pit() -> u64 {
+    let divisor = pit::divisor_for((1000 / CALIBRATION_MS) as u32);
+
+    let start = read();
+    pit::wait_channel_2(divisor);
+    let cycles = read() - start;
+
+    // cycles / (divisor / BASE_FREQUENCY)
+    cycles * u64::from(pit::BASE_FREQUENCY) / u64::from(divisor)
+}
+
+fn calibrate_with_hpet(hpet: &Hpet) -> u64 {
+    let target = hpet.frequency() * CALIBRATION_MS / 1000;
+
+    let hpet_start = hpet.counter();
+    let start = read();
+    while hpet.counter() - hpet_start < target {
+        core::hint::spin_loop();
+    }
+    let cycles = read() - start;
+    let hpet_ticks = hpet.counter() - hpet_start;
+
+    (u128::from(cycles) * u128::from(hpet.frequency()) / u128::from(hpet_ticks)) as u64
+}
+
+// Measures the TSC frequency, against the HPET if there is one, returns it in Hz.
+// Falls back to the PIT when physical memory isn't mapped yet.
+pub fn init() -> u64 {
+    if !cpuid::has_tsc() {
+        return 0;
+    }
+    INVARIANT.store(cpuid::has_invariant_tsc(), Ordering::Relaxed);
+
+    let hpet = Hpet::probe();
+    let frequency = x86_64::instructions::interrupts::without_interrupts(|| {
+        (0..CALIBRATION_RUNS)
+            .map(|_| match &hpet {
+                Some(hpet) => calibrate_with_hpet(hpet),
+                None => calibrate_with_pit(),
+            })
+            .min()
+            .unwrap_or(0)
+    });
+
+    let source = if hpet.is_some() { CalibrationSource::Hpet } else { CalibrationSource::Pit };
+    SOURCE.store(source as u8, Ordering::Relaxed);
+    FREQUENCY.store(frequency, Ordering::SeqCst);
+    frequency
+}
+
+// calibrated frequency in Hz, 0 if `init` didn't run
+#[inline]
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+pub fn calibration_source() -> CalibrationSource {
+    match SOURCE.load(Ordering::Relaxed) {
+        1 => CalibrationSource::Pit,
+        2 => CalibrationSource::Hpet,
+        _ => CalibrationSource::None,
+    }
+}
+
+// without an invariant TSC the rate can change with the cpu frequency,
+// so durations are only trustworthy when this is true.
+#[inline]
+pub fn is_invariant() -> bool {
+    INVARIANT.load(Ordering::Relaxed)
+}
+
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    match frequency() {
+        0 => Duration::ZERO,
+        frequency => Duration::from_nanos((u128::from(cycles) * 1_000_000_000 / u128::from(frequency)) as u64),
+    }
+}
+
+pub fn duration_to_cycles(duration: Duration) -> u64 {
+    (duration.as_nanos() * u128::from(frequency()) / 1_000_000_000) as u64
+}
@@ -0,0 +1,253 @@
+// Virtual memory areas and demand paging.
+// A VMA reserves a range of virtual address space without mapping any of
+// it. The first access to a page of an anonymous VMA page faults, the
+// fault handler maps a zeroed frame with the VMA's flags and the access
+// runs again. Faults outside every VMA are real bugs and still end in the
+// diagnostic panic.
+
+use core::ptr;
+
+use spin::Mutex;
+
+use crate::{addr::VirtualAddr, address_space, idt::PageFaultErrorCode, memory, pmm::BitmapFrameAllocator, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::Mapper, page::{Page, PageRange, PageSize, Size4Kib}, page_table::PageTableFlags}};
+
+
+const MAX_VMAS: usize = 64;
+
+static VMAS: Mutex<[Option<Vma>; MAX_VMAS]> = Mutex::new([None; MAX_VMAS]);
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaKind {
+    // zero filled memory with nothing behind it
+    Anonymous,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    pub pages: PageRange,
+    // flags for the pages once they're mapped, PRESENT is added then
+    pub flags: PageTableFlags,
+    pub kind: VmaKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    Empty,
+    Unaligned,
+    Overlaps,
+    // runs past the end of the address space
+    OutOfRange,
+    // all `MAX_VMAS` slots are taken
+    Full,
+    NotFound,
+}
+
+impl Vma {
+
+    #[inline]
+    pub fn start(&self) -> VirtualAddr {
+        self.pages.start.start_address()
+    }
+
+    // first address past the area
+    #[inline]
+    pub fn end(&self) -> VirtualAddr {
+        self.pages.end.start_address()
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.pages.size()
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: VirtualAddr) -> bool {
+        self.start() <= addr && addr < self.end()
+    }
+
+    fn overlaps(&self, other: &Vma) -> bool {
+        self.start() < other.end() && other.start() < self.end()
+    }
+
+    // Whether an access with this error code is allowed at all.
+    fn permits(&self, error_code: PageFaultErrorCode) -> bool {
+        if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) && !self.flags.contains(PageTableFlags::WRITABLE) {
+            return false;
+        }
+        if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) && self.flags.contains(PageTableFlags::NO_EXECUTE) {
+            return false;
+        }
+        if error_code.contains(PageFaultErrorCode::USER_MODE) && !self.flags.contains(PageTableFlags::USERACCESSIBLE) {
+            return false;
+        }
+        true
+    }
+}
+
+
+// Reserves `size` bytes of anonymous memory at `start`. Nothing gets mapped
+// until the pages are touched.
+pub fn reserve(start: VirtualAddr, size: u64, flags: PageTableFlags) -> Result<Vma, VmaError> {
+    if size == 0 {
+        return Err(VmaError::Empty);
+    }
+    if !start.is_aligned(Size4Kib::SIZE) || size % Size4Kib::SIZE != 0 {
+        return Err(VmaError::Unaligned);
+    }
+
+    let first = Page::containing_address(start);
+    let end = first.forward_checked(size / Size4Kib::SIZE).ok_or(VmaError::OutOfRange)?;
+    let vma = Vma { pages: Page::range(first, end), flags, kind: VmaKind::Anonymous };
+
+    let mut vmas = VMAS.lock();
+    if vmas.iter().flatten().any(|other| other.overlaps(&vma)) {
+        return Err(VmaError::Overlaps);
+    }
+    let slot = vmas.iter_mut().find(|slot| slot.is_none()).ok_or(VmaError::Full)?;
+    *slot = Some(vma);
+    Ok(vma)
+}
+
+// Drops the VMA starting at `start` and unmaps whatever of it was touched.
+// VMAs in the user half are unmapped from the active address space.
+//
+// unsafe: nothing may use the memory anymore.
+pub unsafe fn release(start: VirtualAddr) -> Result<Vma, VmaError> {
+    let vma = {
+        let mut vmas = VMAS.lock();
+        let slot = vmas.iter_mut()
+            .find(|slot| matches!(slot, Some(vma) if vma.start() == start))
+            .ok_or(VmaError::NotFound)?;
+        slot.take().unwrap()
+    };
+
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().expect("memory::init wasn't called");
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().expect("memory::init wasn't called");
+
+    if address_space::is_user_addr(vma.start()) {
+        let mut mapper = unsafe { address_space::active_mapper() };
+        unsafe { unmap_touched(&mut mapper, frame_allocator, &vma) };
+    } else {
+        unsafe { unmap_touched(mapper, frame_allocator, &vma) };
+    }
+    Ok(vma)
+}
+
+// unsafe: see `release`.
+unsafe fn unmap_touched<M>(mapper: &mut M, frame_allocator: &mut BitmapFrameAllocator, vma: &Vma)
+where
+    M: Mapper<Size4Kib>,
+{
+    for page in vma.pages {
+        // pages that were never touched aren't mapped
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+pub fn find(addr: VirtualAddr) -> Option<Vma> {
+    VMAS.lock().iter().flatten().find(|vma| vma.contains(addr)).copied()
+}
+
+// Number of pages of `vma` that have a frame behind them, in the active
+// address space for the user half.
+pub fn resident_pages(vma: &Vma) -> u64 {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().expect("memory::init wasn't called");
+    if address_space::is_user_addr(vma.start()) {
+        let mapper = unsafe { address_space::active_mapper() };
+        return vma.pages.filter(|&page| mapper.translate_page(page).is_ok()).count() as u64;
+    }
+    vma.pages.filter(|&page| mapper.translate_page(page).is_ok()).count() as u64
+}
+
+
+// Called by the page fault handler. Returns true if `addr` was the first
+// touch of a page in an anonymous VMA and the page is mapped now, so the
+// faulting instruction can simply run again.
+pub fn handle_page_fault(addr: VirtualAddr, error_code: PageFaultErrorCode) -> bool {
+    // a present page faulted, that's a real protection violation
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+
+    // the fault can come from code holding the VMA lock, don't deadlock on it
+    let vma = match VMAS.try_lock() {
+        Some(vmas) => vmas.iter().flatten().find(|vma| vma.contains(addr)).copied(),
+        None => None,
+    };
+    let Some(vma) = vma else { return false };
+    if !vma.permits(error_code) {
+        return false;
+    }
+
+    let page = Page::containing_address(addr);
+    memory::with_fault_locks(|mapper, frame_allocator| {
+        // VMAs in the user half are backed in the active address space
+        if address_space::is_user_addr(addr) {
+            let mut mapper = unsafe { address_space::active_mapper() };
+            return map_zeroed(&mut mapper, frame_allocator, page, vma.flags);
+        }
+        map_zeroed(mapper, frame_allocator, page, vma.flags)
+    }).unwrap_or(false)
+}
+
+// Maps a zeroed frame at `page`. A page that's mapped already is a bug
+// (the fault couldn't have happened), it's left to the caller to report.
+fn map_zeroed<M>(mapper: &mut M, frame_allocator: &mut BitmapFrameAllocator, page: Page, flags: PageTableFlags) -> bool
+where
+    M: Mapper<Size4Kib>,
+{
+    let Some(frame) = frame_allocator.allocate_frame() else { return false };
+    let Some(virt) = memory::phys_to_virt(frame.start_addr_of_physframe()) else {
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        return false;
+    };
+    unsafe { ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, Size4Kib::SIZE as usize) };
+
+    match unsafe { mapper.map_to(page, frame, flags | PageTableFlags::PRESENT, frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            true
+        }
+        Err(_) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            false
+        }
+    }
+}
+
+
+#[test_case]
+fn test_reserve_checks() {
+    let start = VirtualAddr::new(0x_6666_0000_0000);
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    assert_eq!(reserve(start, 0, flags), Err(VmaError::Empty));
+    assert_eq!(reserve(start + 1, 4096, flags), Err(VmaError::Unaligned));
+    assert_eq!(reserve(start, 100, flags), Err(VmaError::Unaligned));
+
+    let vma = reserve(start, 16 * 4096, flags).unwrap();
+    assert_eq!(vma.size(), 16 * 4096);
+    assert_eq!(find(start + 5 * 4096), Some(vma));
+    assert_eq!(find(vma.end()), None);
+    assert_eq!(reserve(start + 15 * 4096, 4096, flags), Err(VmaError::Overlaps));
+    assert_eq!(reserve(start - 4096, 2 * 4096, flags), Err(VmaError::Overlaps));
+
+    // a write to a read only VMA isn't fixed up
+    let read_only = reserve(vma.end(), 4096, PageTableFlags::NO_EXECUTE).unwrap();
+    assert!(!handle_page_fault(read_only.start(), PageFaultErrorCode::CAUSED_BY_WRITE));
+    assert!(!handle_page_fault(vma.start(), PageFaultErrorCode::PROTECTION_VIOLATION));
+
+    unsafe {
+        release(read_only.start()).unwrap();
+        release(start).unwrap();
+        assert_eq!(release(start), Err(VmaError::NotFound));
+    }
+    assert_eq!(find(start), None);
+}
@@ -0,0 +1,217 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::{panic::PanicInfo, ptr};
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{addr::VirtualAddr, address_space::{self, AddressSpace, USER_P4_END, USER_P4_START, USER_SPACE_START}, allocator::HEAP_START, memory, stack::STACK_REGION_START, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::Mapper, page::Page, page_table::{PageTable, PageTableFlags}}, vma};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+static KERNEL_DATA: u64 = 0x1234_5678;
+
+fn page(addr: u64) -> Page {
+    Page::containing_address(VirtualAddr::new(addr))
+}
+
+fn free_frames() -> u64 {
+    memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().stats().free
+}
+
+// maps a fresh frame at `addr` in `space` and puts `value` at its start
+fn map(space: &mut AddressSpace, addr: u64, value: u64) {
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+    let frame = allocator.allocate_frame().unwrap();
+    let virt = memory::phys_to_virt(frame.start_addr_of_physframe()).unwrap();
+    unsafe { ptr::write_volatile(virt.as_mut_ptr::<u64>(), value) };
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USERACCESSIBLE;
+    unsafe { space.mapper().map_to(page(addr), frame, flags, allocator).unwrap().flush() };
+}
+
+fn read(addr: u64) -> u64 {
+    unsafe { ptr::read_volatile(addr as *const u64) }
+}
+
+fn write(addr: u64, value: u64) {
+    unsafe { ptr::write_volatile(addr as *mut u64, value) }
+}
+
+#[test_case]
+fn test_kernel_half_is_shared() {
+    let space = AddressSpace::new().unwrap();
+    let kernel = unsafe { memory::active_level_4_table() };
+    let p4 = memory::phys_to_virt(space.p4_frame().start_addr_of_physframe()).unwrap();
+    let p4 = unsafe { &*p4.as_ptr::<PageTable>() };
+
+    for index in 0..512 {
+        if (USER_P4_START..USER_P4_END).contains(&index) {
+            assert!(p4[index].is_unused());
+            assert!(kernel[index].is_unused());
+        } else {
+            assert_eq!(p4[index].is_unused(), kernel[index].is_unused());
+            assert_eq!(p4[index].addr(), kernel[index].addr());
+        }
+    }
+    // the heap and the stacks got their P3 tables in init
+    for addr in [HEAP_START, STACK_REGION_START, memory::DIRECT_MAP_START] {
+        assert!(!p4[(addr >> 39) as usize & 511].is_unused());
+    }
+    assert!(!space.is_active());
+}
+
+#[test_case]
+fn test_user_half_is_private() {
+    let mut space = AddressSpace::new().unwrap();
+    map(&mut space, USER_SPACE_START, 42);
+
+    unsafe { space.activate() };
+    assert!(space.is_active());
+    assert_eq!(read(USER_SPACE_START), 42);
+    // the kernel is still there
+    assert_eq!(read(&KERNEL_DATA as *const u64 as u64), 0x1234_5678);
+    unsafe { address_space::activate_kernel() };
+
+    let page_table = memory::PAGE_TABLE.lock();
+    assert!(page_table.as_ref().unwrap().translate_page(page(USER_SPACE_START)).is_err());
+}
+
+#[test_case]
+fn test_remap_while_inactive() {
+    let mut space = AddressSpace::new().unwrap();
+    map(&mut space, USER_SPACE_START, 1);
+    unsafe { space.activate() };
+    assert_eq!(read(USER_SPACE_START), 1);
+    unsafe { address_space::activate_kernel() };
+
+    // with PCID the old translation may still be in the TLB, activating
+    // has to notice it's stale
+    {
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let (frame, flush) = space.mapper().unmap(page(USER_SPACE_START)).unwrap();
+        flush.ignore();
+        unsafe { frame_allocator.as_mut().unwrap().deallocate_frame(frame) };
+    }
+    map(&mut space, USER_SPACE_START, 2);
+
+    unsafe { space.activate() };
+    assert_eq!(read(USER_SPACE_START), 2);
+    unsafe { address_space::activate_kernel() };
+}
+
+#[test_case]
+fn test_demand_paging_in_the_user_half() {
+    let space = AddressSpace::new().unwrap();
+    unsafe { space.activate() };
+
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let area = vma::reserve(VirtualAddr::new(USER_SPACE_START), 4 * 4096, flags).unwrap();
+    assert_eq!(read(USER_SPACE_START + 4096), 0);
+    write(USER_SPACE_START + 4096, 7);
+    assert_eq!(read(USER_SPACE_START + 4096), 7);
+    assert_eq!(vma::resident_pages(&area), 1);
+    // it went into the address space, not the kernel page table
+    assert!(memory::PAGE_TABLE.lock().as_ref().unwrap().translate_page(page(USER_SPACE_START + 4096)).is_err());
+
+    let free = free_frames();
+    unsafe { vma::release(area.start()).unwrap() };
+    assert_eq!(free_frames(), free + 1);
+    unsafe { address_space::activate_kernel() };
+}
+
+#[test_case]
+fn test_new_kernel_p4_entries_reach_older_spaces() {
+    // P4 entry 180, unused until the VMA below is touched
+    const KERNEL_VMA: u64 = 0x5a00_0000_0000;
+    let space = AddressSpace::new().unwrap();
+    unsafe { space.activate() };
+
+    // the demand fault maps into the boot P4, the next access faults on
+    // the missing entry in ours
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let area = vma::reserve(VirtualAddr::new(KERNEL_VMA), 4096, flags).unwrap();
+    write(KERNEL_VMA, 9);
+    assert_eq!(read(KERNEL_VMA), 9);
+    unsafe { address_space::activate_kernel() };
+    assert_eq!(read(KERNEL_VMA), 9);
+    unsafe { vma::release(area.start()).unwrap() };
+}
+
+#[test_case]
+fn test_fork_is_copy_on_write() {
+    let mut parent = AddressSpace::new().unwrap();
+    map(&mut parent, USER_SPACE_START, 1);
+    map(&mut parent, USER_SPACE_START + 4096, 2);
+    let mut child = parent.fork().unwrap();
+
+    unsafe { parent.activate() };
+    write(USER_SPACE_START, 100);
+    assert_eq!(read(USER_SPACE_START), 100);
+    assert_eq!(read(USER_SPACE_START + 4096), 2);
+
+    unsafe { child.activate() };
+    assert_eq!(read(USER_SPACE_START), 1);
+    write(USER_SPACE_START + 4096, 200);
+    assert_eq!(read(USER_SPACE_START + 4096), 200);
+
+    unsafe { parent.activate() };
+    assert_eq!(read(USER_SPACE_START + 4096), 2);
+    unsafe { address_space::activate_kernel() };
+
+    // pages mapped into the child later stay in the child
+    map(&mut child, USER_SPACE_START + 8192, 3);
+    assert!(parent.mapper().translate_page(page(USER_SPACE_START + 8192)).is_err());
+}
+
+#[test_case]
+fn test_drop_frees_everything() {
+    let before = free_frames();
+    {
+        let mut parent = AddressSpace::new().unwrap();
+        for i in 0..4 {
+            map(&mut parent, USER_SPACE_START + i * 4096, i);
+        }
+        // a second P4 entry, so there's a second set of tables
+        map(&mut parent, USER_SPACE_START + (1 << 39), 5);
+        let child = parent.fork().unwrap();
+
+        unsafe { child.activate() };
+        write(USER_SPACE_START, 10);
+        unsafe { address_space::activate_kernel() };
+    }
+    assert_eq!(free_frames(), before);
+}
+
+#[test_case]
+fn test_pcids() {
+    let first = AddressSpace::new().unwrap();
+    let second = AddressSpace::new().unwrap();
+    if address_space::pcid_enabled() {
+        assert_ne!(first.pcid(), 0);
+        assert_ne!(first.pcid(), second.pcid());
+    } else {
+        assert_eq!((first.pcid(), second.pcid()), (0, 0));
+    }
+
+    // a freed PCID gets handed out again
+    let pcid = second.pcid();
+    drop(second);
+    assert_eq!(AddressSpace::new().unwrap().pcid(), pcid);
+}
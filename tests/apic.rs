@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{addr::VirtualAddr, apic::{self, local::{TimerDivide, TimerMode}}, interrupts::{self, InterruptIndex}, memory, time};
+use x86_64::instructions::interrupts::without_interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    memory::init_physical_memory_offset(VirtualAddr::new(boot_info.physical_memory_offset));
+    apic::init().expect("APIC init failed");
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+// waits (with a bound) until `counter` went past `target`
+fn wait_for(counter: fn() -> u64, target: u64) -> bool {
+    for _ in 0..1000 {
+        if counter() >= target {
+            return true;
+        }
+        x86_64::instructions::hlt();
+    }
+    false
+}
+
+#[test_case]
+fn test_apic_is_enabled() {
+    assert!(apic::is_enabled());
+    assert!(without_interrupts(|| apic::LOCAL_APIC.lock().is_some()));
+    assert!(apic::IO_APIC.lock().as_ref().unwrap().redirection_entries() >= 16);
+}
+
+#[test_case]
+fn test_pit_routed_through_io_apic() {
+    let start = time::ticks();
+    assert!(wait_for(time::ticks, start + 1), "no PIT interrupt through the I/O APIC");
+}
+
+#[test_case]
+fn test_local_apic_timer_periodic() {
+    let start = interrupts::apic_timer_ticks();
+    without_interrupts(|| unsafe {
+        apic::LOCAL_APIC.lock().as_mut().unwrap()
+            .set_timer(InterruptIndex::ApicTimer.as_u8(), TimerMode::Periodic, TimerDivide::By16, 0x10000);
+    });
+
+    assert!(wait_for(interrupts::apic_timer_ticks, start + 3), "periodic APIC timer did not fire");
+    without_interrupts(|| apic::LOCAL_APIC.lock().as_mut().unwrap().stop_timer());
+}
+
+#[test_case]
+fn test_local_apic_timer_one_shot() {
+    let start = interrupts::apic_timer_ticks();
+    without_interrupts(|| unsafe {
+        apic::LOCAL_APIC.lock().as_mut().unwrap()
+            .set_timer(InterruptIndex::ApicTimer.as_u8(), TimerMode::OneShot, TimerDivide::By16, 0x1000);
+    });
+
+    assert!(wait_for(interrupts::apic_timer_ticks, start + 1), "one-shot APIC timer did not fire");
+
+    // a one shot timer stays at zero afterwards
+    let fired = interrupts::apic_timer_ticks();
+    for _ in 0..3 {
+        x86_64::instructions::hlt();
+    }
+    assert_eq!(interrupts::apic_timer_ticks(), fired);
+    assert_eq!(without_interrupts(|| apic::LOCAL_APIC.lock().as_ref().unwrap().timer_current_count()), 0);
+}
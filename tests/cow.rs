@@ -0,0 +1,163 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::{panic::PanicInfo, ptr};
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{addr::VirtualAddr, cow, memory, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::{Mapper, Translate, TranslateResult}, page::{Page, PageRange}, page_table::PageTableFlags, phys_frame::PhysFrame}};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+// P4 entries 172 and 173, nothing else lives there
+const PARENT: u64 = 0x5600_0000_0000;
+const CHILD: u64 = 0x5680_0000_0000;
+const PAGES: u64 = 4;
+
+fn pages(start: u64) -> PageRange {
+    let first = Page::containing_address(VirtualAddr::new(start));
+    Page::range(first, first + PAGES)
+}
+
+fn frame_of(addr: u64) -> PhysFrame {
+    let page_table = memory::PAGE_TABLE.lock();
+    page_table.as_ref().unwrap().translate_page(Page::containing_address(VirtualAddr::new(addr))).unwrap()
+}
+
+fn flags_of(addr: u64) -> PageTableFlags {
+    match memory::PAGE_TABLE.lock().as_ref().unwrap().translate(VirtualAddr::new(addr)) {
+        TranslateResult::Mapped { flags, .. } => flags,
+        other => panic!("{:#x} isn't mapped: {:?}", addr, other),
+    }
+}
+
+fn ref_count(frame: PhysFrame) -> u32 {
+    memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().ref_count(frame)
+}
+
+fn read(addr: u64) -> u64 {
+    unsafe { ptr::read_volatile(addr as *const u64) }
+}
+
+fn write(addr: u64, value: u64) {
+    unsafe { ptr::write_volatile(addr as *mut u64, value) }
+}
+
+// maps the parent pages, page i holds i + 1
+fn map_parent() {
+    {
+        let mut page_table = memory::PAGE_TABLE.lock();
+        let mapper = page_table.as_mut().unwrap();
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let allocator = frame_allocator.as_mut().unwrap();
+        for page in pages(PARENT) {
+            let frame = allocator.allocate_frame().unwrap();
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+            unsafe { mapper.map_to(page, frame, flags, allocator).unwrap().flush() };
+        }
+    }
+    for i in 0..PAGES {
+        write(PARENT + i * 4096, i + 1);
+    }
+}
+
+fn unmap_all() {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().unwrap();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+    for page in pages(PARENT).chain(pages(CHILD)) {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+#[test_case]
+fn test_fork_shares_frames() {
+    map_parent();
+    cow::fork_range(pages(PARENT), pages(CHILD)).unwrap();
+
+    for i in 0..PAGES {
+        let (parent, child) = (PARENT + i * 4096, CHILD + i * 4096);
+        assert_eq!(read(child), i + 1);
+        assert_eq!(frame_of(parent), frame_of(child));
+        assert_eq!(ref_count(frame_of(parent)), 2);
+
+        for flags in [flags_of(parent), flags_of(child)] {
+            assert!(flags.contains(cow::COW));
+            assert!(!flags.contains(PageTableFlags::WRITABLE));
+        }
+    }
+    unmap_all();
+}
+
+#[test_case]
+fn test_writes_are_isolated() {
+    map_parent();
+    cow::fork_range(pages(PARENT), pages(CHILD)).unwrap();
+    let shared = frame_of(PARENT);
+
+    // the child writes, gets its own copy
+    write(CHILD, 100);
+    assert_eq!(read(CHILD), 100);
+    assert_eq!(read(PARENT), 1);
+    assert_ne!(frame_of(CHILD), shared);
+    assert_eq!(frame_of(PARENT), shared);
+    assert_eq!(ref_count(shared), 1);
+    assert!(flags_of(CHILD).contains(PageTableFlags::WRITABLE));
+    // the rest of the copied page came along
+    assert_eq!(read(CHILD + 8), read(PARENT + 8));
+
+    // the parent is the only one left, so no copy this time
+    write(PARENT, 200);
+    assert_eq!(frame_of(PARENT), shared);
+    assert_eq!(read(PARENT), 200);
+    assert_eq!(read(CHILD), 100);
+    assert!(!flags_of(PARENT).contains(cow::COW));
+
+    // the parent writes to another page first
+    let (parent, child) = (PARENT + 4096, CHILD + 4096);
+    let shared = frame_of(parent);
+    write(parent, 300);
+    assert_ne!(frame_of(parent), shared);
+    assert_eq!(frame_of(child), shared);
+    assert_eq!(read(child), 2);
+
+    unmap_all();
+}
+
+#[test_case]
+fn test_shared_frames_are_freed_last() {
+    map_parent();
+    cow::fork_range(pages(PARENT), pages(CHILD)).unwrap();
+    let shared = frame_of(PARENT);
+    let free = memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().stats().free;
+
+    unmap_all();
+    // every frame was shared, each one is freed once
+    assert_eq!(ref_count(shared), 0);
+    assert_eq!(memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().stats().free, free + PAGES);
+}
+
+#[test_case]
+fn test_fork_needs_matching_ranges() {
+    let first = Page::containing_address(VirtualAddr::new(CHILD));
+    assert_eq!(cow::fork_range(pages(PARENT), Page::range(first, first + 1)), Err(cow::CowError::LengthMismatch));
+}
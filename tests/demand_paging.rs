@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::{panic::PanicInfo, ptr};
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{addr::VirtualAddr, idt::PageFaultErrorCode, memory, structures::page_table::PageTableFlags, vma};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+// P4 entry 171, nothing else lives there
+const REGION: u64 = 0x5580_0000_0000;
+const REGION_SIZE: u64 = 64 * 1024 * 1024;
+
+fn free_frames() -> u64 {
+    memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().stats().free
+}
+
+#[test_case]
+fn test_pages_are_mapped_on_first_touch() {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let area = vma::reserve(VirtualAddr::new(REGION), REGION_SIZE, flags).unwrap();
+    assert_eq!(vma::resident_pages(&area), 0);
+
+    // reads see zeroes
+    let first = REGION as *mut u64;
+    assert_eq!(unsafe { ptr::read_volatile(first) }, 0);
+    assert_eq!(vma::resident_pages(&area), 1);
+
+    let far = (REGION + REGION_SIZE - 8) as *mut u64;
+    unsafe { ptr::write_volatile(far, 0xdead_beef) };
+    assert_eq!(unsafe { ptr::read_volatile(far) }, 0xdead_beef);
+    assert_eq!(unsafe { ptr::read_volatile(far.cast::<u8>().sub(4096)) }, 0);
+    assert_eq!(vma::resident_pages(&area), 3);
+
+    // only the touched pages got frames
+    let free = free_frames();
+    unsafe { vma::release(area.start()).unwrap() };
+    assert_eq!(free_frames(), free + 3);
+}
+
+#[test_case]
+fn test_fresh_pages_are_zeroed() {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let area = vma::reserve(VirtualAddr::new(REGION), 4096, flags).unwrap();
+    let page = REGION as *mut u8;
+    unsafe { ptr::write_bytes(page, 0xff, 4096) };
+    unsafe { vma::release(area.start()).unwrap() };
+
+    // the frame may come back, but not with the old contents
+    let area = vma::reserve(VirtualAddr::new(REGION), 4096, flags).unwrap();
+    for offset in 0..4096 {
+        assert_eq!(unsafe { ptr::read_volatile(page.add(offset)) }, 0);
+    }
+    unsafe { vma::release(area.start()).unwrap() };
+}
+
+#[test_case]
+fn test_faults_outside_vmas_are_not_handled() {
+    let addr = VirtualAddr::new(REGION + REGION_SIZE);
+    assert_eq!(vma::find(addr), None);
+    assert!(!vma::handle_page_fault(addr, PageFaultErrorCode::empty()));
+    assert!(!vma::handle_page_fault(addr, PageFaultErrorCode::CAUSED_BY_WRITE));
+}
@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::{panic::PanicInfo, ptr};
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{addr::{PhyAddr, VirtualAddr}, cpuid, memory::{self, DIRECT_MAP_START}, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::{MappedFrame, Mapper, Translate, TranslateError, TranslateResult}, page::{Page, PageSize, Size1GiB, Size2Mib, Size4Kib}, page_table::PageTableFlags, phys_frame::PhysFrame}};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+// nothing lives down here, P4 entry 175
+const TEST_PAGE: u64 = 0x5780_0000_0000;
+
+#[test_case]
+fn test_phys_to_virt_uses_direct_map() {
+    assert_eq!(memory::physical_memory_offset(), Some(VirtualAddr::new(DIRECT_MAP_START)));
+    assert_eq!(memory::phys_to_virt(PhyAddr::new(0xb8000)), Some(VirtualAddr::new(DIRECT_MAP_START + 0xb8000)));
+}
+
+#[test_case]
+fn test_direct_map_uses_huge_pages() {
+    let page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_ref().unwrap();
+
+    // an address in the second huge page either way
+    let phys = (1 << 30) + (3 << 21) + 0x1234;
+    let TranslateResult::Mapped { frame, offset, flags } = mapper.translate(VirtualAddr::new(DIRECT_MAP_START + phys)) else {
+        panic!("direct map isn't mapped");
+    };
+    match frame {
+        MappedFrame::Size1GiB(frame) => {
+            assert!(cpuid::has_1gib_pages());
+            assert_eq!(frame.start_addr_of_physframe(), PhyAddr::new(1 << 30));
+            assert_eq!(offset, phys - (1 << 30));
+        }
+        MappedFrame::Size2Mib(frame) => {
+            assert!(!cpuid::has_1gib_pages());
+            assert_eq!(frame.start_addr_of_physframe(), PhyAddr::new(phys & !(Size2Mib::SIZE - 1)));
+            assert_eq!(offset, 0x1234);
+        }
+        MappedFrame::Size4Kib(frame) => panic!("direct map uses 4KiB pages: {:?}", frame),
+    }
+    assert!(flags.contains(PageTableFlags::HUGE_PAGE | PageTableFlags::WRITABLE | PageTableFlags::GLOBAL | PageTableFlags::NO_EXECUTE));
+    assert_eq!(mapper.translate_addr(VirtualAddr::new(DIRECT_MAP_START + phys)), Some(PhyAddr::new(phys)));
+
+    // a 4KiB lookup runs into the huge page
+    let page = Page::<Size4Kib>::containing_address(VirtualAddr::new(DIRECT_MAP_START + phys));
+    assert_eq!(mapper.translate_page(page), Err(TranslateError::ParentEntryHugePage));
+    if cpuid::has_1gib_pages() {
+        let page = Page::<Size1GiB>::containing_address(VirtualAddr::new(DIRECT_MAP_START + phys));
+        assert_eq!(mapper.translate_page(page), Ok(PhysFrame::frame_containing_addr(PhyAddr::new(phys))));
+    } else {
+        let page = Page::<Size2Mib>::containing_address(VirtualAddr::new(DIRECT_MAP_START + phys));
+        assert_eq!(mapper.translate_page(page), Ok(PhysFrame::frame_containing_addr(PhyAddr::new(phys))));
+    }
+}
+
+#[test_case]
+fn test_direct_map_covers_mmio() {
+    let page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_ref().unwrap();
+    // the I/O apic
+    let io_apic = VirtualAddr::new(DIRECT_MAP_START + 0xFEC0_0000);
+    assert_eq!(mapper.translate_addr(io_apic), Some(PhyAddr::new(0xFEC0_0000)));
+}
+
+#[test_case]
+fn test_direct_map_reaches_frames() {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().unwrap();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+
+    let page = Page::<Size4Kib>::containing_address(VirtualAddr::new(TEST_PAGE));
+    let frame = allocator.allocate_frame().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    unsafe { mapper.map_to(page, frame, flags, allocator).unwrap().flush() };
+
+    // written through the direct map, read through the page
+    let direct = memory::phys_to_virt(frame.start_addr_of_physframe()).unwrap();
+    unsafe { ptr::write_volatile(direct.as_mut_ptr::<u64>().add(3), 0x1122_3344) };
+    assert_eq!(unsafe { ptr::read_volatile((TEST_PAGE as *const u64).add(3)) }, 0x1122_3344);
+
+    let (frame, flush) = mapper.unmap(page).unwrap();
+    flush.flush();
+    unsafe { allocator.deallocate_frame(frame) };
+}
@@ -0,0 +1,120 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{bootinfo::MemoryRegionType, entry_point, BootInfo};
+use rustyos::{memory, structures::frame_alloc::{FrameAllocator, FrameDeallocator}};
+
+static mut BOOT_INFO: Option<&'static BootInfo> = None;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe {
+        memory::init(boot_info);
+        BOOT_INFO = Some(boot_info);
+    }
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+fn boot_info() -> &'static BootInfo {
+    unsafe { BOOT_INFO.unwrap() }
+}
+
+#[test_case]
+fn test_frames_are_usable() {
+    let mut guard = memory::FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+
+    for _ in 0..64 {
+        let frame = allocator.allocate_frame().expect("out of frames");
+        let addr = frame.start_addr_of_physframe().as_u64();
+        assert_eq!(addr % 4096, 0);
+
+        let region = boot_info().memory_map.iter()
+            .find(|region| region.range.start_addr() <= addr && addr < region.range.end_addr())
+            .expect("frame outside of the memory map");
+        assert_eq!(region.region_type, MemoryRegionType::Usable);
+    }
+}
+
+#[test_case]
+fn test_frames_are_unique() {
+    let mut guard = memory::FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+
+    let mut previous = allocator.allocate_frame().unwrap();
+    for _ in 0..256 {
+        let frame = allocator.allocate_frame().unwrap();
+        assert_ne!(frame, previous);
+        previous = frame;
+    }
+}
+
+#[test_case]
+fn test_deallocated_frames_are_reused() {
+    let mut guard = memory::FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+
+    let before = allocator.stats();
+    let first = allocator.allocate_frame().unwrap();
+    let second = allocator.allocate_frame().unwrap();
+    assert_eq!(allocator.stats().used, before.used + 2);
+
+    unsafe {
+        allocator.deallocate_frame(first);
+        allocator.deallocate_frame(second);
+    }
+    assert_eq!(allocator.stats(), before);
+
+    // lowest free frame comes back first
+    assert_eq!(allocator.allocate_frame(), Some(first));
+    assert_eq!(allocator.allocate_frame(), Some(second));
+}
+
+#[test_case]
+fn test_contiguous_allocation() {
+    let mut guard = memory::FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+
+    // a 2MiB aligned run, like a huge page would need
+    let range = allocator.allocate_contiguous(512, 0x20_0000).expect("no 2MiB run left");
+    assert_eq!(range.start.start_addr_of_physframe().as_u64() % 0x20_0000, 0);
+    assert_eq!(range.total_frames_within_range(), 512);
+
+    let before = allocator.stats();
+    unsafe { allocator.deallocate_contiguous(range); }
+    assert_eq!(allocator.stats().free, before.free + 512);
+}
+
+#[test_case]
+fn test_legacy_hole_is_reserved() {
+    let mut guard = memory::FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+
+    let range = allocator.allocate_contiguous(16, 4096).unwrap();
+    let start = range.start.start_addr_of_physframe().as_u64();
+    let end = start + range.size();
+    assert!(start > 0);
+    assert!(end <= 0xA_0000 || start >= 0x10_0000);
+    unsafe { allocator.deallocate_contiguous(range); }
+}
+
+#[test_case]
+fn test_stats_add_up() {
+    let stats = memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().stats();
+    assert!(stats.total > 0);
+    assert_eq!(stats.used + stats.free, stats.total);
+}
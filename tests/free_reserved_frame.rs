@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+use rustyos::{exit_qemu, pmm::{self, BitmapFrameAllocator}, serial_print, serial_println, structures::frame_alloc::FrameDeallocator, QemuExitCode};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    serial_print!("free_reserved_frame::freeing_a_reserved_frame_panics\t");
+
+    let mut bitmap = [0; 2];
+    let mut managed = [0; 2];
+    let mut shares = [0; 128];
+    let mut allocator = BitmapFrameAllocator::new(&mut bitmap, &mut managed, &mut shares);
+    allocator.add_free_range(pmm::range(0, 128 * 4096));
+    allocator.reserve_range(pmm::range(0, 16 * 4096));
+
+    // frame 8 is reserved, it never came from the allocator
+    unsafe { allocator.deallocate_frame(pmm::range(8 * 4096, 9 * 4096).start) };
+
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::{arch::asm, panic::PanicInfo, sync::atomic::{AtomicU64, Ordering}};
+
+use bootloader::{entry_point, BootInfo};
+use lazy_static::lazy_static;
+use rustyos::{exit_qemu, memory, serial_print, serial_println, stack, QemuExitCode};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+static GUARD_PAGE: AtomicU64 = AtomicU64::new(0);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("guard_page::stack_overflow_hits_guard_page\t");
+
+    rustyos::gdt::init();
+    unsafe { memory::init(boot_info) };
+    init_test_idt();
+
+    let stack = stack::allocate(4).expect("allocating a stack failed");
+    GUARD_PAGE.store(stack.guard_page().start_address().as_u64(), Ordering::SeqCst);
+
+    // overflow a stack of our own, not the boot stack
+    unsafe {
+        asm!(
+            "mov rsp, {top}",
+            "call {overflow}",
+            top = in(reg) stack.top().as_u64(),
+            overflow = sym overflow,
+            options(noreturn),
+        );
+    }
+}
+
+extern "C" fn overflow() -> ! {
+    stack_overflow();
+    panic!("Execution continues after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    volatile::Volatile::new(0).read();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(rustyos::gdt::DOUBLE_FAULT_1ST_INDEX);
+        }
+        idt
+    };
+}
+
+fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame, _error_code: u64
+) -> ! {
+    let addr = Cr2::read().as_u64();
+    let guard = GUARD_PAGE.load(Ordering::SeqCst);
+
+    if (guard..guard + 4096).contains(&addr) && stack::is_guard_page(rustyos::addr::VirtualAddr::new(addr)) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n");
+        serial_println!("Error: double fault at {:#x}, expected the guard page at {:#x}\n", addr, guard);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{allocator::{self, HEAP_SIZE}, memory};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn test_large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn test_string() {
+    let mut s = String::new();
+    for _ in 0..100 {
+        s.push_str("It'sMoNdAy ");
+    }
+    assert_eq!(s.len(), 1100);
+}
+
+// more boxes than fit into the heap at once, only works if memory is reused
+#[test_case]
+fn test_many_boxes() {
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+// the bump allocator can't reuse anything while `long_lived` is around
+#[cfg(not(feature = "heap_bump"))]
+#[test_case]
+fn test_many_boxes_long_lived() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn test_freed_memory_is_reused() {
+    let first = Box::new([0u64; 64]);
+    let addr = &*first as *const _ as usize;
+    drop(first);
+
+    let second = Box::new([1u64; 64]);
+    assert_eq!(&*second as *const _ as usize, addr);
+}
+
+#[test_case]
+fn test_large_allocation() {
+    // most of the heap in one piece
+    let vec = alloc::vec![0xAAu8; (HEAP_SIZE / 2) as usize];
+    assert!(vec.iter().all(|&byte| byte == 0xAA));
+}
@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec::Vec};
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{allocator, memory, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+// xorshift, good enough to shuffle sizes around
+#[cfg(not(feature = "heap_bump"))]
+struct Rng(u64);
+
+#[cfg(not(feature = "heap_bump"))]
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(not(feature = "heap_bump"))]
+const SLOTS: usize = 64;
+
+// Random sized allocations freed in random order. Every block is filled
+// with its slot number, so overlapping allocations show up as corruption.
+// The bump allocator would just run out of memory here.
+#[cfg(not(feature = "heap_bump"))]
+#[test_case]
+fn test_random_alloc_free() {
+    let before = allocator::stats();
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut slots: [Option<Box<[u8]>>; SLOTS] = core::array::from_fn(|_| None);
+
+    for _ in 0..20_000 {
+        let slot = (rng.next() % SLOTS as u64) as usize;
+        match slots[slot].take() {
+            Some(block) => assert!(block.iter().all(|&byte| byte == slot as u8), "slot {} corrupted", slot),
+            None => {
+                let size = 1 + (rng.next() % 4096) as usize;
+                slots[slot] = Some(alloc::vec![slot as u8; size].into_boxed_slice());
+            }
+        }
+    }
+
+    let during = allocator::stats();
+    assert!(during.peak >= during.used);
+    drop(slots);
+
+    allocator::print_stats();
+    assert_eq!(allocator::stats().used, before.used);
+}
+
+// lots of small objects at once, all alive together
+#[test_case]
+fn test_many_small_objects() {
+    let before = allocator::stats();
+
+    let objects: Vec<Box<u64>> = (0..10_000).map(Box::new).collect();
+    assert!(objects.iter().enumerate().all(|(i, object)| **object == i as u64));
+    assert!(allocator::stats().used > before.used);
+    drop(objects);
+
+    allocator::print_stats();
+    assert_eq!(allocator::stats().used, before.used);
+}
+
+#[test_case]
+fn test_growing_vec() {
+    let mut vec = Vec::new();
+    for i in 0..50_000u32 {
+        vec.push(i);
+    }
+    assert_eq!(vec.len(), 50_000);
+    serial_println!("after growing a vec: {}", allocator::stats());
+}
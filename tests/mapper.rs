@@ -0,0 +1,206 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{addr::{PhyAddr, VirtualAddr}, memory, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::{FlagUpdateError, MapToError, MappedFrame, Mapper, RecursivePageTable, Translate, TranslateError, TranslateResult, UnmapError}, page::{Page, PageSize, Size1GiB, Size2Mib, Size4Kib}, page_table::{PageTable, PageTableFlags}, phys_frame::PhysFrame}};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+// nothing lives down here, P4 entry 170
+const TEST_PAGE: u64 = 0x5555_0000_0000;
+
+#[test_case]
+fn test_translate_identity_mapped_vga_buffer() {
+    let mut guard = memory::PAGE_TABLE.lock();
+    let mapper = guard.as_mut().unwrap();
+    assert_eq!(mapper.translate_addr(VirtualAddr::new(0xb8000)), Some(PhyAddr::new(0xb8000)));
+    assert_eq!(mapper.translate(VirtualAddr::new(TEST_PAGE)), TranslateResult::NotMapped);
+}
+
+#[test_case]
+fn test_map_and_unmap_4kib() {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().unwrap();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+
+    let page = Page::<Size4Kib>::containing_address(VirtualAddr::new(TEST_PAGE));
+    let frame = allocator.allocate_frame().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    unsafe { mapper.map_to(page, frame, flags, allocator).unwrap().flush() };
+    assert_eq!(mapper.translate_page(page), Ok(frame));
+    assert_eq!(mapper.translate_addr(VirtualAddr::new(TEST_PAGE + 0x123)), Some(PhyAddr::new(frame.start_addr_of_physframe().as_u64() + 0x123)));
+
+    // writes through the page end up in the frame
+    unsafe { (TEST_PAGE as *mut u64).write_volatile(0xdead_beef) };
+    let through_frame = memory::phys_to_virt(frame.start_addr_of_physframe()).unwrap().as_u64() as *const u64;
+    assert_eq!(unsafe { through_frame.read_volatile() }, 0xdead_beef);
+
+    let other = allocator.allocate_frame().unwrap();
+    assert_eq!(unsafe { mapper.map_to(page, other, flags, allocator) }.map(|flush| flush.ignore()), Err(MapToError::PageAlreadyMapped(frame)));
+
+    let (unmapped, flush) = mapper.unmap(page).unwrap();
+    flush.flush();
+    assert_eq!(unmapped, frame);
+    assert_eq!(mapper.translate(VirtualAddr::new(TEST_PAGE)), TranslateResult::NotMapped);
+    assert_eq!(mapper.unmap(page).map(|(_, flush)| flush.ignore()), Err(UnmapError::PageNotMapped));
+
+    unsafe {
+        allocator.deallocate_frame(frame);
+        allocator.deallocate_frame(other);
+    }
+}
+
+#[test_case]
+fn test_update_flags() {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().unwrap();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+
+    let page = Page::<Size4Kib>::containing_address(VirtualAddr::new(TEST_PAGE + 0x1000));
+    let frame = allocator.allocate_frame().unwrap();
+    unsafe { mapper.map_to(page, frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE, allocator).unwrap().flush() };
+
+    unsafe { mapper.update_flags(page, PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE).unwrap().flush() };
+    match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { flags, .. } => {
+            assert!(!flags.contains(PageTableFlags::WRITABLE));
+            assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+        }
+        other => panic!("page not mapped: {:?}", other),
+    }
+
+    let (unmapped, flush) = mapper.unmap(page).unwrap();
+    flush.flush();
+    unsafe { allocator.deallocate_frame(unmapped) };
+}
+
+#[test_case]
+fn test_huge_page() {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().unwrap();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+
+    let range = allocator.allocate_contiguous(512, Size2Mib::SIZE).expect("no 2MiB run left");
+    let frame = PhysFrame::<Size2Mib>::frame_from_start_addr(range.start.start_addr_of_physframe()).unwrap();
+    let page = Page::<Size2Mib>::containing_address(VirtualAddr::new(TEST_PAGE + Size2Mib::SIZE));
+
+    unsafe { mapper.map_to(page, frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE, allocator).unwrap().flush() };
+
+    let addr = VirtualAddr::new(page.start_address().as_u64() + 0x12345);
+    match mapper.translate(addr) {
+        TranslateResult::Mapped { frame: MappedFrame::Size2Mib(mapped), offset, .. } => {
+            assert_eq!(mapped, frame);
+            assert_eq!(offset, 0x12345);
+        }
+        other => panic!("expected a 2MiB mapping, got {:?}", other),
+    }
+
+    // a 4KiB page inside the huge page has no P1 to go into
+    let small = Page::<Size4Kib>::containing_address(addr);
+    let small_frame = allocator.allocate_frame().unwrap();
+    let result = unsafe { mapper.map_to(small, small_frame, PageTableFlags::PRESENT, allocator) };
+    assert_eq!(result.map(|flush| flush.ignore()), Err(MapToError::ParentEntryHugePage));
+
+    let (unmapped, flush) = mapper.unmap(page).unwrap();
+    flush.flush();
+    assert_eq!(unmapped, frame);
+
+    unsafe {
+        allocator.deallocate_frame(small_frame);
+        allocator.deallocate_contiguous(range);
+    }
+}
+
+#[test_case]
+fn test_huge_page_over_small_pages() {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().unwrap();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+
+    let addr = VirtualAddr::new(TEST_PAGE + 2 * Size2Mib::SIZE);
+    let small = Page::<Size4Kib>::containing_address(addr);
+    let frame = allocator.allocate_frame().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    unsafe { mapper.map_to(small, frame, flags, allocator).unwrap().flush() };
+
+    // the P2 and P3 entries above point to tables, not to huge pages
+    let huge = Page::<Size2Mib>::containing_address(addr);
+    assert_eq!(mapper.unmap(huge).map(|(_, flush)| flush.ignore()), Err(UnmapError::PageNotMapped));
+    assert_eq!(unsafe { mapper.update_flags(huge, flags) }.map(|flush| flush.ignore()), Err(FlagUpdateError::PageNotMapped));
+    assert_eq!(mapper.translate_page(huge), Err(TranslateError::PageNotMapped));
+    let giant = Page::<Size1GiB>::containing_address(addr);
+    assert_eq!(mapper.unmap(giant).map(|(_, flush)| flush.ignore()), Err(UnmapError::PageNotMapped));
+    assert_eq!(unsafe { mapper.update_flags(giant, flags) }.map(|flush| flush.ignore()), Err(FlagUpdateError::PageNotMapped));
+    assert_eq!(mapper.translate_page(giant), Err(TranslateError::PageNotMapped));
+
+    // and the small page is still there
+    assert_eq!(mapper.translate_page(small), Ok(frame));
+    let (unmapped, flush) = mapper.unmap(small).unwrap();
+    flush.flush();
+    unsafe { allocator.deallocate_frame(unmapped) };
+}
+
+#[test_case]
+fn test_translate_page_errors() {
+    let mut guard = memory::PAGE_TABLE.lock();
+    let mapper = guard.as_mut().unwrap();
+    let page = Page::<Size4Kib>::containing_address(VirtualAddr::new(TEST_PAGE + 0x4000_0000));
+    assert_eq!(mapper.translate_page(page), Err(TranslateError::PageNotMapped));
+}
+
+#[test_case]
+fn test_recursive_page_table() {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().unwrap();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+
+    // point a free P4 entry back at the P4
+    let p4 = mapper.level_4_table_mut();
+    let r = (1..256).rev().find(|&index| p4[index].is_unused()).expect("no free P4 entry");
+    p4[r].set_frame(PhysFrame::frame_containing_addr(memory::active_level_4_table_addr()), PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    x86_64::instructions::tlb::flush_all();
+
+    let r = r as u64;
+    let table_addr = (r << 39) | (r << 30) | (r << 21) | (r << 12);
+    let mut recursive = RecursivePageTable::new(unsafe { &mut *(table_addr as *mut PageTable) }).unwrap();
+    assert_eq!(usize::from(recursive.recursive_index()), r as usize);
+
+    // both strategies see the same tables
+    let page = Page::<Size4Kib>::containing_address(VirtualAddr::new(TEST_PAGE + 0x8000_0000));
+    let frame = allocator.allocate_frame().unwrap();
+    unsafe { recursive.map_to(page, frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE, allocator).unwrap().flush() };
+    assert_eq!(recursive.translate_page(page), Ok(frame));
+    assert_eq!(mapper.translate_addr(page.start_address()), Some(frame.start_addr_of_physframe()));
+
+    let (unmapped, flush) = recursive.unmap(page).unwrap();
+    flush.flush();
+    assert_eq!(mapper.translate(page.start_address()), TranslateResult::NotMapped);
+    unsafe { allocator.deallocate_frame(unmapped) };
+
+    mapper.level_4_table_mut()[r as usize].set_unused();
+    x86_64::instructions::tlb::flush_all();
+}
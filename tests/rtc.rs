@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::{panic::PanicInfo, time::Duration};
+
+use rustyos::{rtc, serial_println, time};
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    rustyos::init();
+    test_main();
+
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_read_date_time() {
+    let now = rtc::read();
+    serial_println!("RTC: {}", now);
+
+    assert!(now.year >= 2020);
+    assert!((1..=12).contains(&now.month));
+    assert!((1..=31).contains(&now.day));
+    assert!(now.hour < 24 && now.minute < 60 && now.second < 60);
+}
+
+#[test_case]
+fn test_wall_clock_advances() {
+    let before = rtc::read();
+    time::sleep(Duration::from_millis(1100));
+    let after = rtc::read();
+    assert!(after.unix_timestamp() > before.unix_timestamp());
+}
+
+#[test_case]
+fn test_periodic_interrupt() {
+    // 32768 >> 5 = 1024Hz
+    rtc::enable_periodic_interrupt(6);
+
+    let start = rtc::periodic_ticks();
+    time::sleep(Duration::from_millis(20));
+    rtc::disable_periodic_interrupt();
+
+    assert!(rtc::periodic_ticks() > start, "no RTC interrupt received");
+}
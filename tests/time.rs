@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::{panic::PanicInfo, time::Duration};
+
+use rustyos::time;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    rustyos::init();
+    test_main();
+
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_ticks_advance() {
+    let start = time::ticks();
+    time::sleep_ticks(5);
+    assert!(time::ticks() >= start + 5);
+}
+
+#[test_case]
+fn test_uptime_is_monotonic() {
+    let first = time::uptime();
+    time::sleep_ticks(1);
+    let second = time::uptime();
+    assert!(second > first);
+}
+
+#[test_case]
+fn test_sleep_duration() {
+    assert_eq!(time::frequency(), time::DEFAULT_FREQUENCY);
+
+    let start = time::uptime();
+    time::sleep(Duration::from_millis(20));
+    assert!(time::uptime() - start >= Duration::from_millis(20));
+}
+
+#[test_case]
+fn test_change_frequency() {
+    time::init(100);
+    assert_eq!(time::frequency(), 100);
+
+    let start = time::ticks();
+    time::sleep(Duration::from_millis(50));
+    let elapsed = time::ticks() - start;
+    // sleep only guarantees the lower bound, a slow host can add a lot more
+    assert!((5..=50).contains(&elapsed), "{} ticks for 50ms at 100Hz", elapsed);
+
+    time::init(time::DEFAULT_FREQUENCY);
+}
@@ -0,0 +1,37 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::panic::PanicInfo;
+
+use rustyos::time;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    rustyos::init();
+    test_main();
+
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_timer_tick_received() {
+    let start = time::ticks();
+
+    // give the PIT plenty of chances
+    for _ in 0..100 {
+        if time::ticks() > start {
+            return;
+        }
+        x86_64::instructions::hlt();
+    }
+
+    panic!("no timer interrupt received");
+}
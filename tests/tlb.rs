@@ -0,0 +1,120 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::{panic::PanicInfo, ptr};
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{addr::VirtualAddr, address_space, memory, structures::{frame_alloc::{FrameAllocator, FrameDeallocator}, mapper::Mapper, page::{Page, Size4Kib}, page_table::PageTableFlags, phys_frame::PhysFrame}, tlb::{self, FlushRequest}};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+
+    test_main();
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+// nothing lives down here, P4 entry 174
+const TEST_PAGE: u64 = 0x5700_0000_0000;
+
+fn page() -> Page<Size4Kib> {
+    Page::containing_address(VirtualAddr::new(TEST_PAGE))
+}
+
+// a fresh frame holding `value`
+fn frame_with(value: u64) -> PhysFrame {
+    let frame = memory::FRAME_ALLOCATOR.lock().as_mut().unwrap().allocate_frame().unwrap();
+    let virt = memory::phys_to_virt(frame.start_addr_of_physframe()).unwrap();
+    unsafe { ptr::write_volatile(virt.as_mut_ptr::<u64>(), value) };
+    frame
+}
+
+// points the test page at `frame` without flushing, returns the old frame
+fn remap(frame: PhysFrame) -> Option<PhysFrame> {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let mapper = page_table.as_mut().unwrap();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let allocator = frame_allocator.as_mut().unwrap();
+
+    let old = mapper.unmap(page()).ok().map(|(old, flush)| {
+        flush.ignore();
+        old
+    });
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    unsafe { mapper.map_to(page(), frame, flags, allocator).unwrap().ignore() };
+    old
+}
+
+fn read() -> u64 {
+    unsafe { ptr::read_volatile(TEST_PAGE as *const u64) }
+}
+
+fn unmap() {
+    let mut page_table = memory::PAGE_TABLE.lock();
+    let (frame, flush) = page_table.as_mut().unwrap().unmap(page()).unwrap();
+    flush.flush();
+    unsafe { memory::FRAME_ALLOCATOR.lock().as_mut().unwrap().deallocate_frame(frame) };
+}
+
+fn free(frame: PhysFrame) {
+    unsafe { memory::FRAME_ALLOCATOR.lock().as_mut().unwrap().deallocate_frame(frame) };
+}
+
+// Remaps the test page from a frame holding 1 to one holding 2, and checks
+// that `flush` makes the new one show up.
+fn check_flush(flush: impl FnOnce()) {
+    assert_eq!(remap(frame_with(1)), None);
+    tlb::flush(VirtualAddr::new(TEST_PAGE));
+    // the translation is cached now
+    assert_eq!(read(), 1);
+
+    let old = remap(frame_with(2)).unwrap();
+    flush();
+    assert_eq!(read(), 2);
+
+    free(old);
+    unmap();
+}
+
+#[test_case]
+fn test_flush_page() {
+    check_flush(|| tlb::flush(VirtualAddr::new(TEST_PAGE + 0x123)));
+}
+
+#[test_case]
+fn test_flush_all() {
+    check_flush(tlb::flush_all);
+}
+
+#[test_case]
+fn test_flush_everything() {
+    check_flush(tlb::flush_everything);
+}
+
+#[test_case]
+fn test_flush_pcid() {
+    // the kernel's address space has PCID 0
+    check_flush(|| tlb::flush_pcid(0));
+    check_flush(|| tlb::flush_pcid_addr(0, VirtualAddr::new(TEST_PAGE)));
+}
+
+#[test_case]
+fn test_shootdown_single_cpu() {
+    let p4 = memory::active_level_4_table_addr();
+    check_flush(|| tlb::shootdown(p4, FlushRequest::Page(VirtualAddr::new(TEST_PAGE))));
+    check_flush(|| tlb::shootdown(p4, FlushRequest::All));
+
+    // an address space nobody runs needs no flushing at all
+    let space = address_space::AddressSpace::new().unwrap();
+    tlb::shootdown(space.p4_frame().start_addr_of_physframe(), FlushRequest::All);
+}
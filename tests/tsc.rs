@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rustyos::test_runner)]
+#![reexport_test_harness_main="test_main"]
+
+use core::{panic::PanicInfo, time::Duration};
+
+use bootloader::{entry_point, BootInfo};
+use rustyos::{hpet::Hpet, memory, serial_println, time::{self, Instant}, tsc};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rustyos::init();
+    unsafe { memory::init(boot_info) };
+    test_main();
+
+    rustyos::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rustyos::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_tsc_calibrated() {
+    serial_println!(
+        "TSC: {} Hz, source {:?}, invariant {}",
+        tsc::frequency(), tsc::calibration_source(), tsc::is_invariant()
+    );
+    // anything below 100MHz isn't a real cpu
+    assert!(tsc::frequency() > 100_000_000);
+    assert_ne!(tsc::calibration_source(), tsc::CalibrationSource::None);
+}
+
+// qemu always has a HPET, so calibration has to have used it
+#[test_case]
+fn test_calibrated_against_hpet() {
+    let hpet = Hpet::probe().expect("no HPET at the default address");
+    // the spec allows periods up to 100ns
+    assert!(hpet.frequency() >= 10_000_000, "{} Hz", hpet.frequency());
+
+    let start = hpet.counter();
+    time::sleep(Duration::from_millis(10));
+    assert!(hpet.counter() > start, "HPET counter isn't running");
+
+    assert_eq!(tsc::calibration_source(), tsc::CalibrationSource::Hpet);
+}
+
+#[test_case]
+fn test_instant_is_monotonic() {
+    let first = Instant::now();
+    let second = Instant::now();
+    assert!(second >= first);
+    assert_eq!(first.duration_since(second), Duration::ZERO);
+}
+
+#[test_case]
+fn test_instant_matches_pit_clock() {
+    let start = Instant::now();
+    time::sleep(Duration::from_millis(50));
+    let elapsed = start.elapsed();
+
+    // qemu's clocks drift against each other, only check the order of magnitude
+    assert!(elapsed >= Duration::from_millis(25), "{:?}", elapsed);
+    assert!(elapsed <= Duration::from_millis(500), "{:?}", elapsed);
+}
+
+#[test_case]
+fn test_instant_arithmetic() {
+    let now = Instant::now();
+    let later = now + Duration::from_micros(10);
+    assert!(later > now);
+    assert_eq!(later - Duration::from_micros(10), now);
+
+    let diff = later - now;
+    assert!(diff >= Duration::from_nanos(9_999) && diff <= Duration::from_micros(10));
+}